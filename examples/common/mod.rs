@@ -27,16 +27,15 @@ pub fn authenticate_client<'a>(
             })
         }).and_then(move |dclient| {
             dclient.login(&[&login_scope]).and_then(move |token| {
-                dclient
-                    .is_auth(Some(token.token()))
-                    .and_then(move |is_auth| {
-                        if !is_auth {
-                            Err("login failed".into())
-                        } else {
-                            println!("logged in!");
-                            Ok(dclient.set_token(Some(token.token())))
-                        }
-                    })
+                dclient.set_auth(Some(&token));
+                dclient.is_auth(None).and_then(move |is_auth| {
+                    if !is_auth {
+                        Err("login failed".into())
+                    } else {
+                        println!("logged in!");
+                        Ok(&*dclient)
+                    }
+                })
             })
         })
 }
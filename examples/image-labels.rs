@@ -1,4 +1,3 @@
-extern crate dirs;
 extern crate dkregistry;
 extern crate futures;
 extern crate serde_json;
@@ -8,7 +7,7 @@ use dkregistry::reference;
 use futures::prelude::*;
 use std::result::Result;
 use std::str::FromStr;
-use std::{env, fs, io};
+use std::env;
 use tokio_core::reactor::Core;
 
 mod common;
@@ -22,26 +21,21 @@ fn main() {
 
     println!("[{}] downloading image {}", registry, dkr_ref);
 
-    let mut user = None;
-    let mut password = None;
-    let home = dirs::home_dir().unwrap();
-    let cfg = fs::File::open(home.join(".docker/config.json"));
-    if let Ok(fp) = cfg {
-        let creds = dkregistry::get_credentials(io::BufReader::new(fp), &registry);
-        if let Ok(user_pass) = creds {
-            user = user_pass.0;
-            password = user_pass.1;
-        } else {
-            println!("[{}] no credentials found in config.json", registry);
-        }
-    } else {
-        user = env::var("DKREG_USER").ok();
-        if user.is_none() {
-            println!("[{}] no $DKREG_USER for login user", registry);
-        }
-        password = env::var("DKREG_PASSWD").ok();
-        if password.is_none() {
-            println!("[{}] no $DKREG_PASSWD for login password", registry);
+    let resolver = dkregistry::credentials::AuthResolver::from_env();
+    let key = format!("{}/{}", registry, dkr_ref.repository());
+    let (user, password) = match resolver.resolve(&key) {
+        Ok(Some(creds)) => (creds.username, creds.password),
+        _ => {
+            println!("[{}] no credentials found in auth files", registry);
+            let user = env::var("DKREG_USER").ok();
+            if user.is_none() {
+                println!("[{}] no $DKREG_USER for login user", registry);
+            }
+            let password = env::var("DKREG_PASSWD").ok();
+            if password.is_none() {
+                println!("[{}] no $DKREG_PASSWD for login password", registry);
+            }
+            (user, password)
         }
     };
 
@@ -1,4 +1,3 @@
-extern crate dirs;
 extern crate dkregistry;
 extern crate futures;
 extern crate serde_json;
@@ -8,7 +7,7 @@ use dkregistry::{reference, render};
 use futures::prelude::*;
 use std::result::Result;
 use std::str::FromStr;
-use std::{boxed, env, error, fs, io};
+use std::{boxed, env, error};
 use tokio_core::reactor::Core;
 
 mod common;
@@ -22,26 +21,21 @@ fn main() {
 
     println!("[{}] downloading image {}", registry, dkr_ref);
 
-    let mut user = None;
-    let mut password = None;
-    let home = dirs::home_dir().unwrap();
-    let cfg = fs::File::open(home.join(".docker/config.json"));
-    if let Ok(fp) = cfg {
-        let creds = dkregistry::get_credentials(io::BufReader::new(fp), &registry);
-        if let Ok(user_pass) = creds {
-            user = user_pass.0;
-            password = user_pass.1;
-        } else {
-            println!("[{}] no credentials found in config.json", registry);
-        }
-    } else {
-        user = env::var("DKREG_USER").ok();
-        if user.is_none() {
-            println!("[{}] no $DKREG_USER for login user", registry);
-        }
-        password = env::var("DKREG_PASSWD").ok();
-        if password.is_none() {
-            println!("[{}] no $DKREG_PASSWD for login password", registry);
+    let resolver = dkregistry::credentials::AuthResolver::from_env();
+    let key = format!("{}/{}", registry, dkr_ref.repository());
+    let (user, password) = match resolver.resolve(&key) {
+        Ok(Some(creds)) => (creds.username, creds.password),
+        _ => {
+            println!("[{}] no credentials found in auth files", registry);
+            let user = env::var("DKREG_USER").ok();
+            if user.is_none() {
+                println!("[{}] no $DKREG_USER for login user", registry);
+            }
+            let password = env::var("DKREG_PASSWD").ok();
+            if password.is_none() {
+                println!("[{}] no $DKREG_PASSWD for login password", registry);
+            }
+            (user, password)
         }
     };
 
@@ -71,6 +65,14 @@ fn run(
     let login_scope = format!("repository:{}:pull", image);
     let version = dkr_ref.version();
 
+    let path = &format!("{}:{}", &image, &version).replace("/", "_");
+    let path = std::path::Path::new(&path);
+    if path.exists() {
+        return Err(format!("path {:?} already exists, exiting", &path).into());
+    }
+    std::fs::create_dir(&path)?;
+    let can_path = path.canonicalize()?;
+
     let futures = common::authenticate_client(&mut client, &login_scope)
         .and_then(|dclient| {
             dclient
@@ -107,36 +109,23 @@ fn run(
             )
         }).and_then(|(dclient, layers)| {
             let image = image.clone();
+            let can_path = can_path.clone();
 
             println!("{} -> got {} layer(s)", &image, layers.len(),);
-
-            futures::stream::iter_ok::<_, dkregistry::errors::Error>(layers)
-                .and_then(move |layer| {
-                    let get_blob_future = dclient.get_blob(&image, &layer);
-                    get_blob_future.inspect(move |blob| {
-                        println!("Layer {}, got {} bytes.\n", layer, blob.len());
-                    })
-                }).collect()
+            println!("Unpacking layers to {:?}", &can_path);
+
+            // Stream each layer directly into extraction, lower ones first.
+            futures::stream::iter_ok::<_, dkregistry::errors::Error>(layers).for_each(
+                move |layer| {
+                    println!("Layer {}, unpacking.", layer);
+                    let blob = dclient.get_blob_stream(&image, &layer);
+                    render::unpack_layer_stream(blob, &can_path)
+                },
+            )
         });
 
-    let blobs = match tcore.run(futures) {
-        Ok(blobs) => blobs,
-        Err(e) => return Err(Box::new(e)),
-    };
-
-    println!("Downloaded {} layers", blobs.len());
-
-    let path = &format!("{}:{}", &image, &version).replace("/", "_");
-    let path = std::path::Path::new(&path);
-    if path.exists() {
-        return Err(format!("path {:?} already exists, exiting", &path).into());
+    match tcore.run(futures) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(Box::new(e)),
     }
-    // TODO: use async io
-    std::fs::create_dir(&path).unwrap();
-    let can_path = path.canonicalize().unwrap();
-
-    println!("Unpacking layers to {:?}", &can_path);
-    let r = render::unpack(&blobs, &can_path).unwrap();
-
-    Ok(r)
 }
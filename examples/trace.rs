@@ -1,4 +1,3 @@
-extern crate dirs;
 extern crate dkregistry;
 extern crate env_logger;
 extern crate futures;
@@ -11,7 +10,7 @@ mod common;
 use dkregistry::reference;
 use futures::prelude::*;
 use std::str::FromStr;
-use std::{boxed, env, error, fs, io};
+use std::{boxed, env, error};
 use tokio_core::reactor::Core;
 
 fn main() {
@@ -23,26 +22,21 @@ fn main() {
 
     println!("[{}] downloading image {}", registry, dkr_ref);
 
-    let mut user = None;
-    let mut password = None;
-    let home = dirs::home_dir().unwrap();
-    let cfg = fs::File::open(home.join(".docker/config.json"));
-    if let Ok(fp) = cfg {
-        let creds = dkregistry::get_credentials(io::BufReader::new(fp), &registry);
-        if let Ok(user_pass) = creds {
-            user = user_pass.0;
-            password = user_pass.1;
-        } else {
-            println!("[{}] no credentials found in config.json", registry);
-        }
-    } else {
-        user = env::var("DKREG_USER").ok();
-        if user.is_none() {
-            println!("[{}] no $DKREG_USER for login user", registry);
-        }
-        password = env::var("DKREG_PASSWD").ok();
-        if password.is_none() {
-            println!("[{}] no $DKREG_PASSWD for login password", registry);
+    let resolver = dkregistry::credentials::AuthResolver::from_env();
+    let key = format!("{}/{}", registry, dkr_ref.repository());
+    let (user, password) = match resolver.resolve(&key) {
+        Ok(Some(creds)) => (creds.username, creds.password),
+        _ => {
+            println!("[{}] no credentials found in auth files", registry);
+            let user = env::var("DKREG_USER").ok();
+            if user.is_none() {
+                println!("[{}] no $DKREG_USER for login user", registry);
+            }
+            let password = env::var("DKREG_PASSWD").ok();
+            if password.is_none() {
+                println!("[{}] no $DKREG_PASSWD for login password", registry);
+            }
+            (user, password)
         }
     };
 
@@ -0,0 +1,609 @@
+//! Registry credentials.
+//!
+//! This module provides support for decoding and storing credentials
+//! used by docker-compatible clients, typically under `~/.docker/config.json`.
+
+use base64;
+use dirs;
+use errors::*;
+use reference::normalize_registry;
+use serde_json;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::{env, fs, io, path, process};
+
+/// Credentials for a single registry.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Credentials {
+    /// Username, for basic or OAuth2 password authentication.
+    pub username: Option<String>,
+    /// Password, for basic or OAuth2 password authentication.
+    pub password: Option<String>,
+    /// OAuth2 identity token, used as a refresh token at the token endpoint.
+    pub identity_token: Option<String>,
+}
+
+/// An executor for docker credential helpers.
+///
+/// Credential helpers are external programs which store registry
+/// credentials on behalf of docker-compatible clients. They are referenced
+/// by name from the `credHelpers` and `credsStore` entries of a config file.
+pub trait CredentialHelper {
+    /// Retrieve credentials for `server` from the helper called `name`.
+    ///
+    /// This returns `None` if the helper does not know about `server`.
+    fn get(&self, name: &str, server: &str) -> Result<Option<Credentials>>;
+
+    /// Store credentials for `server` via the helper called `name`.
+    fn store(&self, name: &str, server: &str, _creds: &Credentials) -> Result<()> {
+        bail!("credential helper {} cannot store {}", name, server)
+    }
+
+    /// Erase credentials for `server` from the helper called `name`.
+    fn erase(&self, name: &str, server: &str) -> Result<()> {
+        bail!("credential helper {} cannot erase {}", name, server)
+    }
+}
+
+/// Default credential helper executor.
+///
+/// This runs `docker-credential-<name>` binaries found in `$PATH`,
+/// following the docker credential helpers protocol.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExecHelper;
+
+/// Message printed by helpers when they have no credentials for a server.
+static HELPER_NOT_FOUND: &str = "credentials not found in native keychain";
+
+/// Username marking the secret returned by a helper as an identity token.
+static HELPER_TOKEN_USERNAME: &str = "<token>";
+
+impl ExecHelper {
+    fn run(&self, name: &str, action: &str, input: &[u8]) -> Result<process::Output> {
+        let program = format!("docker-credential-{}", name);
+        trace!("Running credential helper: {} {}", program, action);
+        let mut child = process::Command::new(&program)
+            .arg(action)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run {}: {}", program, e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input)?;
+        }
+        Ok(child.wait_with_output()?)
+    }
+}
+
+impl CredentialHelper for ExecHelper {
+    fn get(&self, name: &str, server: &str) -> Result<Option<Credentials>> {
+        let out = self.run(name, "get", server.as_bytes())?;
+        if !out.status.success() {
+            let msg = String::from_utf8_lossy(&out.stdout);
+            if msg.trim() == HELPER_NOT_FOUND {
+                return Ok(None);
+            }
+            bail!(
+                "credential helper {} failed ({}): {}",
+                name,
+                out.status,
+                msg.trim()
+            );
+        }
+        let creds: HelperCredentials = serde_json::from_slice(&out.stdout)?;
+        Ok(Some(creds.into()))
+    }
+
+    fn store(&self, name: &str, server: &str, creds: &Credentials) -> Result<()> {
+        let input = serde_json::to_vec(&HelperCredentials::new(server, creds))?;
+        let out = self.run(name, "store", &input)?;
+        if !out.status.success() {
+            bail!(
+                "credential helper {} failed ({}): {}",
+                name,
+                out.status,
+                String::from_utf8_lossy(&out.stdout).trim()
+            );
+        }
+        Ok(())
+    }
+
+    fn erase(&self, name: &str, server: &str) -> Result<()> {
+        let out = self.run(name, "erase", server.as_bytes())?;
+        if !out.status.success() {
+            let msg = String::from_utf8_lossy(&out.stdout);
+            if msg.trim() == HELPER_NOT_FOUND {
+                return Ok(());
+            }
+            bail!(
+                "credential helper {} failed ({}): {}",
+                name,
+                out.status,
+                msg.trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Credentials, as exchanged with credential helpers.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct HelperCredentials {
+    #[serde(rename = "ServerURL", default)]
+    server_url: String,
+    #[serde(rename = "Username", default)]
+    username: String,
+    #[serde(rename = "Secret", default)]
+    secret: String,
+}
+
+impl HelperCredentials {
+    fn new(server: &str, creds: &Credentials) -> Self {
+        let (username, secret) = match creds.identity_token {
+            Some(ref t) => (HELPER_TOKEN_USERNAME.to_string(), t.clone()),
+            None => (
+                creds.username.clone().unwrap_or_default(),
+                creds.password.clone().unwrap_or_default(),
+            ),
+        };
+        HelperCredentials {
+            server_url: server.to_string(),
+            username,
+            secret,
+        }
+    }
+}
+
+impl From<HelperCredentials> for Credentials {
+    fn from(hc: HelperCredentials) -> Self {
+        let non_empty = |s: String| if s.is_empty() { None } else { Some(s) };
+        if hc.username == HELPER_TOKEN_USERNAME {
+            Credentials {
+                identity_token: non_empty(hc.secret),
+                ..Credentials::default()
+            }
+        } else {
+            Credentials {
+                username: non_empty(hc.username),
+                password: non_empty(hc.secret),
+                identity_token: None,
+            }
+        }
+    }
+}
+
+/// Get registry credentials from a JSON config reader.
+///
+/// Both inline `auth` entries and `identitytoken` entries are decoded.
+/// Credential helpers referenced by `credHelpers` and `credsStore`
+/// are run via `ExecHelper`.
+pub fn read_credentials<T: Read>(reader: T, index: &str) -> Result<Credentials> {
+    read_credentials_with(reader, index, &ExecHelper)
+}
+
+/// Get registry credentials from a JSON config reader, using a custom
+/// executor for credential helpers.
+///
+/// A helper configured for `index` under `credHelpers` takes precedence,
+/// followed by the default `credsStore` helper and finally by inline
+/// `auths` entries.
+pub fn read_credentials_with<T: Read, H: CredentialHelper>(
+    reader: T,
+    index: &str,
+    helper: &H,
+) -> Result<Credentials> {
+    AuthFile::from_reader(reader)?.credentials_with(index, helper)
+}
+
+/// An editable docker `config.json` or containers `auth.json` file.
+///
+/// Entries which are not related to credentials are preserved
+/// as-is when the file is written back.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AuthFile {
+    json: serde_json::Map<String, serde_json::Value>,
+}
+
+impl AuthFile {
+    /// Parse an auth file from a JSON reader.
+    pub fn from_reader<T: Read>(reader: T) -> Result<Self> {
+        match serde_json::from_reader(reader)? {
+            serde_json::Value::Object(json) => Ok(Self { json }),
+            _ => bail!("auth file is not a JSON object"),
+        }
+    }
+
+    /// Load an auth file from `path`, or return an empty one if it does not exist.
+    pub fn load(path: &path::Path) -> Result<Self> {
+        match fs::File::open(path) {
+            Ok(fp) => Self::from_reader(io::BufReader::new(fp)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Serialize this auth file as JSON to a writer.
+    pub fn to_writer<T: Write>(&self, writer: T) -> Result<()> {
+        Ok(serde_json::to_writer_pretty(writer, &self.json)?)
+    }
+
+    /// Atomically write this auth file to `path`.
+    ///
+    /// The parent directory is created if missing. As the file may contain
+    /// secrets, it is only made readable by its owner.
+    pub fn save(&self, path: &path::Path) -> Result<()> {
+        let dir = match path.parent() {
+            Some(d) if !d.as_os_str().is_empty() => d,
+            _ => path::Path::new("."),
+        };
+        fs::create_dir_all(dir)?;
+        let fname = path
+            .file_name()
+            .ok_or_else(|| Error::from(format!("invalid auth file path {:?}", path)))?;
+        let tmp_path = dir.join(format!(".{}.tmp{}", fname.to_string_lossy(), process::id()));
+        {
+            let mut opts = fs::OpenOptions::new();
+            opts.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                opts.mode(0o600);
+            }
+            let mut fp = opts.open(&tmp_path)?;
+            self.to_writer(&mut fp)?;
+            fp.write_all(b"\n")?;
+            fp.sync_all()?;
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Get credentials for `index`, running credential helpers via `ExecHelper`.
+    pub fn credentials(&self, index: &str) -> Result<Credentials> {
+        self.credentials_with(index, &ExecHelper)
+    }
+
+    /// Get credentials for `index`, using a custom executor for credential helpers.
+    ///
+    /// See `lookup_with` for the lookup rules.
+    pub fn credentials_with<H: CredentialHelper>(
+        &self,
+        index: &str,
+        helper: &H,
+    ) -> Result<Credentials> {
+        match self.lookup_with(index, helper)? {
+            Some(creds) => Ok(creds),
+            None => bail!("no auth for index {}", server_key(index)),
+        }
+    }
+
+    /// Look up credentials for `key`, using a custom executor for credential helpers.
+    ///
+    /// The key is either a registry (e.g. `quay.io`) or a repository within
+    /// a registry (e.g. `quay.io/org/repo`). A helper configured for the
+    /// registry under `credHelpers` takes precedence, followed by the default
+    /// `credsStore` helper. Inline `auths` entries are then matched from the
+    /// most specific repository scope up to the whole registry.
+    pub fn lookup_with<H: CredentialHelper>(
+        &self,
+        key: &str,
+        helper: &H,
+    ) -> Result<Option<Credentials>> {
+        let config = self.config()?;
+        let (registry, repository) = split_key(key);
+        let server = server_key(&registry);
+
+        if let Some(name) = config.cred_helper(&registry, server) {
+            let creds = helper.get(name, server)?;
+            if creds.is_some() {
+                trace!("Found credentials for {} via helper {}", key, name);
+            }
+            return Ok(creds);
+        }
+        if let Some(ref name) = config.creds_store {
+            if let Some(creds) = helper.get(name, server)? {
+                trace!("Found credentials for {} via store {}", key, name);
+                return Ok(Some(creds));
+            }
+        }
+
+        let auths: Vec<(String, &AuthObj)> = config
+            .auths
+            .iter()
+            .map(|(k, v)| (normalize_auths_key(k), v))
+            .collect();
+        let mut scope = match repository {
+            Some(r) => format!("{}/{}", registry, r),
+            None => registry.clone(),
+        };
+        loop {
+            if let Some(&(_, entry)) = auths.iter().find(|(k, _)| *k == scope) {
+                return decode_auth(entry, &scope).map(Some);
+            }
+            match scope.rfind('/') {
+                Some(i) => scope.truncate(i),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Store credentials for `index`, running credential helpers via `ExecHelper`.
+    pub fn store(&mut self, index: &str, creds: &Credentials) -> Result<()> {
+        self.store_with(index, creds, &ExecHelper)
+    }
+
+    /// Store credentials for `index`, using a custom executor for credential helpers.
+    ///
+    /// Credentials are handed to the helper configured for `index` (either
+    /// under `credHelpers` or as `credsStore`) if any, otherwise they are
+    /// stored inline under `auths`.
+    pub fn store_with<H: CredentialHelper>(
+        &mut self,
+        index: &str,
+        creds: &Credentials,
+        helper: &H,
+    ) -> Result<()> {
+        let config = self.config()?;
+        let real_index = server_key(index);
+        let (registry, _) = split_key(index);
+
+        let entry = match config.helper_for(&registry, real_index) {
+            Some(name) => {
+                helper.store(name, real_index, creds)?;
+                if config.cred_helper(&registry, real_index).is_some() {
+                    return Ok(());
+                }
+                // Keep an empty entry, so that the registry is still listed.
+                serde_json::Map::new()
+            }
+            None => encode_auth(creds),
+        };
+        let auths = self
+            .json
+            .entry("auths")
+            .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+        match auths.as_object_mut() {
+            Some(m) => {
+                m.insert(real_index.to_string(), serde_json::Value::Object(entry));
+            }
+            None => bail!("invalid auths entry in auth file"),
+        }
+        trace!("Stored credentials for {}", index);
+        Ok(())
+    }
+
+    /// Erase credentials for `index`, running credential helpers via `ExecHelper`.
+    pub fn erase(&mut self, index: &str) -> Result<()> {
+        self.erase_with(index, &ExecHelper)
+    }
+
+    /// Erase credentials for `index`, using a custom executor for credential helpers.
+    pub fn erase_with<H: CredentialHelper>(&mut self, index: &str, helper: &H) -> Result<()> {
+        let config = self.config()?;
+        let real_index = server_key(index);
+        let (registry, _) = split_key(index);
+
+        if let Some(name) = config.helper_for(&registry, real_index) {
+            helper.erase(name, real_index)?;
+        }
+        if let Some(m) = self
+            .json
+            .get_mut("auths")
+            .and_then(serde_json::Value::as_object_mut)
+        {
+            m.remove(real_index);
+        }
+        trace!("Erased credentials for {}", index);
+        Ok(())
+    }
+
+    fn config(&self) -> Result<ConfigFile> {
+        let value = serde_json::Value::Object(self.json.clone());
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+/// Return the config key under which credentials for `index` are stored.
+fn server_key(index: &str) -> &str {
+    match normalize_registry(index) {
+        // docker.io has some special casing in config.json
+        "docker.io" => "https://index.docker.io/v1/",
+        other => other,
+    }
+}
+
+/// Split a lookup key into normalized registry and optional repository.
+fn split_key(key: &str) -> (String, Option<String>) {
+    let key = key
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_end_matches('/');
+    match key.find('/') {
+        Some(i) => (
+            normalize_registry(&key[..i]).to_string(),
+            Some(key[i + 1..].to_string()),
+        ),
+        None => (normalize_registry(key).to_string(), None),
+    }
+}
+
+/// Normalize a key found under `auths`.
+///
+/// Legacy docker keys are URLs (e.g. `https://index.docker.io/v1/`), for
+/// which only the hostname is relevant. Other keys may be scoped to a
+/// namespace or repository (e.g. `quay.io/org/repo`).
+fn normalize_auths_key(key: &str) -> String {
+    if key.starts_with("http://") || key.starts_with("https://") {
+        let host = key
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+            .split('/')
+            .next()
+            .unwrap_or("");
+        return normalize_registry(host).to_string();
+    }
+    match split_key(key) {
+        (reg, Some(repo)) => format!("{}/{}", reg, repo),
+        (reg, None) => reg,
+    }
+}
+
+fn decode_auth(entry: &AuthObj, index: &str) -> Result<Credentials> {
+    let auth = base64::decode(entry.auth.as_str())?;
+    let s = String::from_utf8(auth)?;
+    let creds: Vec<&str> = s.splitn(2, ':').collect();
+    let (username, password) = match (creds.first(), creds.get(1)) {
+        (Some(&""), Some(p)) => (None, Some(p.to_string())),
+        (Some(u), Some(&"")) => (Some(u.to_string()), None),
+        (Some(u), Some(p)) => (Some(u.to_string()), Some(p.to_string())),
+        (_, _) => (None, None),
+    };
+    let identity_token = entry.identitytoken.clone().filter(|t| !t.is_empty());
+    trace!(
+        "Found credentials for user={:?} on {} (identity token: {})",
+        username,
+        index,
+        identity_token.is_some()
+    );
+    Ok(Credentials {
+        username,
+        password,
+        identity_token,
+    })
+}
+
+/// Resolver for credentials spread across multiple auth files.
+///
+/// Files are searched in order, and the first one providing credentials
+/// for a key wins. Missing files are skipped.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AuthResolver {
+    paths: Vec<path::PathBuf>,
+}
+
+impl AuthResolver {
+    /// Initialize a resolver searching the given auth files, in order.
+    pub fn new(paths: Vec<path::PathBuf>) -> Self {
+        Self { paths }
+    }
+
+    /// Initialize a resolver searching the standard auth file locations.
+    ///
+    /// Locations are searched in the same order as containers tools do:
+    ///  1. `$REGISTRY_AUTH_FILE`
+    ///  2. `$XDG_RUNTIME_DIR/containers/auth.json`
+    ///  3. `$XDG_CONFIG_HOME/containers/auth.json` (or `~/.config/containers/auth.json`)
+    ///  4. `$DOCKER_CONFIG/config.json`, or `~/.docker/config.json` if unset
+    ///
+    /// As with docker, `$DOCKER_CONFIG` replaces the default docker config
+    /// directory: `~/.docker/config.json` is not searched when it is set.
+    pub fn from_env() -> Self {
+        let var = |name: &str| env::var_os(name).filter(|v| !v.is_empty());
+        let home = dirs::home_dir();
+        let mut paths = vec![];
+
+        if let Some(p) = var("REGISTRY_AUTH_FILE") {
+            paths.push(path::PathBuf::from(p));
+        }
+        if let Some(d) = var("XDG_RUNTIME_DIR") {
+            paths.push(path::Path::new(&d).join("containers/auth.json"));
+        }
+        match (var("XDG_CONFIG_HOME"), &home) {
+            (Some(d), _) => paths.push(path::Path::new(&d).join("containers/auth.json")),
+            (None, Some(h)) => paths.push(h.join(".config/containers/auth.json")),
+            (None, None) => {}
+        };
+        match (var("DOCKER_CONFIG"), &home) {
+            (Some(d), _) => paths.push(path::Path::new(&d).join("config.json")),
+            (None, Some(h)) => paths.push(h.join(".docker/config.json")),
+            (None, None) => {}
+        };
+        Self { paths }
+    }
+
+    /// Auth files searched by this resolver, in order.
+    pub fn paths(&self) -> &[path::PathBuf] {
+        &self.paths
+    }
+
+    /// Resolve credentials for `key`, running credential helpers via `ExecHelper`.
+    pub fn resolve(&self, key: &str) -> Result<Option<Credentials>> {
+        self.resolve_with(key, &ExecHelper)
+    }
+
+    /// Resolve credentials for `key`, using a custom executor for credential helpers.
+    ///
+    /// The key is either a registry or a repository within a registry,
+    /// see `AuthFile::lookup_with` for the lookup rules within each file.
+    pub fn resolve_with<H: CredentialHelper>(
+        &self,
+        key: &str,
+        helper: &H,
+    ) -> Result<Option<Credentials>> {
+        for p in &self.paths {
+            let authfile = match AuthFile::load(p) {
+                Ok(a) => a,
+                Err(e) => bail!("failed to read auth file {:?}: {}", p, e),
+            };
+            if let Some(creds) = authfile.lookup_with(key, helper)? {
+                trace!("Found credentials for {} in {:?}", key, p);
+                return Ok(Some(creds));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn encode_auth(creds: &Credentials) -> serde_json::Map<String, serde_json::Value> {
+    let mut entry = serde_json::Map::new();
+    if creds.username.is_some() || creds.password.is_some() {
+        let plain = format!(
+            "{}:{}",
+            creds.username.as_deref().unwrap_or(""),
+            creds.password.as_deref().unwrap_or("")
+        );
+        entry.insert("auth".into(), base64::encode(&plain).into());
+    }
+    if let Some(ref t) = creds.identity_token {
+        entry.insert("identitytoken".into(), t.clone().into());
+    }
+    entry
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct ConfigFile {
+    #[serde(default)]
+    auths: HashMap<String, AuthObj>,
+    #[serde(rename = "credHelpers", default)]
+    cred_helpers: HashMap<String, String>,
+    #[serde(rename = "credsStore", default)]
+    creds_store: Option<String>,
+}
+
+impl ConfigFile {
+    /// Name of the `credHelpers` entry for a registry, if any.
+    ///
+    /// Entries are keyed either by registry (e.g. `docker.io`) or by server
+    /// (e.g. `https://index.docker.io/v1/`), the former taking precedence.
+    fn cred_helper(&self, registry: &str, server: &str) -> Option<&str> {
+        self.cred_helpers
+            .get(registry)
+            .or_else(|| self.cred_helpers.get(server))
+            .map(String::as_str)
+    }
+
+    /// Name of the credential helper in charge of a registry, if any.
+    fn helper_for(&self, registry: &str, server: &str) -> Option<&str> {
+        self.cred_helper(registry, server)
+            .or(self.creds_store.as_deref())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct AuthObj {
+    #[serde(default)]
+    auth: String,
+    #[serde(default)]
+    identitytoken: Option<String>,
+}
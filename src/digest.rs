@@ -0,0 +1,291 @@
+//! Content digests.
+//!
+//! This module provides support for the content-addressable digests used
+//! by registries to identify blobs and manifests, e.g. `sha256:<hex>`.
+//!
+//! ## Example
+//!
+//! ```rust
+//! # extern crate dkregistry;
+//! # fn main() {
+//! # fn run() -> dkregistry::errors::Result<()> {
+//! #
+//! use std::str::FromStr;
+//! use dkregistry::digest::{Algorithm, Digest, Hasher};
+//!
+//! // Parse a digest
+//! let digest = Digest::from_str(
+//!     "sha256:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae",
+//! )?;
+//! assert_eq!(digest.algorithm(), Algorithm::Sha256);
+//!
+//! // Compute it over streamed content, and verify it
+//! let mut hasher = Hasher::new(Algorithm::Sha256);
+//! hasher.update(b"f");
+//! hasher.update(b"oo");
+//! assert_eq!(hasher.finish(), digest);
+//! digest.verify(b"foo")?;
+//! #
+//! # Ok(())
+//! # };
+//! # run().unwrap();
+//! # }
+//! ```
+
+use errors::{self, ErrorKind};
+use reference::ParseError;
+use ring;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, io, str};
+
+/// Hash algorithm of a digest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    /// Name of this algorithm, as used in digests.
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha512 => "sha512",
+        }
+    }
+
+    /// Length of the hex encoding of digests computed with this algorithm.
+    pub fn hex_len(self) -> usize {
+        match self {
+            Algorithm::Sha256 => 64,
+            Algorithm::Sha512 => 128,
+        }
+    }
+
+    fn ring_algorithm(self) -> &'static ring::digest::Algorithm {
+        match self {
+            Algorithm::Sha256 => &ring::digest::SHA256,
+            Algorithm::Sha512 => &ring::digest::SHA512,
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A validated content digest, e.g. `sha256:<hex>`.
+///
+/// There is no default digest, so types holding one do not implement `Default`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Digest {
+    algorithm: Algorithm,
+    hex: String,
+}
+
+impl Digest {
+    /// Build a digest from its algorithm and hex encoding.
+    pub fn new(algorithm: Algorithm, hex: &str) -> errors::Result<Self> {
+        let valid_hex = hex.len() == algorithm.hex_len()
+            && hex
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+        if !valid_hex {
+            let s = format!("{}:{}", algorithm, hex);
+            bail!(ErrorKind::InvalidDigest(ParseError::InvalidDigest(s)));
+        }
+        Ok(Self {
+            algorithm,
+            hex: hex.to_string(),
+        })
+    }
+
+    /// Compute the digest of some content.
+    pub fn from_bytes(algorithm: Algorithm, data: &[u8]) -> Self {
+        let mut hasher = Hasher::new(algorithm);
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    /// Hash algorithm of this digest.
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Hex encoding of this digest, without the algorithm.
+    pub fn hex(&self) -> &str {
+        &self.hex
+    }
+
+    /// Check that some content matches this digest.
+    pub fn verify(&self, data: &[u8]) -> errors::Result<()> {
+        let mut verifier = Verifier::new(self.clone());
+        verifier.update(data);
+        verifier.verify()
+    }
+}
+
+impl str::FromStr for Digest {
+    type Err = errors::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s).map_err(|e| ErrorKind::InvalidDigest(e).into())
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.hex)
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Digest({})", self)
+    }
+}
+
+/// Serialize as a string, e.g. `sha256:<hex>`.
+impl Serialize for Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Parse and validate a digest, in `algorithm:encoded` form.
+///
+/// The algorithm must follow the digest grammar, and be a supported one.
+/// The encoding must be lowercase hex, of the algorithm's length.
+pub(crate) fn parse(s: &str) -> Result<Digest, ParseError> {
+    let invalid = || ParseError::InvalidDigest(s.to_string());
+    let i = s.find(':').ok_or_else(invalid)?;
+    let (algo, hex) = (&s[..i], &s[i + 1..]);
+
+    let is_alnum = |b: u8| b.is_ascii_lowercase() || b.is_ascii_digit();
+    let algo_ok = algo
+        .split(['+', '.', '_', '-'])
+        .all(|c| !c.is_empty() && c.bytes().all(is_alnum));
+    if !algo_ok || hex.len() < 32 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let algorithm = match algo {
+        "sha256" => Algorithm::Sha256,
+        "sha512" => Algorithm::Sha512,
+        _ => return Err(ParseError::UnsupportedDigestAlgorithm(algo.to_string())),
+    };
+    if hex.len() != algorithm.hex_len() || hex.bytes().any(|b| b.is_ascii_uppercase()) {
+        return Err(invalid());
+    }
+    Ok(Digest {
+        algorithm,
+        hex: hex.to_string(),
+    })
+}
+
+/// Streaming digest computation.
+///
+/// Content can be fed incrementally via `update`, or written to the
+/// hasher as an `io::Write`.
+pub struct Hasher {
+    algorithm: Algorithm,
+    context: ring::digest::Context,
+}
+
+impl Hasher {
+    /// Start computing a digest with the given algorithm.
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            context: ring::digest::Context::new(algorithm.ring_algorithm()),
+        }
+    }
+
+    /// Feed more content.
+    pub fn update(&mut self, data: &[u8]) {
+        self.context.update(data);
+    }
+
+    /// Return the digest of all content fed so far.
+    pub fn finish(self) -> Digest {
+        let hex: String = self
+            .context
+            .finish()
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Digest {
+            algorithm: self.algorithm,
+            hex,
+        }
+    }
+}
+
+impl fmt::Debug for Hasher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Hasher")
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+impl io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Streaming verification of content against an expected digest.
+#[derive(Debug)]
+pub struct Verifier {
+    expected: Digest,
+    hasher: Hasher,
+}
+
+impl Verifier {
+    /// Start verifying content against `expected`.
+    pub fn new(expected: Digest) -> Self {
+        let hasher = Hasher::new(expected.algorithm);
+        Self { expected, hasher }
+    }
+
+    /// Feed more content.
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    /// Check that all content fed so far matches the expected digest.
+    ///
+    /// This fails with `ErrorKind::DigestMismatch` otherwise.
+    pub fn verify(self) -> errors::Result<()> {
+        let actual = self.hasher.finish();
+        if actual != self.expected {
+            bail!(ErrorKind::DigestMismatch(self.expected, actual));
+        }
+        Ok(())
+    }
+}
+
+impl io::Write for Verifier {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
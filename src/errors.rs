@@ -1,10 +1,15 @@
 //! Error chains, types and traits.
 
 use base64;
+use digest::Digest;
 use http;
 use hyper;
+use reference::ParseError;
 use serde_json;
-use std::{io, string};
+use std::time::Duration;
+use std::{fmt, io, string};
+use toml;
+use url;
 
 error_chain! {
     foreign_links {
@@ -14,7 +19,66 @@ error_chain! {
         Hyper(hyper::Error);
         Io(io::Error);
         Json(serde_json::Error);
+        TomlParse(toml::de::Error);
         UriParse(http::uri::InvalidUri);
+        UrlParse(url::ParseError);
         Utf8Parse(string::FromUtf8Error);
     }
+
+    errors {
+        /// A digest is malformed, or uses an unsupported algorithm.
+        InvalidDigest(err: ParseError) {
+            description("invalid digest")
+            display("invalid digest: {}", err)
+        }
+
+        /// Some content does not match its expected digest.
+        DigestMismatch(expected: Digest, actual: Digest) {
+            description("digest mismatch")
+            display("digest mismatch: expected {}, got {}", expected, actual)
+        }
+
+        /// An image reference does not follow the reference grammar.
+        InvalidReference(err: ParseError) {
+            description("invalid image reference")
+            display("invalid image reference: {}", err)
+        }
+
+        /// A token endpoint answered with an unexpected HTTP status.
+        TokenStatus(status: hyper::StatusCode) {
+            description("unexpected token endpoint status")
+            display("login: wrong HTTP status '{}'", status)
+        }
+
+        /// An operation did not complete within the configured time limit.
+        Timeout(kind: TimeoutKind, limit: Duration) {
+            description("operation timed out")
+            display("{} timeout of {:?} exceeded", kind, limit)
+        }
+    }
+}
+
+/// Phase of a request which timed out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutKind {
+    /// Establishing the connection to the registry.
+    Connect,
+    /// Waiting for the response headers, once the request was sent.
+    FirstByte,
+    /// Waiting for the next chunk of the response body.
+    Idle,
+    /// Completing the whole operation, including retries.
+    Total,
+}
+
+impl fmt::Display for TimeoutKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            TimeoutKind::Connect => "connect",
+            TimeoutKind::FirstByte => "first-byte",
+            TimeoutKind::Idle => "idle",
+            TimeoutKind::Total => "total",
+        };
+        f.write_str(s)
+    }
 }
@@ -0,0 +1,403 @@
+//! Build image layers.
+//!
+//! A `LayerBuilder` collects entries, from directories on disk or from
+//! memory, and produces a reproducible layer tarball: entries are sorted
+//! by path, with fixed timestamps and ownership, so that the same content
+//! always yields the same digest.
+
+use digest::{Algorithm, Digest, Hasher};
+use errors::*;
+use libflate::gzip;
+use mediatypes::MediaTypes;
+use render::{self, Compression};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Read, Write};
+use std::{fs, path};
+use tar;
+use zstd;
+
+/// Permission bits of synthesized parent directories.
+const DEFAULT_DIR_MODE: u32 = 0o755;
+
+/// Permission bits of whiteout markers.
+const WHITEOUT_MODE: u32 = 0o644;
+
+/// Content of a layer entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EntryContent {
+    /// A regular file, with its data.
+    File(Vec<u8>),
+    Directory,
+    /// A symlink, with its target.
+    Symlink(path::PathBuf),
+}
+
+/// An entry to add to a layer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LayerEntry {
+    /// Path, relative to the root of the image.
+    pub path: path::PathBuf,
+    pub content: EntryContent,
+    /// Permission bits.
+    pub mode: u32,
+}
+
+impl LayerEntry {
+    /// A regular file, with mode `0644`.
+    pub fn file<P: Into<path::PathBuf>>(path: P, data: Vec<u8>) -> Self {
+        LayerEntry {
+            path: path.into(),
+            content: EntryContent::File(data),
+            mode: 0o644,
+        }
+    }
+
+    /// A directory, with mode `0755`.
+    pub fn dir<P: Into<path::PathBuf>>(path: P) -> Self {
+        LayerEntry {
+            path: path.into(),
+            content: EntryContent::Directory,
+            mode: DEFAULT_DIR_MODE,
+        }
+    }
+
+    /// A symlink to `target`.
+    pub fn symlink<P: Into<path::PathBuf>, T: Into<path::PathBuf>>(path: P, target: T) -> Self {
+        LayerEntry {
+            path: path.into(),
+            content: EntryContent::Symlink(target.into()),
+            mode: 0o777,
+        }
+    }
+
+    /// Set permission bits.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+/// A built layer.
+#[derive(Debug)]
+pub struct Layer {
+    /// Layer blob, as compressed.
+    pub data: Vec<u8>,
+    /// Media type of the blob.
+    pub media_type: MediaTypes,
+    /// Digest of the blob, as referenced by manifests.
+    pub digest: Digest,
+    /// Digest of the uncompressed tar archive, as listed in the image
+    /// configuration.
+    pub diff_id: Digest,
+}
+
+/// Builder of reproducible layers.
+///
+/// Entries are kept sorted by path, and all of them get the same
+/// modification time and ownership. Missing parent directories are
+/// added with mode `0755`.
+#[derive(Clone, Debug)]
+pub struct LayerBuilder {
+    compression: Compression,
+    mtime: u64,
+    uid: u64,
+    gid: u64,
+    entries: BTreeMap<path::PathBuf, LayerEntry>,
+}
+
+impl Default for LayerBuilder {
+    fn default() -> Self {
+        LayerBuilder {
+            compression: Compression::Gzip,
+            mtime: 0,
+            uid: 0,
+            gid: 0,
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl LayerBuilder {
+    /// Start an empty gzip layer, with entries owned by root and dated
+    /// at the epoch.
+    pub fn new() -> Self {
+        LayerBuilder::default()
+    }
+
+    /// Set the compression of the layer blob.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set the modification time of all entries, in seconds since the epoch.
+    pub fn mtime(mut self, mtime: u64) -> Self {
+        self.mtime = mtime;
+        self
+    }
+
+    /// Set the owner of all entries.
+    pub fn owner(mut self, uid: u64, gid: u64) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// Add an entry, replacing any previous one at the same path.
+    pub fn add_entry(&mut self, mut entry: LayerEntry) -> Result<()> {
+        entry.path = render::relative_path(&entry.path)?;
+        if entry.path.file_name().is_none() {
+            bail!("layer entries must not be the root directory");
+        }
+        self.entries.insert(entry.path.clone(), entry);
+        Ok(())
+    }
+
+    /// Hide a path provided by lower layers.
+    pub fn add_whiteout<P: AsRef<path::Path>>(&mut self, path: P) -> Result<()> {
+        let path = render::relative_path(path.as_ref())?;
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => bail!("the root directory cannot be whited out"),
+        };
+        let marker = path.with_file_name(format!("{}{}", render::WHITEOUT_PREFIX, name));
+        self.add_entry(LayerEntry::file(marker, vec![]).mode(WHITEOUT_MODE))
+    }
+
+    /// Hide the contents of a directory provided by lower layers.
+    pub fn add_opaque_whiteout<P: AsRef<path::Path>>(&mut self, dir: P) -> Result<()> {
+        let marker = dir.as_ref().join(render::OPAQUE_WHITEOUT);
+        self.add_entry(LayerEntry::file(marker, vec![]).mode(WHITEOUT_MODE))
+    }
+
+    /// Add all contents of a directory, which becomes the root of the image.
+    ///
+    /// Regular files, directories and symlinks are supported, and hard
+    /// links are stored as separate files.
+    pub fn add_dir<P: AsRef<path::Path>>(&mut self, dir: P) -> Result<()> {
+        self.diff_dir(None, dir.as_ref(), path::Path::new(""))
+            .map(|_| ())
+    }
+
+    /// Add the changes from the `lower` directory to the `upper` one.
+    ///
+    /// Entries which are new or modified in `upper` are added, along with
+    /// their parent directories, and entries missing from `upper` are
+    /// whited out. Files are compared by type, permission bits and content.
+    pub fn add_diff<L, U>(&mut self, lower: L, upper: U) -> Result<()>
+    where
+        L: AsRef<path::Path>,
+        U: AsRef<path::Path>,
+    {
+        let lower = lower.as_ref();
+        if !lower.is_dir() {
+            bail!("lower tree '{}' is not a directory", lower.display());
+        }
+        self.diff_dir(Some(lower), upper.as_ref(), path::Path::new(""))
+            .map(|_| ())
+    }
+
+    /// Build the layer.
+    pub fn build(&self) -> Result<Layer> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut parents = HashSet::new();
+        for entry in self.entries.values() {
+            // Entries are sorted, so existing parents are already written.
+            let missing: Vec<&path::Path> = entry
+                .path
+                .ancestors()
+                .skip(1)
+                .filter(|p| p.file_name().is_some() && !self.entries.contains_key(*p))
+                .collect();
+            for parent in missing.into_iter().rev() {
+                if parents.insert(parent.to_path_buf()) {
+                    self.append(&mut builder, &LayerEntry::dir(parent))?;
+                }
+            }
+            self.append(&mut builder, entry)?;
+        }
+        let tar = builder.into_inner()?;
+
+        let (data, media_type) = match self.compression {
+            Compression::None => (tar.clone(), MediaTypes::OciImageLayerTar),
+            Compression::Gzip => {
+                // The gzip header would otherwise hold the current time.
+                let header = gzip::HeaderBuilder::new().modification_time(0).finish();
+                let options = gzip::EncodeOptions::new().header(header);
+                let mut encoder = gzip::Encoder::with_options(Vec::new(), options)?;
+                encoder.write_all(&tar)?;
+                (
+                    encoder.finish().into_result()?,
+                    MediaTypes::OciImageLayerTgz,
+                )
+            }
+            Compression::Zstd => (
+                zstd::stream::encode_all(tar.as_slice(), 0)?,
+                MediaTypes::OciImageLayerTzst,
+            ),
+        };
+        Ok(Layer {
+            digest: Digest::from_bytes(Algorithm::Sha256, &data),
+            diff_id: Digest::from_bytes(Algorithm::Sha256, &tar),
+            data,
+            media_type,
+        })
+    }
+
+    /// Write an entry to a tar archive, with normalized metadata.
+    fn append(&self, builder: &mut tar::Builder<Vec<u8>>, entry: &LayerEntry) -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(entry.mode);
+        header.set_uid(self.uid);
+        header.set_gid(self.gid);
+        header.set_mtime(self.mtime);
+        let data: &[u8] = match entry.content {
+            EntryContent::File(ref data) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                data
+            }
+            EntryContent::Directory => {
+                header.set_entry_type(tar::EntryType::Directory);
+                &[]
+            }
+            EntryContent::Symlink(ref target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                builder.append_link(&mut header, &entry.path, target)?;
+                return Ok(());
+            }
+        };
+        header.set_size(data.len() as u64);
+        builder.append_data(&mut header, &entry.path, data)?;
+        Ok(())
+    }
+
+    /// Add the changes below a directory, at `rel` in the layer.
+    ///
+    /// Without a lower directory, all contents are added. Returns whether
+    /// anything changed.
+    fn diff_dir(
+        &mut self,
+        lower: Option<&path::Path>,
+        upper: &path::Path,
+        rel: &path::Path,
+    ) -> Result<bool> {
+        let mut changed = false;
+        let mut names = HashSet::new();
+        for entry in fs::read_dir(upper)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let (upper_path, path) = (entry.path(), rel.join(&name));
+            let meta = fs::symlink_metadata(&upper_path)?;
+            let lower_path = lower.map(|l| l.join(&name));
+            let lower_meta = lower_path
+                .as_ref()
+                .and_then(|p| fs::symlink_metadata(p).ok());
+            names.insert(name);
+
+            let modified = if meta.is_dir() {
+                let lower_dir = match lower_meta {
+                    Some(ref m) if m.is_dir() => lower_path.as_deref(),
+                    _ => None,
+                };
+                let below = self.diff_dir(lower_dir, &upper_path, &path)?;
+                below
+                    || lower_dir.is_none()
+                    || lower_meta.map(|m| file_mode(&m)) != Some(file_mode(&meta))
+            } else {
+                match (lower_path, lower_meta) {
+                    (Some(ref p), Some(ref m)) => !same_file(p, m, &upper_path, &meta)?,
+                    _ => true,
+                }
+            };
+            if modified {
+                self.add_entry(read_entry(&upper_path, &meta, path)?)?;
+                changed = true;
+            }
+        }
+
+        if let Some(lower) = lower {
+            for entry in fs::read_dir(lower)? {
+                let name = entry?.file_name();
+                if !names.contains(&name) {
+                    self.add_whiteout(rel.join(&name))?;
+                    changed = true;
+                }
+            }
+        }
+        Ok(changed)
+    }
+}
+
+/// Read a layer entry from a file on disk.
+fn read_entry(src: &path::Path, meta: &fs::Metadata, path: path::PathBuf) -> Result<LayerEntry> {
+    let file_type = meta.file_type();
+    let content = if file_type.is_dir() {
+        EntryContent::Directory
+    } else if file_type.is_symlink() {
+        EntryContent::Symlink(fs::read_link(src)?)
+    } else if file_type.is_file() {
+        let mut data = vec![];
+        fs::File::open(src)?.read_to_end(&mut data)?;
+        EntryContent::File(data)
+    } else {
+        bail!("unsupported file type at '{}'", src.display());
+    };
+    Ok(LayerEntry {
+        path,
+        content,
+        mode: file_mode(meta),
+    })
+}
+
+/// Whether two files on disk have the same type, mode and content.
+fn same_file(
+    a: &path::Path,
+    a_meta: &fs::Metadata,
+    b: &path::Path,
+    b_meta: &fs::Metadata,
+) -> Result<bool> {
+    let (a_type, b_type) = (a_meta.file_type(), b_meta.file_type());
+    if a_type.is_dir() != b_type.is_dir()
+        || a_type.is_symlink() != b_type.is_symlink()
+        || a_type.is_file() != b_type.is_file()
+        || file_mode(a_meta) != file_mode(b_meta)
+    {
+        return Ok(false);
+    }
+    if a_type.is_symlink() {
+        return Ok(fs::read_link(a)? == fs::read_link(b)?);
+    }
+    if !a_type.is_file() {
+        return Ok(false);
+    }
+    if a_meta.len() != b_meta.len() {
+        return Ok(false);
+    }
+    Ok(read_hash(a)? == read_hash(b)?)
+}
+
+/// Digest of the content of a file.
+fn read_hash(path: &path::Path) -> Result<Digest> {
+    let mut hasher = Hasher::new(Algorithm::Sha256);
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finish())
+}
+
+/// Permission bits of a file.
+#[cfg(unix)]
+fn file_mode(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(meta: &fs::Metadata) -> u32 {
+    if meta.is_dir() {
+        DEFAULT_DIR_MODE
+    } else if meta.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
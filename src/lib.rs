@@ -36,6 +36,8 @@
 #![deny(missing_debug_implementations)]
 
 extern crate base64;
+extern crate ct_logs;
+extern crate dirs;
 extern crate futures;
 extern crate http;
 extern crate hyper;
@@ -44,6 +46,7 @@ extern crate mime;
 extern crate serde;
 extern crate serde_json;
 extern crate tokio_core;
+extern crate tokio_io;
 #[macro_use]
 extern crate error_chain;
 #[macro_use]
@@ -55,21 +58,36 @@ extern crate strum;
 extern crate tar;
 #[macro_use]
 extern crate strum_macros;
-extern crate reqwest;
+extern crate ring;
+extern crate rustls;
+extern crate time;
+extern crate toml;
+extern crate url;
+extern crate webpki;
+extern crate webpki_roots;
+#[cfg(unix)]
+extern crate xattr;
+extern crate zstd;
 
+pub mod credentials;
+pub mod digest;
 pub mod errors;
+pub mod layer;
 pub mod mediatypes;
 pub mod reference;
+pub mod registries;
 pub mod render;
 pub mod v2;
 
 use errors::Result;
-use std::collections::HashMap;
 use std::io::Read;
 
 /// Default User-Agent client identity.
 pub static USER_AGENT: &'static str = "camallo-dkregistry/0.0";
 
+/// Default client identifier for OAuth2 token requests.
+pub static CLIENT_ID: &str = "dkregistry";
+
 /// Get registry credentials from a JSON config reader.
 ///
 /// This is a convenience decoder for docker-client credentials
@@ -78,34 +96,6 @@ pub fn get_credentials<T: Read>(
     reader: T,
     index: &str,
 ) -> Result<(Option<String>, Option<String>)> {
-    let map: Auths = try!(serde_json::from_reader(reader));
-    let real_index = match index {
-        // docker.io has some special casing in config.json
-        "docker.io" | "registry-1.docker.io" => "https://index.docker.io/v1/",
-        other => other,
-    };
-    let auth = match map.auths.get(real_index) {
-        Some(x) => try!(base64::decode(x.auth.as_str())),
-        None => bail!("no auth for index {}", real_index),
-    };
-    let s = try!(String::from_utf8(auth));
-    let creds: Vec<&str> = s.splitn(2, ':').collect();
-    let up = match (creds.get(0), creds.get(1)) {
-        (Some(&""), Some(p)) => (None, Some(p.to_string())),
-        (Some(u), Some(&"")) => (Some(u.to_string()), None),
-        (Some(u), Some(p)) => (Some(u.to_string()), Some(p.to_string())),
-        (_, _) => (None, None),
-    };
-    trace!("Found credentials for user={:?} on {}", up.0, index);
-    Ok(up)
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Auths {
-    auths: HashMap<String, AuthObj>,
-}
-
-#[derive(Debug, Default, Deserialize, Serialize)]
-struct AuthObj {
-    auth: String,
+    let creds = credentials::read_credentials(reader, index)?;
+    Ok((creds.username, creds.password))
 }
@@ -28,10 +28,30 @@ pub enum MediaTypes {
     #[strum(serialize = "application/vnd.docker.distribution.manifest.list.v2+json")]
     #[strum(props(Sub = "vnd.docker.distribution.manifest.list.v2+json"))]
     ManifestList,
+    /// OCI image manifest.
+    #[strum(serialize = "application/vnd.oci.image.manifest.v1+json")]
+    #[strum(props(Sub = "vnd.oci.image.manifest.v1+json"))]
+    OciImageManifest,
+    /// OCI image index, referencing manifests for several platforms.
+    #[strum(serialize = "application/vnd.oci.image.index.v1+json")]
+    #[strum(props(Sub = "vnd.oci.image.index.v1+json"))]
+    OciImageIndex,
     /// Image layer, as a gzip-compressed tar.
     #[strum(serialize = "application/vnd.docker.image.rootfs.diff.tar.gzip")]
     #[strum(props(Sub = "vnd.docker.image.rootfs.diff.tar.gzip"))]
     ImageLayerTgz,
+    /// OCI image layer, as an uncompressed tar.
+    #[strum(serialize = "application/vnd.oci.image.layer.v1.tar")]
+    #[strum(props(Sub = "vnd.oci.image.layer.v1.tar"))]
+    OciImageLayerTar,
+    /// OCI image layer, as a gzip-compressed tar.
+    #[strum(serialize = "application/vnd.oci.image.layer.v1.tar+gzip")]
+    #[strum(props(Sub = "vnd.oci.image.layer.v1.tar+gzip"))]
+    OciImageLayerTgz,
+    /// OCI image layer, as a zstd-compressed tar.
+    #[strum(serialize = "application/vnd.oci.image.layer.v1.tar+zstd")]
+    #[strum(props(Sub = "vnd.oci.image.layer.v1.tar+zstd"))]
+    OciImageLayerTzst,
     /// Configuration object for a container.
     #[strum(serialize = "application/vnd.docker.container.image.v1+json")]
     #[strum(props(Sub = "vnd.docker.container.image.v1+json"))]
@@ -57,11 +77,20 @@ impl MediaTypes {
                     ("vnd.docker.distribution.manifest.list.v2", "json") => {
                         Ok(MediaTypes::ManifestList)
                     }
+                    ("vnd.oci.image.manifest.v1", "json") => Ok(MediaTypes::OciImageManifest),
+                    ("vnd.oci.image.index.v1", "json") => Ok(MediaTypes::OciImageIndex),
                     ("vnd.docker.image.rootfs.diff.tar.gzip", _) => Ok(MediaTypes::ImageLayerTgz),
                     ("vnd.docker.container.image.v1", "json") => Ok(MediaTypes::ContainerConfigV1),
+                    ("vnd.oci.image.layer.v1.tar", "gzip") => Ok(MediaTypes::OciImageLayerTgz),
+                    ("vnd.oci.image.layer.v1.tar", "zstd") => Ok(MediaTypes::OciImageLayerTzst),
                     _ => bail!("unknown mediatype {:?}", mtype),
                 }
             }
+            (mime::APPLICATION, subt, None) => match subt.to_string().as_str() {
+                "vnd.docker.image.rootfs.diff.tar.gzip" => Ok(MediaTypes::ImageLayerTgz),
+                "vnd.oci.image.layer.v1.tar" => Ok(MediaTypes::OciImageLayerTar),
+                _ => bail!("unknown mediatype {:?}", mtype),
+            },
             _ => bail!("unknown mediatype {:?}", mtype),
         }
     }
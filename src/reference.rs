@@ -14,9 +14,14 @@
 //!
 //! // Parse an image reference
 //! let dkref = Reference::from_str("docker://busybox")?;
-//! assert_eq!(dkref.registry(), "registry-1.docker.io");
+//! assert_eq!(dkref.registry(), "docker.io");
+//! assert_eq!(dkref.endpoint(), "registry-1.docker.io");
 //! assert_eq!(dkref.repository(), "library/busybox");
 //! assert_eq!(dkref.version(), "latest");
+//!
+//! // Format it in canonical and familiar forms
+//! assert_eq!(dkref.canonical(), "docker.io/library/busybox:latest");
+//! assert_eq!(dkref.familiar(), "busybox");
 //! #
 //! # Ok(())
 //! # };
@@ -28,31 +33,125 @@
 
 // The `docker://` schema is not officially documented, but has a reference implementation:
 // https://github.com/docker/distribution/blob/v2.6.1/reference/reference.go
+//
+// Grammar:
+//
+//  reference       := name [ ":" tag ] [ "@" digest ]
+//  name            := [domain '/'] path-component ['/' path-component]*
+//  domain          := host [':' port-number]
+//  host            := domain-name | IPv4address | '[' IPv6address ']'
+//  domain-name     := domain-component ['.' domain-component]*
+//  domain-component := /([a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9])/
+//  port-number     := /[0-9]+/
+//  path-component  := alpha-numeric [separator alpha-numeric]*
+//  alpha-numeric   := /[a-z0-9]+/
+//  separator       := /[_.]|__|[-]*/
+//  tag             := /[\w][\w.-]{0,127}/
+//  digest          := algorithm ":" encoded
+//  algorithm       := /[a-z0-9]+(?:[.+_-][a-z0-9]+)*/
+//  encoded         := /[a-fA-F0-9]{32,}/
 
+use digest::Digest;
+use errors::{self, ErrorKind};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::hash::{Hash, Hasher};
+use std::net::Ipv6Addr;
 use std::str::FromStr;
 use std::{fmt, str};
 
-/// Image version, either a tag or a digest.
-#[derive(Clone)]
+/// Registry name of Docker Hub, as used in canonical references.
+pub static DOCKER_HUB: &str = "docker.io";
+
+/// API endpoint host of Docker Hub.
+pub static DOCKER_HUB_ENDPOINT: &str = "registry-1.docker.io";
+
+/// Maximum length of a repository name, including the registry domain.
+pub const NAME_MAX_LENGTH: usize = 255;
+
+/// Maximum length of a tag.
+pub const TAG_MAX_LENGTH: usize = 128;
+
+/// Reason for an image reference to be rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The repository name is empty.
+    EmptyName,
+    /// The repository name is longer than `NAME_MAX_LENGTH`.
+    NameTooLong(usize),
+    /// The registry domain is not a valid hostname, IP address or port.
+    InvalidDomain(String),
+    /// A path component of the repository name is invalid, e.g. uppercase.
+    InvalidPathComponent(String),
+    /// The tag is empty, too long or contains invalid characters.
+    InvalidTag(String),
+    /// The digest is not in `algorithm:encoded` form, or its encoding is invalid.
+    InvalidDigest(String),
+    /// The digest algorithm is well-formed but not supported.
+    UnsupportedDigestAlgorithm(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::EmptyName => write!(f, "empty repository name"),
+            ParseError::NameTooLong(len) => write!(
+                f,
+                "repository name too long ({} > {} characters)",
+                len, NAME_MAX_LENGTH
+            ),
+            ParseError::InvalidDomain(ref d) => write!(f, "invalid registry domain {:?}", d),
+            ParseError::InvalidPathComponent(ref c) => {
+                write!(f, "invalid repository path component {:?}", c)
+            }
+            ParseError::InvalidTag(ref t) => write!(f, "invalid tag {:?}", t),
+            ParseError::InvalidDigest(ref d) => write!(f, "invalid digest {:?}", d),
+            ParseError::UnsupportedDigestAlgorithm(ref a) => {
+                write!(f, "unsupported digest algorithm {:?}", a)
+            }
+        }
+    }
+}
+
+/// Image version, either a tag, a digest, or both.
+///
+/// When both are present, the digest identifies the image and the tag
+/// is only informative.
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub enum Version {
     Tag(String),
-    Digest(String, String),
+    Digest(Digest),
+    TagDigest(String, Digest),
+}
+
+impl Version {
+    /// Tag of this version, if any.
+    pub fn tag(&self) -> Option<&str> {
+        match *self {
+            Version::Tag(ref t) | Version::TagDigest(ref t, _) => Some(t),
+            Version::Digest(..) => None,
+        }
+    }
+
+    /// Digest of this version, if any.
+    pub fn digest(&self) -> Option<&Digest> {
+        match *self {
+            Version::Digest(ref d) | Version::TagDigest(_, ref d) => Some(d),
+            Version::Tag(_) => None,
+        }
+    }
 }
 
 impl str::FromStr for Version {
     type Err = ::errors::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let v = match s.chars().nth(0) {
-            Some(':') => Version::Tag(s.trim_left_matches(':').to_string()),
-            Some('@') => {
-                let r: Vec<&str> = s.trim_left_matches('@').splitn(2, ':').collect();
-                if r.len() != 2 {
-                    bail!("wrong digest format");
-                };
-                Version::Digest(r[0].to_string(), r[1].to_string())
+        let v = match (s.chars().next(), s.find('@')) {
+            (Some(':'), Some(i)) => {
+                Version::TagDigest(parse_tag(&s[1..i])?, parse_digest(&s[i + 1..])?)
             }
-            Some(_) => bail!("unknown prefix"),
-            None => bail!("too short"),
+            (Some(':'), None) => Version::Tag(parse_tag(&s[1..])?),
+            (Some('@'), _) => Version::Digest(parse_digest(&s[1..])?),
+            (Some(_), _) => bail!("unknown prefix"),
+            (None, _) => bail!("too short"),
         };
         Ok(v)
     }
@@ -68,23 +167,45 @@ impl fmt::Debug for Version {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         let v = match *self {
             Version::Tag(ref s) => ":".to_string() + s,
-            Version::Digest(ref t, ref d) => "@".to_string() + t + ":" + d,
+            Version::Digest(ref d) => format!("@{}", d),
+            Version::TagDigest(ref s, ref d) => format!(":{}@{}", s, d),
         };
         write!(f, "{}", v)
     }
 }
 
+/// Format the tag or digest to request from a registry.
+///
+/// The digest takes precedence if both are present.
 impl fmt::Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         let v = match *self {
             Version::Tag(ref s) => s.to_string(),
-            Version::Digest(ref t, ref d) => t.to_string() + ":" + d,
+            Version::Digest(ref d) | Version::TagDigest(_, ref d) => d.to_string(),
         };
         write!(f, "{}", v)
     }
 }
 
+/// Serialize as a string prefixed by `:` for tags and `@` for digests,
+/// e.g. `:latest` or `:latest@sha256:<hex>`.
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{:?}", self))
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Version::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
 /// A registry image reference.
+///
+/// References are compared and hashed by their canonical form, regardless
+/// of how they were written, e.g. `busybox` equals `docker.io/library/busybox:latest`.
 #[derive(Clone, Debug, Default)]
 pub struct Reference {
     has_schema: bool,
@@ -95,8 +216,14 @@ pub struct Reference {
 }
 
 impl Reference {
+    /// Build a reference from its parts, defaulting to Docker Hub and the `latest` tag.
+    ///
+    /// The registry name is normalized, see `normalize_registry`.
     pub fn new(registry: Option<String>, repository: String, version: Option<Version>) -> Self {
-        let reg = registry.unwrap_or_else(|| "registry-1.docker.io".to_string());
+        let reg = match registry {
+            Some(r) => normalize_registry(&r).to_string(),
+            None => DOCKER_HUB.to_string(),
+        };
         let ver = version.unwrap_or_else(|| Version::Tag("latest".to_string()));
         Self {
             has_schema: false,
@@ -107,22 +234,80 @@ impl Reference {
         }
     }
 
+    /// Registry name, e.g. `docker.io` or `quay.io`.
+    ///
+    /// This is the name users type and under which credentials are
+    /// stored, see `endpoint` for the host serving the registry API.
     pub fn registry(&self) -> String {
         self.registry.clone()
     }
 
+    /// Host (and port) serving the registry API, e.g. `registry-1.docker.io`.
+    pub fn endpoint(&self) -> String {
+        registry_endpoint(&self.registry).to_string()
+    }
+
     pub fn repository(&self) -> String {
         self.repository.clone()
     }
 
+    /// Tag or digest to request from the registry, see `Version`.
     pub fn version(&self) -> String {
         self.version.to_string()
     }
 
+    /// Tag of this reference, if any.
+    pub fn tag(&self) -> Option<String> {
+        self.version.tag().map(str::to_string)
+    }
+
+    /// Digest of this reference, if any.
+    pub fn digest(&self) -> Option<Digest> {
+        self.version.digest().cloned()
+    }
+
+    pub(crate) fn version_spec(&self) -> &Version {
+        &self.version
+    }
+
     pub fn to_raw_string(&self) -> String {
         self.raw_input.clone()
     }
 
+    /// Fully qualified repository name, e.g. `docker.io/library/busybox`.
+    pub fn canonical_name(&self) -> String {
+        format!("{}/{}", self.registry, self.repository)
+    }
+
+    /// Fully qualified reference, e.g. `docker.io/library/busybox:latest`.
+    pub fn canonical(&self) -> String {
+        format!("{}{:?}", self.canonical_name(), self.version)
+    }
+
+    /// Shortest repository name resolving to this one, as shown by docker.
+    ///
+    /// The registry is omitted for Docker Hub, and so is the `library/`
+    /// namespace of official images (e.g. `busybox`).
+    pub fn familiar_name(&self) -> String {
+        if self.registry != DOCKER_HUB {
+            return self.canonical_name();
+        }
+        match self.repository.strip_prefix("library/") {
+            Some(name) if !name.contains('/') => name.to_string(),
+            _ => self.repository.clone(),
+        }
+    }
+
+    /// Shortest reference resolving to this one, e.g. `busybox` or `quay.io/coreos/etcd:v3.3`.
+    ///
+    /// The default `latest` tag is omitted.
+    pub fn familiar(&self) -> String {
+        match self.version {
+            Version::Tag(ref t) if t == "latest" => self.familiar_name(),
+            ref v => format!("{}{:?}", self.familiar_name(), v),
+        }
+    }
+
     //TODO(lucab): move this to a real URL type
     pub fn to_url(&self) -> String {
         format!(
@@ -132,6 +317,38 @@ impl Reference {
     }
 }
 
+impl PartialEq for Reference {
+    fn eq(&self, other: &Self) -> bool {
+        self.registry == other.registry
+            && self.repository == other.repository
+            && self.version == other.version
+    }
+}
+
+impl Eq for Reference {}
+
+impl Hash for Reference {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.registry.hash(state);
+        self.repository.hash(state);
+        self.version.hash(state);
+    }
+}
+
+/// Serialize as a string, in canonical form.
+impl Serialize for Reference {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.canonical())
+    }
+}
+
+impl<'de> Deserialize<'de> for Reference {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Reference::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
 impl fmt::Display for Reference {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         write!(f, "{}/{}{:?}", self.registry, self.repository, self.version)
@@ -145,41 +362,183 @@ impl str::FromStr for Reference {
     }
 }
 
-fn parse_url(s: &str) -> Result<Reference, ::errors::Error> {
-    // TODO(lucab): move to nom
-    let mut rest = s;
-    let has_schema = rest.starts_with("docker://");
-    if has_schema {
-        rest = s.trim_left_matches("docker://");
+fn parse_url(s: &str) -> errors::Result<Reference> {
+    let has_schema = s.starts_with("docker://");
+    let rest = if has_schema {
+        &s["docker://".len()..]
+    } else {
+        s
     };
-    let (rest, version) = match (rest.rfind('@'), rest.rfind(':')) {
-        (Some(i), _) | (None, Some(i)) => {
-            let s = rest.split_at(i);
-            (s.0, Version::from_str(s.1)?)
-        }
-        (None, None) => (rest, Version::default()),
+
+    let (rest, digest) = match rest.find('@') {
+        Some(i) => (&rest[..i], Some(parse_digest(&rest[i + 1..])?)),
+        None => (rest, None),
     };
-    if rest.is_empty() {
-        bail!("name too short");
-    }
-    let mut reg = "registry-1.docker.io";
-    let split: Vec<&str> = rest.rsplitn(3, '/').collect();
-    let repository = match split.len() {
-        1 => "library/".to_string() + rest,
-        2 => rest.to_string(),
-        _ => {
-            reg = split[2];
-            split[1].to_string() + "/" + split[0]
+    let last_slash = rest.rfind('/').map_or(0, |i| i + 1);
+    let (name, tag) = match rest[last_slash..].find(':') {
+        Some(i) => {
+            let i = last_slash + i;
+            (&rest[..i], Some(parse_tag(&rest[i + 1..])?))
         }
+        None => (rest, None),
     };
-    if repository.len() > 127 {
-        bail!("name too long");
+
+    if name.is_empty() {
+        bail!(ErrorKind::InvalidReference(ParseError::EmptyName));
     }
+    if name.len() > NAME_MAX_LENGTH {
+        bail!(ErrorKind::InvalidReference(ParseError::NameTooLong(
+            name.len()
+        )));
+    }
+    let (domain, path) = split_domain(name);
+    if let Some(d) = domain {
+        validate_domain(d)?;
+    }
+    for component in path.split('/') {
+        validate_path_component(component)?;
+    }
+
+    let registry = normalize_registry(domain.unwrap_or(DOCKER_HUB)).to_string();
+    // Official images on Docker Hub live under the `library/` namespace.
+    let repository = if registry == DOCKER_HUB && !path.contains('/') {
+        "library/".to_string() + path
+    } else {
+        path.to_string()
+    };
+    let version = match (digest, tag) {
+        (Some(d), Some(t)) => Version::TagDigest(t, d),
+        (Some(d), None) => Version::Digest(d),
+        (None, Some(t)) => Version::Tag(t),
+        (None, None) => Version::default(),
+    };
     Ok(Reference {
         has_schema,
         raw_input: s.to_string(),
-        registry: reg.to_string(),
+        registry,
         repository,
         version,
     })
 }
+
+/// Normalize a registry name, folding Docker Hub aliases into `docker.io`.
+pub fn normalize_registry(registry: &str) -> &str {
+    match registry {
+        "index.docker.io" | "registry-1.docker.io" => DOCKER_HUB,
+        other => other,
+    }
+}
+
+/// Return the host serving the API for a registry name.
+///
+/// This is only different from the registry name for Docker Hub.
+pub fn registry_endpoint(registry: &str) -> &str {
+    match normalize_registry(registry) {
+        "docker.io" => DOCKER_HUB_ENDPOINT,
+        other => other,
+    }
+}
+
+/// Split a name into its optional registry domain and its path.
+///
+/// The first component is a domain if it looks like a hostname
+/// (i.e. it contains a dot or a port, or it is `localhost`) or if it
+/// contains uppercase characters, which are invalid in paths.
+fn split_domain(name: &str) -> (Option<&str>, &str) {
+    let i = match name.find('/') {
+        Some(i) => i,
+        None => return (None, name),
+    };
+    let first = &name[..i];
+    let is_domain = first.contains('.')
+        || first.contains(':')
+        || first == "localhost"
+        || first.chars().any(|c| c.is_ascii_uppercase());
+    if is_domain {
+        (Some(first), &name[i + 1..])
+    } else {
+        (None, name)
+    }
+}
+
+fn validate_domain(domain: &str) -> errors::Result<()> {
+    let invalid = || ErrorKind::InvalidReference(ParseError::InvalidDomain(domain.to_string()));
+    let (host_ok, port) = if domain.starts_with('[') {
+        let end = domain.find(']').ok_or_else(invalid)?;
+        let ok = Ipv6Addr::from_str(&domain[1..end]).is_ok();
+        (ok, &domain[end + 1..])
+    } else {
+        let end = domain.find(':').unwrap_or(domain.len());
+        let ok = domain[..end].split('.').all(is_domain_component);
+        (ok, &domain[end..])
+    };
+    let port_ok = match port.chars().next() {
+        Some(':') => port.len() > 1 && port[1..].bytes().all(|b| b.is_ascii_digit()),
+        Some(_) => false,
+        None => true,
+    };
+    if !host_ok || !port_ok {
+        bail!(invalid());
+    }
+    Ok(())
+}
+
+fn is_domain_component(c: &str) -> bool {
+    !c.is_empty()
+        && !c.starts_with('-')
+        && !c.ends_with('-')
+        && c.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+fn validate_path_component(component: &str) -> errors::Result<()> {
+    let is_alnum = |b: u8| b.is_ascii_lowercase() || b.is_ascii_digit();
+    let bytes = component.as_bytes();
+    let mut valid = !bytes.is_empty() && is_alnum(bytes[0]) && is_alnum(bytes[bytes.len() - 1]);
+    // Separators are a single `.` or `_`, a double `__`, or any number of `-`.
+    let mut separator = String::new();
+    for &b in bytes {
+        if is_alnum(b) {
+            let sep_ok = match separator.as_str() {
+                "" | "." | "_" | "__" => true,
+                sep => sep.bytes().all(|c| c == b'-'),
+            };
+            valid &= sep_ok;
+            separator.clear();
+        } else if b == b'.' || b == b'_' || b == b'-' {
+            separator.push(b as char);
+        } else {
+            valid = false;
+        }
+    }
+    if !valid {
+        bail!(ErrorKind::InvalidReference(
+            ParseError::InvalidPathComponent(component.to_string())
+        ));
+    }
+    Ok(())
+}
+
+fn parse_tag(tag: &str) -> errors::Result<String> {
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    let valid = match tag.as_bytes().split_first() {
+        Some((&first, rest)) => {
+            tag.len() <= TAG_MAX_LENGTH
+                && is_word(first)
+                && rest.iter().all(|&b| is_word(b) || b == b'.' || b == b'-')
+        }
+        None => false,
+    };
+    if !valid {
+        bail!(ErrorKind::InvalidReference(ParseError::InvalidTag(
+            tag.to_string()
+        )));
+    }
+    Ok(tag.to_string())
+}
+
+fn parse_digest(digest: &str) -> errors::Result<Digest> {
+    match ::digest::parse(digest) {
+        Ok(d) => Ok(d),
+        Err(e) => bail!(ErrorKind::InvalidReference(e)),
+    }
+}
@@ -0,0 +1,475 @@
+//! Registries configuration.
+//!
+//! This module provides support for the `registries.conf` file (version 2)
+//! used by containers tools, typically under `/etc/containers/registries.conf`.
+//! It controls how short image names are resolved, and how references are
+//! rewritten to the actual locations to pull from.
+//!
+//! ## Example
+//!
+//! ```rust
+//! # extern crate dkregistry;
+//! # fn main() {
+//! # fn run() -> dkregistry::errors::Result<()> {
+//! #
+//! use dkregistry::registries::RegistriesConf;
+//!
+//! let conf = RegistriesConf::from_reader(r#"
+//!     unqualified-search-registries = ["registry.fedoraproject.org"]
+//! "#.as_bytes())?;
+//! let sources = conf.resolve("fedora:39")?;
+//! assert_eq!(sources[0].reference.registry(), "registry.fedoraproject.org");
+//! assert_eq!(sources[0].reference.repository(), "fedora");
+//! assert_eq!(sources[0].reference.version(), "39");
+//! #
+//! # Ok(())
+//! # };
+//! # run().unwrap();
+//! # }
+//! ```
+
+use dirs;
+use errors::*;
+use reference::{normalize_registry, Reference, Version, DOCKER_HUB};
+use std::collections::HashMap;
+use std::io::Read;
+use std::str::FromStr;
+use std::{env, fs, io, path};
+use toml;
+
+/// Default location of the system-wide registries configuration.
+pub static SYSTEM_REGISTRIES_CONF: &str = "/etc/containers/registries.conf";
+
+/// Policy for short names which are not covered by an alias.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShortNameMode {
+    /// Short names resolving to multiple candidates are an error, as there
+    /// is no user to disambiguate them.
+    Enforcing,
+    /// All candidates are tried in order. This is the default.
+    Permissive,
+    /// Aliases are ignored, and all candidates are tried in order.
+    Disabled,
+}
+
+/// A mirror of a registry namespace.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct Mirror {
+    /// Location of the mirror, replacing the prefix of the registry entry,
+    /// or the matching host for a wildcard prefix.
+    pub location: String,
+    /// Whether the mirror may be reached over plain HTTP or without TLS verification.
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+/// A `[[registry]]` entry, applying to all references matching its prefix.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Registry {
+    /// Prefix of the references this entry applies to.
+    ///
+    /// This is either a `host[:port][/path]` namespace or a `*.domain`
+    /// wildcard. It defaults to `location` when empty.
+    #[serde(default)]
+    pub prefix: String,
+    /// Location replacing the prefix in matching references.
+    #[serde(default)]
+    pub location: String,
+    /// Whether the location may be reached over plain HTTP or without TLS verification.
+    #[serde(default)]
+    pub insecure: bool,
+    /// Whether pulling from matching references is forbidden.
+    #[serde(default)]
+    pub blocked: bool,
+    /// Mirrors to try, in order, before the location itself.
+    #[serde(rename = "mirror", default)]
+    pub mirrors: Vec<Mirror>,
+    /// Only use mirrors for references by digest.
+    #[serde(default)]
+    pub mirror_by_digest_only: bool,
+}
+
+impl Registry {
+    /// Whether `name` (a fully qualified name, optionally with tag or digest)
+    /// falls under the prefix of this entry.
+    pub fn matches(&self, name: &str) -> bool {
+        let prefix = self.prefix();
+        if prefix.starts_with("*.") {
+            let host = name.split('/').next().unwrap_or("");
+            let host = host.split([':', '@']).next().unwrap_or("");
+            return host.ends_with(&prefix[1..]);
+        }
+        if !name.starts_with(prefix) {
+            return false;
+        }
+        match name[prefix.len()..].chars().next() {
+            None | Some('/') | Some(':') | Some('@') => true,
+            Some(_) => false,
+        }
+    }
+
+    /// Rewrite a matching `name`, replacing the prefix with `location`.
+    pub fn rewrite(&self, name: &str) -> String {
+        rewrite_with(self.prefix(), &self.location, name)
+    }
+
+    fn prefix(&self) -> &str {
+        if self.prefix.is_empty() {
+            &self.location
+        } else {
+            &self.prefix
+        }
+    }
+}
+
+/// A location to pull an image from.
+#[derive(Clone, Debug)]
+pub struct PullSource {
+    /// Reference to pull from.
+    pub reference: Reference,
+    /// Whether the registry may be reached over plain HTTP or without TLS verification.
+    pub insecure: bool,
+}
+
+/// Parsed `registries.conf` configuration, in version 2 format.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RegistriesConf {
+    /// Registries to search, in order, for short names without an alias.
+    ///
+    /// An empty list is kept apart from an unset one, as it overrides the
+    /// list of previous files when merging.
+    #[serde(default)]
+    pub unqualified_search_registries: Option<Vec<String>>,
+    /// Policy for short names which are not covered by an alias.
+    #[serde(default)]
+    pub short_name_mode: Option<ShortNameMode>,
+    /// Per-namespace registry entries.
+    #[serde(rename = "registry", default)]
+    pub registries: Vec<Registry>,
+    /// Short-name aliases, mapping short names to fully qualified names.
+    ///
+    /// Neither side may have a tag or digest.
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+}
+
+impl RegistriesConf {
+    /// Parse a configuration from a TOML reader.
+    pub fn from_reader<T: Read>(mut reader: T) -> Result<Self> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        let value: toml::Value = toml::from_str(&content)?;
+        if value.get("registries").is_some() {
+            bail!("registries.conf version 1 format is not supported");
+        }
+        let conf: Self = value.try_into()?;
+        for reg in &conf.registries {
+            validate_registry(reg)?;
+        }
+        for (name, value) in &conf.aliases {
+            validate_alias(name, value)?;
+        }
+        Ok(conf)
+    }
+
+    /// Load a configuration from `path`, followed by drop-in files.
+    ///
+    /// Drop-in files are the `*.conf` files in the `<path>.d` directory,
+    /// applied in lexical order on top of the main file. Missing files
+    /// and directories are skipped.
+    pub fn load(path: &path::Path) -> Result<Self> {
+        let mut conf = match fs::File::open(path) {
+            Ok(fp) => Self::from_reader(io::BufReader::new(fp))
+                .chain_err(|| format!("failed to parse {:?}", path))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut dropins_dir = path.as_os_str().to_owned();
+        dropins_dir.push(".d");
+        let mut dropins = match fs::read_dir(&dropins_dir) {
+            Ok(entries) => entries
+                .map(|e| e.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        dropins.retain(|p| p.extension().is_some_and(|ext| ext == "conf"));
+        dropins.sort();
+        for p in dropins {
+            let fp = fs::File::open(&p)?;
+            let dropin = Self::from_reader(io::BufReader::new(fp))
+                .chain_err(|| format!("failed to parse {:?}", p))?;
+            trace!("Applying registries drop-in {:?}", p);
+            conf.merge(dropin);
+        }
+        Ok(conf)
+    }
+
+    /// Load the configuration from its standard location.
+    ///
+    /// This is `$CONTAINERS_REGISTRIES_CONF` if set, otherwise the
+    /// per-user `~/.config/containers/registries.conf` if it exists,
+    /// otherwise `SYSTEM_REGISTRIES_CONF`.
+    pub fn load_default() -> Result<Self> {
+        if let Some(p) = env::var_os("CONTAINERS_REGISTRIES_CONF").filter(|v| !v.is_empty()) {
+            return Self::load(path::Path::new(&p));
+        }
+        let user = env::var_os("XDG_CONFIG_HOME")
+            .filter(|v| !v.is_empty())
+            .map(path::PathBuf::from)
+            .or_else(|| dirs::home_dir().map(|h| h.join(".config")))
+            .map(|d| d.join("containers/registries.conf"));
+        match user {
+            Some(ref p) if p.exists() => Self::load(p),
+            _ => Self::load(path::Path::new(SYSTEM_REGISTRIES_CONF)),
+        }
+    }
+
+    /// Apply another configuration (e.g. a drop-in) on top of this one.
+    ///
+    /// Search registries and a short-name mode replace the current ones
+    /// when set, even to an empty list. Registry entries replace existing
+    /// entries with the same prefix, and aliases are added or replaced one
+    /// by one.
+    pub fn merge(&mut self, other: RegistriesConf) {
+        if other.unqualified_search_registries.is_some() {
+            self.unqualified_search_registries = other.unqualified_search_registries;
+        }
+        if other.short_name_mode.is_some() {
+            self.short_name_mode = other.short_name_mode;
+        }
+        for reg in other.registries {
+            match self
+                .registries
+                .iter_mut()
+                .find(|r| r.prefix() == reg.prefix())
+            {
+                Some(r) => *r = reg,
+                None => self.registries.push(reg),
+            }
+        }
+        self.aliases.extend(other.aliases);
+    }
+
+    /// Find the registry entry with the longest prefix matching `name`.
+    pub fn find_registry(&self, name: &str) -> Option<&Registry> {
+        let name = qualified_name(name);
+        self.registries
+            .iter()
+            .filter(|r| r.matches(&name))
+            .max_by_key(|r| r.prefix().len())
+    }
+
+    /// Resolve an image name into candidate references.
+    ///
+    /// Fully qualified names resolve to themselves. Short names (without
+    /// a registry, e.g. `fedora:39`) resolve to their alias if any,
+    /// otherwise to the same name on each of the unqualified search
+    /// registries, in order.
+    ///
+    /// Aliases are ignored in `Disabled` short-name mode, and short names
+    /// with several candidates are an error in `Enforcing` mode.
+    pub fn resolve_short_name(&self, name: &str) -> Result<Vec<Reference>> {
+        let (base, version) = split_version(name)?;
+        if base.is_empty() {
+            bail!("empty image name");
+        }
+        if !is_short_name(base) {
+            return Ok(vec![to_reference(base, version)]);
+        }
+        let mode = self.short_name_mode.unwrap_or(ShortNameMode::Permissive);
+        if let Some(alias) = self.aliases.get(base) {
+            if mode != ShortNameMode::Disabled {
+                trace!("Short name {} is an alias for {}", base, alias);
+                return Ok(vec![to_reference(alias, version)]);
+            }
+        }
+        let registries = self
+            .unqualified_search_registries
+            .as_deref()
+            .unwrap_or_default();
+        if registries.is_empty() {
+            bail!(
+                "short name {} cannot be resolved: no unqualified-search registries",
+                base
+            );
+        }
+        if mode == ShortNameMode::Enforcing && registries.len() > 1 {
+            bail!(
+                "short name {} is ambiguous in enforcing short-name mode: use a fully qualified name",
+                base
+            );
+        }
+        let candidates = registries
+            .iter()
+            .map(|reg| to_reference(&format!("{}/{}", reg, base), version.clone()))
+            .collect();
+        Ok(candidates)
+    }
+
+    /// Get the locations to pull a reference from, in order.
+    ///
+    /// Mirrors of the matching registry entry come first, followed by
+    /// the (possibly rewritten) location itself. Pulling from a blocked
+    /// registry is an error.
+    pub fn pull_sources(&self, reference: &Reference) -> Result<Vec<PullSource>> {
+        let name = reference.canonical_name();
+        let reg = match self.find_registry(&name) {
+            Some(r) => r,
+            None => {
+                return Ok(vec![PullSource {
+                    reference: reference.clone(),
+                    insecure: false,
+                }])
+            }
+        };
+        if reg.blocked {
+            bail!("registry for {} is blocked in registries.conf", name);
+        }
+
+        let version = reference.version_spec().clone();
+        let by_digest = version.digest().is_some();
+        let mut sources = vec![];
+        if by_digest || !reg.mirror_by_digest_only {
+            for m in &reg.mirrors {
+                let location = rewrite_with(reg.prefix(), &m.location, &name);
+                sources.push(PullSource {
+                    reference: to_reference(&location, Some(version.clone())),
+                    insecure: m.insecure,
+                });
+            }
+        }
+        sources.push(PullSource {
+            reference: to_reference(&reg.rewrite(&name), Some(version)),
+            insecure: reg.insecure,
+        });
+        Ok(sources)
+    }
+
+    /// Resolve an image name into the locations to pull it from, in order.
+    ///
+    /// This combines `resolve_short_name` and `pull_sources`, skipping
+    /// candidates on blocked registries.
+    pub fn resolve(&self, name: &str) -> Result<Vec<PullSource>> {
+        let mut sources = vec![];
+        let mut last_err = None;
+        for candidate in self.resolve_short_name(name)? {
+            match self.pull_sources(&candidate) {
+                Ok(s) => sources.extend(s),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        match (sources.is_empty(), last_err) {
+            (true, Some(e)) => Err(e),
+            _ => Ok(sources),
+        }
+    }
+}
+
+fn validate_registry(reg: &Registry) -> Result<()> {
+    let prefix = reg.prefix();
+    if prefix.is_empty() {
+        bail!("registry entry without prefix nor location");
+    }
+    if prefix.contains('*') {
+        let domain = prefix.trim_start_matches("*.");
+        if !prefix.starts_with("*.") || domain.contains(['*', '/']) {
+            bail!("invalid wildcard prefix {}, expected *.domain", prefix);
+        }
+    }
+    if prefix.starts_with("*.") && !reg.location.is_empty() {
+        bail!(
+            "registry entry with wildcard prefix {} cannot set a location",
+            prefix
+        );
+    }
+    Ok(())
+}
+
+/// Check that an alias maps a short name to a fully qualified name.
+fn validate_alias(name: &str, value: &str) -> Result<()> {
+    let (base, version) = split_version(name)?;
+    if base.is_empty() || !is_short_name(base) || version.is_some() {
+        bail!(
+            "invalid alias {}: expected a short name without tag or digest",
+            name
+        );
+    }
+    let (base, version) = split_version(value)?;
+    let has_repository = base.find('/').is_some_and(|i| i + 1 < base.len());
+    if is_short_name(base) || !has_repository || version.is_some() {
+        bail!(
+            "invalid alias {} = {}: expected a fully qualified name without tag or digest",
+            name,
+            value
+        );
+    }
+    Ok(())
+}
+
+/// Replace `prefix` with `location` at the beginning of `name`.
+///
+/// For wildcard prefixes, the matching host (without port) is replaced.
+/// Empty locations leave `name` unchanged.
+fn rewrite_with(prefix: &str, location: &str, name: &str) -> String {
+    let matched = if prefix.starts_with("*.") {
+        name.find([':', '/', '@']).unwrap_or(name.len())
+    } else if name.starts_with(prefix) {
+        prefix.len()
+    } else {
+        return name.to_string();
+    };
+    if location.is_empty() {
+        return name.to_string();
+    }
+    format!("{}{}", location, &name[matched..])
+}
+
+/// Whether `name` lacks a registry domain, e.g. `fedora` or `library/busybox`.
+fn is_short_name(name: &str) -> bool {
+    match name.find('/') {
+        Some(i) => {
+            let domain = &name[..i];
+            !(domain.contains('.') || domain.contains(':') || domain == "localhost")
+        }
+        None => true,
+    }
+}
+
+/// Split the tag or digest off an image name.
+fn split_version(name: &str) -> Result<(&str, Option<Version>)> {
+    if let Some(i) = name.find('@') {
+        return Ok((&name[..i], Some(Version::from_str(&name[i..])?)));
+    }
+    let last_slash = name.rfind('/').unwrap_or(0);
+    match name.rfind(':') {
+        Some(i) if i > last_slash => Ok((&name[..i], Some(Version::from_str(&name[i..])?))),
+        _ => Ok((name, None)),
+    }
+}
+
+/// Normalize the docker.io registry aliases in a fully qualified name.
+fn qualified_name(name: &str) -> String {
+    match name.find('/') {
+        Some(i) => format!("{}{}", normalize_registry(&name[..i]), &name[i..]),
+        None => name.to_string(),
+    }
+}
+
+/// Build a reference from a fully qualified name, without tag or digest.
+fn to_reference(name: &str, version: Option<Version>) -> Reference {
+    let (registry, repository) = match name.find('/') {
+        Some(i) => (normalize_registry(&name[..i]), &name[i + 1..]),
+        None => (name, ""),
+    };
+    let repository = if registry == DOCKER_HUB && !repository.contains('/') {
+        format!("library/{}", repository)
+    } else {
+        repository.to_string()
+    };
+    Reference::new(Some(registry.to_string()), repository, version)
+}
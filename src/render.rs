@@ -4,52 +4,1085 @@
 // https://github.com/moby/moby/blob/v17.05.0-ce/image/spec/v1.md
 
 use errors::*;
+use futures::{self, Future, Sink, Stream};
 use libflate::gzip;
-use std::{fs, path};
+use mediatypes::MediaTypes;
+use std::collections::{btree_map, BTreeMap, HashMap, HashSet};
+use std::io::Read;
+use std::ops::Bound;
+use std::{fs, io, path, thread};
 use tar;
+use zstd;
+
+/// Marker for a directory whose contents in lower layers are hidden.
+pub(crate) static OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Prefix of whiteout markers for single entries.
+pub(crate) static WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Prefix of PAX extension keys holding extended attributes.
+static PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// Maximum number of links followed when looking up a file.
+const MAX_LINK_HOPS: usize = 16;
+
+/// Number of chunks buffered between a layer stream and its extraction.
+const STREAM_BUFFER: usize = 16;
+
+/// Magic bytes at the start of gzip streams.
+static GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Magic bytes at the start of zstd frames.
+static ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Convenience alias for future layer extraction.
+pub type FutureUnpack = Box<futures::Future<Item = (), Error = Error>>;
+
+/// Compression of a layer tar archive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// Uncompressed tar.
+    None,
+    /// Gzip-compressed tar.
+    Gzip,
+    /// Zstd-compressed tar.
+    Zstd,
+}
+
+impl Compression {
+    /// Compression of layers with the given media type, if it is a layer one.
+    pub fn from_media_type(media_type: &MediaTypes) -> Option<Self> {
+        match *media_type {
+            MediaTypes::OciImageLayerTar => Some(Compression::None),
+            MediaTypes::ImageLayerTgz | MediaTypes::OciImageLayerTgz => Some(Compression::Gzip),
+            MediaTypes::OciImageLayerTzst => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Detect compression from the first bytes of a layer.
+    ///
+    /// Layers without a known magic number are assumed to be uncompressed.
+    pub fn sniff(header: &[u8]) -> Self {
+        if header.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if header.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Ownership policy for unpacked entries.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Ownership {
+    /// Leave entries owned by the current user.
+    #[default]
+    Ignore,
+    /// Keep owners from layers, which usually requires running as root.
+    Preserve,
+    /// Remap owners from layers to host IDs, e.g. for user namespaces.
+    Map {
+        /// Mappings for user IDs.
+        uids: Vec<IdMapping>,
+        /// Mappings for group IDs.
+        gids: Vec<IdMapping>,
+    },
+}
+
+/// A range of IDs mapped to host IDs, as in `/etc/subuid`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IdMapping {
+    /// First ID in layers.
+    pub container_id: u64,
+    /// First host ID that `container_id` maps to.
+    pub host_id: u64,
+    /// Number of consecutive IDs mapped.
+    pub size: u64,
+}
+
+impl IdMapping {
+    /// Map an ID from layers, if within this range.
+    pub fn map(&self, id: u64) -> Option<u64> {
+        if id >= self.container_id && id - self.container_id < self.size {
+            Some(self.host_id + (id - self.container_id))
+        } else {
+            None
+        }
+    }
+}
+
+/// Map an ID through a set of mappings.
+fn map_id(mappings: &[IdMapping], id: u64, kind: &str) -> Result<u64> {
+    match mappings.iter().filter_map(|m| m.map(id)).next() {
+        Some(host_id) => Ok(host_id),
+        None => bail!("no mapping for {} {}", kind, id),
+    }
+}
+
+/// Layer unpacker, with options for how entries are written.
+///
+/// The default unpacker leaves entries owned by the current user, and
+/// keeps permissions and all extended attributes from layers.
+#[derive(Clone, Debug, Default)]
+pub struct Unpacker {
+    ownership: Ownership,
+    skip_devices: bool,
+    skip_xattrs: Vec<String>,
+}
+
+impl Unpacker {
+    /// Set how entries ownership is handled.
+    pub fn ownership(mut self, ownership: Ownership) -> Self {
+        self.ownership = ownership;
+        self
+    }
+
+    /// Set whether to skip block and character devices, which can only be
+    /// created by privileged users.
+    pub fn skip_devices(mut self, skip: bool) -> Self {
+        self.skip_devices = skip;
+        self
+    }
+
+    /// Set prefixes of extended attributes to skip, e.g. `security.`.
+    pub fn skip_xattrs(mut self, prefixes: Vec<String>) -> Self {
+        self.skip_xattrs = prefixes;
+        self
+    }
+
+    /// Unpack an ordered list of layers to a target directory.
+    ///
+    /// Layers must be provided as tar archives, either uncompressed or gzip
+    /// or zstd compressed, with lower layers coming first. Target directory
+    /// must be an existing absolute path.
+    ///
+    /// See `unpack_layer` for how each layer is applied.
+    pub fn unpack(&self, layers: &[Vec<u8>], target_dir: &path::Path) -> Result<()> {
+        check_target(target_dir)?;
+        for l in layers {
+            self.unpack_layer(l.as_slice(), target_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Apply a single layer on top of a target directory, in a single pass.
+    ///
+    /// The layer must be a tar archive, and is read incrementally. Its
+    /// compression is detected from its first bytes. Target directory must
+    /// be an existing absolute path, containing the lower layers.
+    ///
+    /// Whiteouts are applied as per the OCI image layer specification, as
+    /// they are encountered: a whiteout hides the lower-layer entry it names,
+    /// and an opaque whiteout hides all lower-layer contents of its directory.
+    /// Entries from the same layer are never hidden.
+    ///
+    /// Layers are treated as untrusted: entries with `..` components, or
+    /// reaching outside of the target directory through symlinks, are
+    /// rejected with an error.
+    pub fn unpack_layer<R: io::Read>(&self, layer: R, target_dir: &path::Path) -> Result<()> {
+        self.unpack_layer_as(layer, None, target_dir)
+    }
+
+    /// Apply a single layer with a known media type on top of a target directory.
+    ///
+    /// This behaves as `unpack_layer`, except that the layer is decompressed
+    /// according to its media type. Compression is detected from the first
+    /// bytes of the layer when the media type is unknown, or not a layer one.
+    pub fn unpack_layer_as<R: io::Read>(
+        &self,
+        layer: R,
+        media_type: Option<&MediaTypes>,
+        target_dir: &path::Path,
+    ) -> Result<()> {
+        check_target(target_dir)?;
+        let root = target_dir.canonicalize()?;
+        // Paths written by this layer, with their ancestors.
+        let mut written = HashSet::new();
+
+        let compression = media_type.and_then(Compression::from_media_type);
+        let mut archive = tar::Archive::new(decompress(layer, compression)?);
+        archive.set_preserve_permissions(true);
+        // Extended attributes are filtered, then set after unpacking.
+        archive.set_unpack_xattrs(false);
+        for entry in archive.entries()? {
+            let mut file = entry?;
+            let path = confined_path(&root, &file.path()?)?;
+            let name = match path.file_name() {
+                Some(fname) => fname.to_string_lossy().into_owned(),
+                None => continue,
+            };
+
+            if name == OPAQUE_WHITEOUT {
+                if let Some(parent) = path.parent() {
+                    clear_dir(parent, &written)?;
+                }
+            } else if let Some(real_name) = name.strip_prefix(WHITEOUT_PREFIX) {
+                let real_path = path.with_file_name(real_name);
+                if !real_name.is_empty() && !written.contains(&real_path) {
+                    remove_path(&real_path)?;
+                }
+            } else {
+                let entry_type = file.header().entry_type();
+                if self.skip_devices
+                    && (entry_type.is_block_special() || entry_type.is_character_special())
+                {
+                    trace!("Skipping device {:?}", path);
+                    continue;
+                }
+                // Replace lower-layer entries of another kind, and never
+                // descend into a lower-layer symlink.
+                match fs::symlink_metadata(&path) {
+                    Ok(ref m) if m.is_dir() != entry_type.is_dir() => remove_path(&path)?,
+                    _ => {}
+                };
+                let xattrs = self.xattrs(&mut file)?;
+                file.unpack_in(&root)?;
+                for p in path.ancestors().take_while(|p| *p != root) {
+                    written.insert(p.to_path_buf());
+                }
+                for (key, value) in xattrs {
+                    set_xattr(&path, &key, &value)?;
+                }
+                self.set_owner(&path, file.header())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply a single layer on top of a target directory, from a stream of chunks.
+    ///
+    /// This behaves as `unpack_layer`, e.g. for piping a blob download
+    /// directly into extraction. Chunks are consumed as they are extracted,
+    /// which happens on a dedicated thread so that the event loop is never
+    /// blocked on filesystem operations.
+    pub fn unpack_layer_stream<S>(&self, layer: S, target_dir: &path::Path) -> FutureUnpack
+    where
+        S: Stream<Error = Error> + 'static,
+        S::Item: AsRef<[u8]>,
+    {
+        let (tx, rx) = futures::sync::mpsc::channel::<Vec<u8>>(STREAM_BUFFER);
+        let (done_tx, done_rx) = futures::sync::oneshot::channel();
+        let target_dir = target_dir.to_path_buf();
+        let unpacker = self.clone();
+        thread::spawn(move || {
+            let mut reader = ChunkReader {
+                chunks: rx.wait(),
+                buf: vec![],
+                pos: 0,
+            };
+            // Drain trailing data, so that the whole stream is checked for errors.
+            let res = unpacker
+                .unpack_layer(&mut reader, &target_dir)
+                .and_then(|_| Ok(io::copy(&mut reader, &mut io::sink()).map(|_| ())?));
+            let _ = done_tx.send(res);
+        });
+
+        let tx = tx.sink_map_err(|_| Error::from("layer extraction stopped early"));
+        let fres = layer
+            .map(|chunk| chunk.as_ref().to_vec())
+            .forward(tx)
+            // Drop the sink, signalling the end of the layer.
+            .map(|_| ())
+            .then(move |fed| {
+                done_rx.then(move |done| match done {
+                    // Extraction errors are more relevant than feeding ones.
+                    Ok(Err(e)) => Err(e),
+                    Ok(Ok(())) => fed,
+                    Err(_) => Err("layer extraction thread panicked".into()),
+                })
+            });
+        Box::new(fres)
+    }
+
+    /// Extended attributes of an entry, without skipped ones.
+    fn xattrs<R: io::Read>(&self, file: &mut tar::Entry<R>) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut xattrs = vec![];
+        let exts = match file.pax_extensions()? {
+            Some(exts) => exts,
+            None => return Ok(xattrs),
+        };
+        for ext in exts {
+            let ext = ext?;
+            let key = ext
+                .key()
+                .ok()
+                .and_then(|k| k.strip_prefix(PAX_XATTR_PREFIX));
+            let key = match key {
+                Some(key) => key,
+                None => continue,
+            };
+            if self.skip_xattrs.iter().any(|p| key.starts_with(p.as_str())) {
+                trace!("Skipping xattr {}", key);
+                continue;
+            }
+            xattrs.push((key.to_string(), ext.value_bytes().to_vec()));
+        }
+        Ok(xattrs)
+    }
+
+    /// Apply the ownership policy to an unpacked entry.
+    fn set_owner(&self, path: &path::Path, header: &tar::Header) -> Result<()> {
+        let (uid, gid) = match self.ownership {
+            Ownership::Ignore => return Ok(()),
+            Ownership::Preserve => (header.uid()?, header.gid()?),
+            Ownership::Map { ref uids, ref gids } => (
+                map_id(uids, header.uid()?, "uid")?,
+                map_id(gids, header.gid()?, "gid")?,
+            ),
+        };
+        let mode = if header.entry_type().is_symlink() {
+            None
+        } else {
+            Some(header.mode()?)
+        };
+        chown(path, uid, gid, mode)
+    }
+}
 
 /// Unpack an ordered list of layers to a target directory.
 ///
-/// Layers must be provided as gzip-compressed tar archives, with lower layers
-/// coming first. Target directory must be an existing absolute path.
+/// See `Unpacker::unpack`, with default options.
 pub fn unpack(layers: &[Vec<u8>], target_dir: &path::Path) -> Result<()> {
-    if !target_dir.is_absolute() || !target_dir.exists() || !target_dir.is_dir() {
-        bail!("wrong target path");
+    Unpacker::default().unpack(layers, target_dir)
+}
+
+/// Apply a single layer on top of a target directory, in a single pass.
+///
+/// See `Unpacker::unpack_layer`, with default options.
+pub fn unpack_layer<R: io::Read>(layer: R, target_dir: &path::Path) -> Result<()> {
+    Unpacker::default().unpack_layer(layer, target_dir)
+}
+
+/// Apply a single layer with a known media type on top of a target directory.
+///
+/// See `Unpacker::unpack_layer_as`, with default options.
+pub fn unpack_layer_as<R: io::Read>(
+    layer: R,
+    media_type: Option<&MediaTypes>,
+    target_dir: &path::Path,
+) -> Result<()> {
+    Unpacker::default().unpack_layer_as(layer, media_type, target_dir)
+}
+
+/// Apply a single layer on top of a target directory, from a stream of chunks.
+///
+/// See `Unpacker::unpack_layer_stream`, with default options.
+pub fn unpack_layer_stream<S>(layer: S, target_dir: &path::Path) -> FutureUnpack
+where
+    S: Stream<Error = Error> + 'static,
+    S::Item: AsRef<[u8]>,
+{
+    Unpacker::default().unpack_layer_stream(layer, target_dir)
+}
+
+/// Flatten an ordered list of layers into a single tar archive.
+///
+/// Layers must be provided as tar archives, either uncompressed or gzip or
+/// zstd compressed, with lower layers coming first. The result is the
+/// root filesystem that unpacking all layers would produce, written as an
+/// uncompressed tar archive to `out`, which is returned once complete.
+///
+/// Whiteouts and opaque directories are applied in memory: layers are read
+/// once each, from the top one, and only the entries which are not hidden
+/// by upper layers are written. Entries from upper layers come first.
+///
+/// Hard links whose target is hidden by an upper layer are written as
+/// regular files, with the content of the target, as unpacking would leave
+/// them. The hidden files of a layer are therefore kept in memory while it
+/// is read. Hard links to other hidden entries are dropped.
+pub fn flatten<I, R, W>(layers: I, out: W) -> Result<W>
+where
+    I: IntoIterator<Item = R>,
+    I::IntoIter: DoubleEndedIterator,
+    R: io::Read,
+    W: io::Write,
+{
+    let mut builder = tar::Builder::new(out);
+    // Paths provided by upper layers, which hide lower-layer entries.
+    let mut seen = HashSet::new();
+    // Paths whose lower-layer descendants are hidden.
+    let mut hidden = HashSet::new();
+
+    for layer in layers.into_iter().rev() {
+        // Whiteouts only apply to lower layers, so collect them separately.
+        let (mut layer_seen, mut layer_hidden) = (vec![], vec![]);
+        // Hidden files of this layer, for hard links to them.
+        let mut hidden_files = HashMap::new();
+        let mut archive = tar::Archive::new(decompress(layer, None)?);
+        for entry in archive.entries()? {
+            let mut file = entry?;
+            let path = relative_path(&file.path()?)?;
+            let name = match path.file_name() {
+                Some(fname) => fname.to_string_lossy().into_owned(),
+                None => continue,
+            };
+
+            if name == OPAQUE_WHITEOUT {
+                if let Some(parent) = path.parent() {
+                    layer_hidden.push(parent.to_path_buf());
+                }
+                continue;
+            }
+            if let Some(real_name) = name.strip_prefix(WHITEOUT_PREFIX) {
+                if !real_name.is_empty() {
+                    let real_path = path.with_file_name(real_name);
+                    layer_seen.push(real_path.clone());
+                    layer_hidden.push(real_path);
+                }
+                continue;
+            }
+            if is_hidden(&seen, &hidden, &path) {
+                if FileType::from_entry_type(file.header().entry_type()) == FileType::File {
+                    let mut header = file.header().clone();
+                    let mut content = vec![];
+                    file.read_to_end(&mut content)?;
+                    header.set_size(content.len() as u64);
+                    hidden_files.insert(path, (header, content));
+                }
+                continue;
+            }
+
+            let hidden_target = match file.link_name()? {
+                Some(ref target) if file.header().entry_type().is_hard_link() => {
+                    Some(relative_path(target)?)
+                }
+                _ => None,
+            }
+            .filter(|target| is_hidden(&seen, &hidden, target));
+            match hidden_target.map(|t| hidden_files.get(&t)) {
+                Some(Some((header, content))) => {
+                    builder.append_data(&mut header.clone(), &path, content.as_slice())?
+                }
+                Some(None) => trace!("Skipping hard link {:?} to a hidden entry", path),
+                None => append_entry(&mut builder, &mut file, &path)?,
+            }
+            if !file.header().entry_type().is_dir() {
+                layer_hidden.push(path.clone());
+            }
+            layer_seen.push(path);
+        }
+        seen.extend(layer_seen);
+        hidden.extend(layer_hidden);
+    }
+    Ok(builder.into_inner()?)
+}
+
+/// Kind of an entry in a filesystem index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+    HardLink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    /// Any other tar entry type.
+    Other,
+}
+
+impl FileType {
+    fn from_entry_type(entry_type: tar::EntryType) -> Self {
+        match entry_type {
+            tar::EntryType::Regular | tar::EntryType::Continuous => FileType::File,
+            tar::EntryType::Directory => FileType::Directory,
+            tar::EntryType::Symlink => FileType::Symlink,
+            tar::EntryType::Link => FileType::HardLink,
+            tar::EntryType::Char => FileType::CharDevice,
+            tar::EntryType::Block => FileType::BlockDevice,
+            tar::EntryType::Fifo => FileType::Fifo,
+            _ => FileType::Other,
+        }
+    }
+}
+
+/// An entry of a merged filesystem index.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Path, relative to the root of the image.
+    pub path: path::PathBuf,
+    pub file_type: FileType,
+    /// Permission bits.
+    pub mode: u32,
+    pub uid: u64,
+    pub gid: u64,
+    /// Size of content, in bytes.
+    pub size: u64,
+    /// Target of symlinks and hard links.
+    pub link_target: Option<path::PathBuf>,
+    /// Position of the layer which last provided this entry, from 0 for
+    /// the lowest one.
+    pub layer: usize,
+}
+
+/// Merged filesystem tree of an image, built from layer tar headers.
+///
+/// Entries are sorted by path.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Index {
+    entries: BTreeMap<path::PathBuf, IndexEntry>,
+}
+
+impl Index {
+    /// Build the index of an ordered list of layers.
+    ///
+    /// Layers must be provided as tar archives, either uncompressed or gzip
+    /// or zstd compressed, with lower layers coming first. Only headers are
+    /// inspected, and nothing is written to disk. Whiteouts and opaque
+    /// directories are applied as when unpacking.
+    pub fn from_layers<I, R>(layers: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = R>,
+        R: io::Read,
+    {
+        let mut index = Index::default();
+        for (n, layer) in layers.into_iter().enumerate() {
+            index.add_layer(layer, n)?;
+        }
+        Ok(index)
+    }
+
+    /// Entry at a path, relative to the root of the image.
+    pub fn get<P: AsRef<path::Path>>(&self, path: P) -> Option<&IndexEntry> {
+        relative_path(path.as_ref())
+            .ok()
+            .and_then(|p| self.entries.get(&p))
+    }
+
+    /// Iterate over all entries, sorted by path.
+    pub fn iter<'a>(&'a self) -> btree_map::Values<'a, path::PathBuf, IndexEntry> {
+        self.entries.values()
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
     }
-    for l in layers {
-        // Unpack layers
-        let gz_dec = gzip::Decoder::new(l.as_slice())?;
-        let mut archive = tar::Archive::new(gz_dec);
-        archive.set_preserve_permissions(true);
-        archive.set_unpack_xattrs(true);
-        archive.unpack(target_dir)?;
 
-        // Clean whiteouts
-        let gz_dec = gzip::Decoder::new(l.as_slice())?;
-        let mut archive = tar::Archive::new(gz_dec);
+    /// Whether there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn add_layer<R: io::Read>(&mut self, layer: R, n: usize) -> Result<()> {
+        let mut archive = tar::Archive::new(decompress(layer, None)?);
         for entry in archive.entries()? {
             let file = entry?;
-            let path = file.path()?;
-            let parent = path.parent().unwrap_or_else(|| path::Path::new("/"));
-            if let Some(fname) = path.file_name() {
-                let wh_name = fname.to_string_lossy();
-                if wh_name == ".wh..wh..opq" {
-                    //TODO(lucab): opaque whiteout, dir removal
-                } else if wh_name.starts_with(".wh.") {
-                    let rel_parent =
-                        path::PathBuf::from("./".to_string() + &parent.to_string_lossy());
-
-                    // Remove real file behind whiteout
-                    let real_name = wh_name.trim_left_matches(".wh.");
-                    let abs_real_path = target_dir.join(&rel_parent).join(real_name);
-                    fs::remove_dir_all(abs_real_path)?;
-
-                    // Remove whiteout place-holder
-                    let abs_wh_path = target_dir.join(&rel_parent).join(fname);
-                    fs::remove_dir_all(abs_wh_path)?;
+            let path = relative_path(&file.path()?)?;
+            let name = match path.file_name() {
+                Some(fname) => fname.to_string_lossy().into_owned(),
+                None => continue,
+            };
+
+            if name == OPAQUE_WHITEOUT {
+                if let Some(parent) = path.parent() {
+                    self.remove_lower(parent, false, n);
+                }
+                continue;
+            }
+            if let Some(real_name) = name.strip_prefix(WHITEOUT_PREFIX) {
+                if !real_name.is_empty() {
+                    self.remove_lower(&path.with_file_name(real_name), true, n);
+                }
+                continue;
+            }
+
+            let header = file.header();
+            let file_type = FileType::from_entry_type(header.entry_type());
+            if file_type != FileType::Directory {
+                // Lower-layer directory contents are replaced too.
+                self.remove_lower(&path, false, n);
+            }
+            let link_target = match file.link_name()? {
+                Some(ref target) if file_type == FileType::HardLink => Some(relative_path(target)?),
+                target => target.map(|t| t.into_owned()),
+            };
+            let entry = IndexEntry {
+                path: path.clone(),
+                file_type,
+                mode: header.mode()?,
+                uid: header.uid()?,
+                gid: header.gid()?,
+                size: file.size(),
+                link_target,
+                layer: n,
+            };
+            self.entries.insert(path, entry);
+        }
+        Ok(())
+    }
+
+    /// Remove descendants of a path provided by lower layers, and
+    /// optionally the path itself.
+    fn remove_lower(&mut self, path: &path::Path, itself: bool, layer: usize) {
+        let lower: Vec<path::PathBuf> = self
+            .entries
+            .range::<path::Path, _>((Bound::Included(path), Bound::Unbounded))
+            .take_while(|&(p, _)| p.starts_with(path))
+            .filter(|&(p, e)| e.layer < layer && (itself || p != path))
+            .map(|(p, _)| p.clone())
+            .collect();
+        for p in lower {
+            self.entries.remove(&p);
+        }
+    }
+}
+
+/// A file being looked up through layers.
+#[derive(Clone, Debug)]
+struct Lookup {
+    /// Path originally requested.
+    key: String,
+    /// Current path, after following links.
+    path: path::PathBuf,
+    /// Number of links followed so far.
+    hops: usize,
+    /// Layer the path must be read from, counted from the top, for hard links.
+    layer: Option<usize>,
+}
+
+impl Lookup {
+    /// Whether this lookup can be resolved in the given layer.
+    fn is_for_layer(&self, layer: usize) -> bool {
+        self.layer.is_none() || self.layer == Some(layer)
+    }
+}
+
+/// Lookup of individual files in an image, one layer at a time.
+///
+/// Layers are fed from the top one down, each being read once, and only
+/// the entries for the requested paths are kept. Whiteouts and opaque
+/// directories are applied as when unpacking, and symlinks and hard links
+/// are followed within the image root.
+///
+/// Links pointing further down the same layer, or to lower layers, are
+/// followed in the same walk. Links pointing to an entry which was already
+/// read, either in an upper layer or earlier in the same one (as hard links
+/// always do), are deferred: `restart` must then be called to walk the
+/// layers again from the top.
+#[derive(Debug, Default)]
+pub struct FileFinder {
+    pending: Vec<Lookup>,
+    deferred: Vec<Lookup>,
+    found: HashMap<String, Vec<u8>>,
+    seen: HashSet<path::PathBuf>,
+    hidden: HashSet<path::PathBuf>,
+    layer: usize,
+}
+
+impl FileFinder {
+    /// Look up a list of paths, relative to the root of the image.
+    pub fn new<S: AsRef<str>>(paths: &[S]) -> Result<Self> {
+        let mut pending = vec![];
+        for p in paths {
+            pending.push(Lookup {
+                key: p.as_ref().to_string(),
+                path: relative_path(path::Path::new(p.as_ref()))?,
+                hops: 0,
+                layer: None,
+            });
+        }
+        Ok(FileFinder {
+            pending,
+            ..FileFinder::default()
+        })
+    }
+
+    /// Whether some files are still being looked up in lower layers.
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Start a new walk from the top layer, for deferred lookups.
+    ///
+    /// Lookups which are still pending are dropped, as their files are not
+    /// in the image. Returns whether there is anything left to look up.
+    pub fn restart(&mut self) -> bool {
+        self.pending = self.deferred.split_off(0);
+        self.seen.clear();
+        self.hidden.clear();
+        self.layer = 0;
+        self.is_pending()
+    }
+
+    /// Look up pending files in the next layer, reading it once.
+    ///
+    /// The layer must be a tar archive, either uncompressed or gzip or
+    /// zstd compressed. Reading stops as soon as nothing is left to look
+    /// up in this layer.
+    pub fn add_layer<R: io::Read>(&mut self, layer: R) -> Result<()> {
+        let current = self.layer;
+        // Entries of this layer, which only hide lower layers.
+        let (mut layer_seen, mut layer_hidden) = (HashSet::new(), HashSet::new());
+        let mut archive = tar::Archive::new(decompress(layer, None)?);
+        for entry in archive.entries()? {
+            if !self.pending.iter().any(|l| l.is_for_layer(current)) {
+                break;
+            }
+            let mut file = entry?;
+            let path = relative_path(&file.path()?)?;
+            let name = match path.file_name() {
+                Some(fname) => fname.to_string_lossy().into_owned(),
+                None => continue,
+            };
+
+            if name == OPAQUE_WHITEOUT {
+                if let Some(parent) = path.parent() {
+                    layer_hidden.insert(parent.to_path_buf());
+                }
+                continue;
+            }
+            if let Some(real_name) = name.strip_prefix(WHITEOUT_PREFIX) {
+                if !real_name.is_empty() {
+                    let real_path = path.with_file_name(real_name);
+                    layer_seen.insert(real_path.clone());
+                    layer_hidden.insert(real_path);
+                }
+                continue;
+            }
+            let entry_type = file.header().entry_type();
+            let hidden = self.is_hidden(&path);
+            if !hidden {
+                if !entry_type.is_dir() {
+                    layer_hidden.insert(path.clone());
+                }
+                layer_seen.insert(path.clone());
+            }
+
+            // Lookups for this entry, or below it if it is not a directory.
+            // Hard link targets are read from their own layer, whatever is above.
+            let (matching, rest): (Vec<Lookup>, Vec<Lookup>) =
+                self.pending.drain(..).partition(|l| match l.layer {
+                    Some(n) => n == current && l.path == path,
+                    None => {
+                        !hidden
+                            && (l.path == path
+                                || (!entry_type.is_dir() && l.path.starts_with(&path)))
+                    }
+                });
+            self.pending = rest;
+            if matching.is_empty() {
+                continue;
+            }
+
+            let is_file = FileType::from_entry_type(entry_type) == FileType::File;
+            let mut content = vec![];
+            if is_file && matching.iter().any(|l| l.path == path) {
+                file.read_to_end(&mut content)?;
+            }
+            for lookup in matching {
+                let exact = lookup.path == path;
+                if exact && is_file {
+                    self.found.insert(lookup.key, content.clone());
+                    continue;
+                }
+
+                let target = match file.link_name()? {
+                    Some(target) => target.into_owned(),
+                    None => continue,
                 };
+                if lookup.hops >= MAX_LINK_HOPS {
+                    bail!("too many levels of links looking up '{}'", lookup.key);
+                }
+                if entry_type.is_symlink() {
+                    let rest = lookup.path.strip_prefix(&path).unwrap_or(&lookup.path);
+                    let new_path = resolve_link(&path, &target).join(rest);
+                    let next = Lookup {
+                        path: relative_path(&new_path)?,
+                        hops: lookup.hops + 1,
+                        layer: None,
+                        ..lookup
+                    };
+                    if self.is_hidden(&next.path)
+                        || is_hidden(&layer_seen, &layer_hidden, &next.path)
+                    {
+                        // Provided by an upper layer, or earlier in this one.
+                        self.deferred.push(next);
+                    } else {
+                        self.pending.push(next);
+                    }
+                } else if exact && entry_type.is_hard_link() {
+                    // Hard links point to an earlier entry of the same layer.
+                    self.deferred.push(Lookup {
+                        path: relative_path(&target)?,
+                        hops: lookup.hops + 1,
+                        layer: Some(current),
+                        ..lookup
+                    });
+                }
+            }
+        }
+
+        self.seen.extend(layer_seen);
+        self.hidden.extend(layer_hidden);
+        self.layer += 1;
+        let (seen, hidden) = (&self.seen, &self.hidden);
+        self.pending
+            .retain(|l| l.layer.is_some() || !is_hidden(seen, hidden, &l.path));
+        Ok(())
+    }
+
+    /// Contents of the files found so far, by requested path.
+    pub fn into_files(self) -> HashMap<String, Vec<u8>> {
+        self.found
+    }
+
+    /// Whether a path is provided or hidden by upper layers.
+    fn is_hidden(&self, path: &path::Path) -> bool {
+        is_hidden(&self.seen, &self.hidden, path)
+    }
+}
+
+/// Copy a layer entry to a tar archive, at a normalized path.
+///
+/// Long paths and link names are re-encoded, and other PAX extensions
+/// (e.g. extended attributes) are kept.
+fn append_entry<R: io::Read, W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    file: &mut tar::Entry<R>,
+    path: &path::Path,
+) -> Result<()> {
+    let mut records = vec![];
+    if let Some(exts) = file.pax_extensions()? {
+        for ext in exts {
+            let ext = ext?;
+            let key = ext.key().map_err(|e| format!("invalid PAX key: {}", e))?;
+            if key != "path" && key != "linkpath" {
+                records.push((key.to_string(), ext.value_bytes().to_vec()));
+            }
+        }
+    }
+    if !records.is_empty() {
+        append_pax(builder, &records)?;
+    }
+
+    let mut header = file.header().clone();
+    header.set_size(file.size());
+    match file.link_name()? {
+        Some(ref target) if header.entry_type().is_hard_link() => {
+            builder.append_link(&mut header, path, relative_path(target)?)?
+        }
+        Some(target) => builder.append_link(&mut header, path, target)?,
+        None => builder.append_data(&mut header, path, file)?,
+    };
+    Ok(())
+}
+
+/// Write a PAX extended header, applying to the next entry.
+fn append_pax<W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    records: &[(String, Vec<u8>)],
+) -> Result<()> {
+    let mut data = vec![];
+    for (key, value) in records {
+        // Each record is `<length> <key>=<value>\n`, its length included.
+        let len = key.len() + value.len() + 3;
+        let mut total = len + len.to_string().len();
+        if total.to_string().len() != len.to_string().len() {
+            total += 1;
+        }
+        data.extend_from_slice(format!("{} {}=", total, key).as_bytes());
+        data.extend_from_slice(value);
+        data.push(b'\n');
+    }
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::XHeader);
+    header.set_mode(0o644);
+    header.set_size(data.len() as u64);
+    builder.append_data(&mut header, "././@PaxHeader", data.as_slice())?;
+    Ok(())
+}
+
+/// Change the owner of an unpacked entry, without following symlinks.
+///
+/// Setuid and setgid bits cleared by the kernel are restored from `mode`.
+#[cfg(unix)]
+fn chown(path: &path::Path, uid: u64, gid: u64, mode: Option<u32>) -> Result<()> {
+    use std::convert::TryFrom;
+    use std::os::unix::fs::{lchown, PermissionsExt};
+    let uid = u32::try_from(uid).map_err(|_| format!("uid {} out of range", uid))?;
+    let gid = u32::try_from(gid).map_err(|_| format!("gid {} out of range", gid))?;
+    lchown(path, Some(uid), Some(gid))?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn chown(_: &path::Path, _: u64, _: u64, _: Option<u32>) -> Result<()> {
+    Ok(())
+}
+
+/// Set an extended attribute on an unpacked entry, without following symlinks.
+#[cfg(unix)]
+fn set_xattr(path: &path::Path, key: &str, value: &[u8]) -> Result<()> {
+    xattr::set(path, key, value)
+        .map_err(|e| format!("failed to set xattr {} on {:?}: {}", key, path, e).into())
+}
+
+#[cfg(not(unix))]
+fn set_xattr(_: &path::Path, _: &str, _: &[u8]) -> Result<()> {
+    Ok(())
+}
+
+/// Decompress a layer, detecting its compression if not known.
+fn decompress<'a, R: io::Read + 'a>(
+    mut layer: R,
+    compression: Option<Compression>,
+) -> Result<Box<io::Read + 'a>> {
+    let (compression, layer): (_, Box<io::Read>) = match compression {
+        Some(c) => (c, Box::new(layer)),
+        None => {
+            // Peek at the magic number, then put it back in front.
+            let mut header = vec![];
+            (&mut layer)
+                .take(ZSTD_MAGIC.len() as u64)
+                .read_to_end(&mut header)?;
+            let c = Compression::sniff(&header);
+            (c, Box::new(io::Cursor::new(header).chain(layer)))
+        }
+    };
+    let decoded: Box<io::Read> = match compression {
+        Compression::None => layer,
+        Compression::Gzip => Box::new(gzip::Decoder::new(layer)?),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(layer)?),
+    };
+    Ok(decoded)
+}
+
+/// Blocking reader over chunks received from a channel.
+struct ChunkReader {
+    chunks: futures::stream::Wait<futures::sync::mpsc::Receiver<Vec<u8>>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl io::Read for ChunkReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.buf.len() {
+            match self.chunks.next() {
+                Some(Ok(chunk)) => {
+                    self.buf = chunk;
+                    self.pos = 0;
+                }
+                _ => return Ok(0),
             }
         }
+        let n = (self.buf.len() - self.pos).min(out.len());
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Check that a target directory is an existing absolute path.
+fn check_target(target_dir: &path::Path) -> Result<()> {
+    if !target_dir.is_absolute() || !target_dir.exists() || !target_dir.is_dir() {
+        bail!("wrong target path");
+    }
+    Ok(())
+}
+
+/// Normalize a layer entry path, relative to the root of the image.
+///
+/// The entry path must not contain `..` components.
+pub(crate) fn relative_path(entry_path: &path::Path) -> Result<path::PathBuf> {
+    let mut path = path::PathBuf::new();
+    for c in entry_path.components() {
+        match c {
+            path::Component::Normal(p) => path.push(p),
+            path::Component::ParentDir => {
+                bail!("entry path '{}' contains '..'", entry_path.display())
+            }
+            path::Component::CurDir | path::Component::RootDir | path::Component::Prefix(_) => {}
+        }
+    }
+    Ok(path)
+}
+
+/// Whether a path is in `seen`, or below a path in `hidden`.
+fn is_hidden(
+    seen: &HashSet<path::PathBuf>,
+    hidden: &HashSet<path::PathBuf>,
+    path: &path::Path,
+) -> bool {
+    seen.contains(path) || path.ancestors().skip(1).any(|p| hidden.contains(p))
+}
+
+/// Resolve the target of a symlink lexically, within the image root.
+///
+/// `..` components never go above the root.
+fn resolve_link(link_path: &path::Path, target: &path::Path) -> path::PathBuf {
+    let mut path = match link_path.parent() {
+        Some(parent) if target.is_relative() => parent.to_path_buf(),
+        _ => path::PathBuf::new(),
+    };
+    for c in target.components() {
+        match c {
+            path::Component::Normal(p) => path.push(p),
+            path::Component::ParentDir => {
+                path.pop();
+            }
+            path::Component::CurDir | path::Component::RootDir | path::Component::Prefix(_) => {}
+        }
+    }
+    path
+}
+
+/// Resolve a layer entry path to a location within `root`.
+///
+/// `root` must be canonical. The entry path is interpreted relative to it,
+/// must not contain `..` components, and its closest existing ancestor
+/// must not resolve (through symlinks) outside of `root`.
+fn confined_path(root: &path::Path, entry_path: &path::Path) -> Result<path::PathBuf> {
+    let path = root.join(relative_path(entry_path)?);
+
+    let mut ancestor = path.parent();
+    while let Some(dir) = ancestor {
+        if fs::symlink_metadata(dir).is_ok() {
+            if !dir.canonicalize()?.starts_with(root) {
+                bail!(
+                    "entry path '{}' escapes the target directory",
+                    entry_path.display()
+                );
+            }
+            break;
+        }
+        ancestor = dir.parent();
+    }
+    Ok(path)
+}
+
+/// Remove a file, symlink or directory, if it exists.
+///
+/// Symlinks are removed themselves, never followed.
+fn remove_path(path: &path::Path) -> Result<()> {
+    let res = match fs::symlink_metadata(path) {
+        Ok(ref m) if m.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    };
+    Ok(res?)
+}
+
+/// Remove all contents of a directory, if it exists, except for `keep` paths.
+fn clear_dir(dir: &path::Path, keep: &HashSet<path::PathBuf>) -> Result<()> {
+    match fs::symlink_metadata(dir) {
+        Ok(ref m) if m.is_dir() => {}
+        _ => return Ok(()),
+    };
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if !keep.contains(&path) {
+            remove_path(&path)?;
+        } else if entry.file_type()?.is_dir() {
+            clear_dir(&path, keep)?;
+        }
     }
     Ok(())
 }
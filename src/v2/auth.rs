@@ -1,32 +1,99 @@
 use base64;
-use futures::Stream;
+use futures::future;
 use hyper::header;
+use url;
 use v2::*;
 
 /// Convenience alias for future `TokenAuth` result.
 pub type FutureTokenAuth = Box<futures::Future<Item = TokenAuth, Error = Error> + 'static>;
 
+/// HTTP authentication scheme used for registry requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AuthScheme {
+    /// Plain HTTP Basic authentication, with base64-encoded credentials.
+    Basic,
+    /// Bearer token, as returned by a token endpoint.
+    #[default]
+    Bearer,
+}
+
+impl AuthScheme {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            AuthScheme::Basic => "Basic",
+            AuthScheme::Bearer => "Bearer",
+        }
+    }
+}
+
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct TokenAuth {
+    #[serde(default)]
     token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    access_token: Option<String>,
     expires_in: Option<u32>,
     issued_at: Option<String>,
     refresh_token: Option<String>,
+    #[serde(skip)]
+    scheme: AuthScheme,
 }
 
 impl TokenAuth {
     pub fn token(&self) -> &str {
         self.token.as_str()
     }
+
+    /// Refresh token, if one was issued by the token endpoint.
+    ///
+    /// This can be stored as an identity token and passed back via
+    /// `Config::identity_token` to authenticate without a password.
+    pub fn refresh_token(&self) -> Option<&str> {
+        self.refresh_token.as_deref()
+    }
+
+    /// Lifetime of the token in seconds, if known.
+    pub fn expires_in(&self) -> Option<u32> {
+        self.expires_in
+    }
+
+    /// Issue time of the token, as an RFC3339 string, if known.
+    pub fn issued_at(&self) -> Option<&str> {
+        self.issued_at.as_deref()
+    }
+
+    /// Authentication scheme this token has to be presented with.
+    pub fn scheme(&self) -> AuthScheme {
+        self.scheme
+    }
 }
 
-type FutureString = Box<futures::Future<Item = String, Error = self::Error>>;
+/// Format an `Authorization` header value for the given scheme and token.
+pub(crate) fn auth_header_value(scheme: AuthScheme, token: &str) -> String {
+    format!("{} {}", scheme.as_str(), token)
+}
+
+/// Encode credentials as a Basic authentication token.
+fn basic_auth_token(user: &str, password: &str) -> String {
+    base64::encode(&format!("{}:{}", user, password))
+}
+
+fn basic_auth_value(user: &str, password: &str) -> String {
+    auth_header_value(AuthScheme::Basic, &basic_auth_token(user, password))
+}
+
+/// Convenience alias for future authentication challenges.
+pub type FutureChallenges = Box<dyn futures::Future<Item = Vec<Challenge>, Error = Error>>;
 
 impl Client {
-    fn get_token_provider(&self) -> FutureString {
+    /// Retrieve the authentication challenges advertised by the registry.
+    ///
+    /// Challenges are taken from the `WWW-Authenticate` headers returned
+    /// when querying the base API endpoint.
+    pub fn get_challenges(&self) -> FutureChallenges {
         let url = {
             let ep = format!("{}/v2/", self.base_url);
-            match hyper::Uri::from_str(ep.as_str()) {
+            match url::Url::parse(&ep) {
                 Ok(url) => url,
                 Err(e) => {
                     return Box::new(futures::future::err::<_, _>(Error::from(format!(
@@ -44,102 +111,237 @@ impl Client {
                 return Box::new(futures::future::err::<_, _>(Error::from(msg)));
             }
         };
-        let freq = self.hclient.request(req);
-        let www_auth = freq
-            .from_err()
-            .and_then(|r| {
-                let a = r
-                    .headers()
-                    .get(hyper::header::WWW_AUTHENTICATE)
-                    .ok_or_else(|| Error::from("get_token: missing Auth header"))?;
-                let chal = String::from_utf8(a.as_bytes().to_vec())?;
-                Ok(chal)
-            }).and_then(move |hdr| {
-                let mut auth_ep = "".to_owned();
-                let mut service = None;
-                for item in hdr.trim_left_matches("Bearer ").split(',') {
-                    let kv: Vec<&str> = item.split('=').collect();
-                    match (kv.get(0), kv.get(1)) {
-                        (Some(&"realm"), Some(v)) => auth_ep = v.trim_matches('"').to_owned(),
-                        (Some(&"service"), Some(v)) => service = Some(v.trim_matches('"')),
-                        (Some(&"scope"), _) => {}
-                        (_, _) => return Err("unsupported key".to_owned().into()),
-                    };
-                }
-                trace!("Token provider: {}", auth_ep);
-                if let Some(sv) = service {
-                    auth_ep += &format!("?service={}", sv);
-                    trace!("Service identity: {}", sv);
-                }
-                Ok(auth_ep)
-            });
-        Box::new(www_auth)
+        let freq = self.execute(req, Operation::Metadata);
+        let challenges = freq.and_then(|r| {
+            let mut challenges = vec![];
+            for hdr in r.headers().get_all(header::WWW_AUTHENTICATE) {
+                challenges.extend(Challenge::parse_all(hdr.to_str()?)?);
+            }
+            if challenges.is_empty() {
+                bail!("get_challenges: missing Auth header");
+            }
+            trace!("Authentication challenges: {:?}", challenges);
+            Ok(challenges)
+        });
+        Box::new(self.with_timeout(challenges, Operation::Metadata))
     }
 
     /// Set the token to be used for further registry requests.
     pub fn set_token(&mut self, token: Option<&str>) -> &Self {
         if let Some(ref t) = token {
             self.token = Some(t.to_string());
+            self.auth_scheme = AuthScheme::Bearer;
+        }
+        self
+    }
+
+    /// Set the authentication obtained via `login` for further registry requests.
+    pub fn set_auth(&mut self, auth: Option<&TokenAuth>) -> &Self {
+        if let Some(a) = auth {
+            self.token = Some(a.token.clone());
+            self.auth_scheme = a.scheme;
         }
         self
     }
 
     /// Perform registry authentication and return an authenticated token.
     ///
-    /// On success, the returned token will be valid for all requested scopes.
+    /// Registries advertising a `Bearer` challenge are authenticated against
+    /// their token endpoint. If no scopes are given, the scope advertised in
+    /// the challenge (if any) is requested. On success, the returned token
+    /// will be valid for all requested scopes.
+    ///
+    /// Registries advertising only a `Basic` challenge are authenticated by
+    /// checking configured credentials against the base API endpoint. The
+    /// returned token then carries those credentials.
     pub fn login(&self, scopes: &[&str]) -> FutureTokenAuth {
-        let subclient = self.hclient.clone();
-        let creds = self.credentials.clone();
-        let scope = scopes
+        let dclient = self.clone();
+        let scopes: Vec<String> = scopes
             .iter()
-            .fold("".to_string(), |acc, &s| acc + "&scope=" + s);
-        let auth = self
-            .get_token_provider()
-            .and_then(move |token_ep| {
-                let auth_ep = token_ep + scope.as_str();
-                trace!("Token endpoint: {}", auth_ep);
-                hyper::Uri::from_str(auth_ep.as_str()).map_err(|e| e.into())
-            }).and_then(move |u| {
-                let mut auth_req = hyper::Request::default();
-                *auth_req.method_mut() = hyper::Method::GET;
-                *auth_req.uri_mut() = u;
-                if let Some(c) = creds {
-                    let plain = format!("{}:{}", c.0, c.1);
-                    let basic = format!("Basic {}", base64::encode(&plain));
-                    if let Ok(basic_header) = header::HeaderValue::from_str(&basic) {
-                        auth_req
-                            .headers_mut()
-                            .append(header::AUTHORIZATION, basic_header);
-                    } else {
-                        let msg = format!("could not parse HeaderValue from '{}'", basic);
-                        error!("{}", msg);
-                        // TODO: return an error. seems difficult to match the error type for the whole closure
-                    };
-                };
-                subclient.request(auth_req).map_err(|e| e.into())
-            }).and_then(|r| {
-                let status = r.status();
-                trace!("Got status {}", status);
-                match status {
-                    hyper::StatusCode::OK => Ok(r),
-                    _ => Err(format!("login: wrong HTTP status '{}'", status).into()),
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect();
+        let auth = self.get_challenges().and_then(move |challenges| {
+            let bearer = challenges.iter().find(|c| c.is_scheme("bearer"));
+            let basic = challenges.iter().find(|c| c.is_scheme("basic"));
+            match (bearer, basic) {
+                (Some(c), _) => dclient.login_bearer(c, scopes),
+                (None, Some(_)) => dclient.login_basic(),
+                (None, None) => Box::new(futures::future::err(Error::from(format!(
+                    "login: no supported authentication scheme in {:?}",
+                    challenges
+                )))),
+            }
+        });
+        Box::new(auth)
+    }
+
+    fn login_bearer(&self, challenge: &Challenge, scopes: Vec<String>) -> FutureTokenAuth {
+        let realm = match challenge.realm().map(url::Url::parse) {
+            Some(Ok(u)) => u,
+            Some(Err(e)) => {
+                return Box::new(futures::future::err(Error::from(format!(
+                    "login: invalid realm: {}",
+                    e
+                ))))
+            }
+            None => return Box::new(futures::future::err(Error::from("login: missing realm"))),
+        };
+        let service = challenge.param("service").map(|s| s.to_string());
+        if let Some(ref sv) = service {
+            trace!("Service identity: {}", sv);
+        }
+        let scopes = if scopes.is_empty() {
+            // Scopes in a challenge are space-separated.
+            challenge
+                .param("scope")
+                .unwrap_or("")
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect()
+        } else {
+            scopes
+        };
+
+        match (&self.identity_token, &self.credentials) {
+            (Some(ref rt), _) => {
+                let form = self.oauth2_form(
+                    &[("grant_type", "refresh_token"), ("refresh_token", rt)],
+                    &service,
+                    &scopes,
+                );
+                self.post_token(realm, form)
+            }
+            (None, Some(ref c)) if self.offline_token => {
+                let form = self.oauth2_form(
+                    &[
+                        ("grant_type", "password"),
+                        ("username", &c.0),
+                        ("password", &c.1),
+                        ("access_type", "offline"),
+                    ],
+                    &service,
+                    &scopes,
+                );
+                let dclient = self.clone();
+                let fallback_realm = realm.clone();
+                let auth = self.post_token(realm, form).or_else(move |e| {
+                    // Token servers without OAuth2 support reject the POST, retry with a plain GET.
+                    match *e.kind() {
+                        ErrorKind::TokenStatus(status)
+                            if status == hyper::StatusCode::NOT_FOUND
+                                || status == hyper::StatusCode::METHOD_NOT_ALLOWED =>
+                        {
+                            trace!("OAuth2 login unsupported, falling back to basic: {}", e);
+                            future::Either::A(dclient.get_token(fallback_realm, &service, &scopes))
+                        }
+                        _ => future::Either::B(future::err(e)),
+                    }
+                });
+                Box::new(auth)
+            }
+            (None, _) => self.get_token(realm, &service, &scopes),
+        }
+    }
+
+    fn oauth2_form(
+        &self,
+        grant: &[(&str, &str)],
+        service: &Option<String>,
+        scopes: &[String],
+    ) -> String {
+        let mut form = url::form_urlencoded::Serializer::new(String::new());
+        form.extend_pairs(grant);
+        form.append_pair("client_id", &self.client_id);
+        if let Some(ref sv) = service {
+            form.append_pair("service", sv);
+        }
+        if !scopes.is_empty() {
+            form.append_pair("scope", &scopes.join(" "));
+        }
+        form.finish()
+    }
+
+    /// Request a token via the OAuth2 POST flow.
+    fn post_token(&self, realm: url::Url, form: String) -> FutureTokenAuth {
+        trace!("Token endpoint (OAuth2): {}", realm);
+        let mut auth_req = match self.plain_request(hyper::Method::POST, realm, form.into()) {
+            Ok(r) => r,
+            Err(e) => return Box::new(futures::future::err(e)),
+        };
+        auth_req.headers_mut().append(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        self.fetch_token(auth_req)
+    }
+
+    /// Request a token via a GET, with credentials sent as basic authentication.
+    fn get_token(
+        &self,
+        mut realm: url::Url,
+        service: &Option<String>,
+        scopes: &[String],
+    ) -> FutureTokenAuth {
+        {
+            let mut query = realm.query_pairs_mut();
+            if let Some(ref sv) = service {
+                query.append_pair("service", sv);
+            }
+            for s in scopes {
+                query.append_pair("scope", s);
+            }
+        }
+        trace!("Token endpoint: {}", realm);
+        let body = hyper::Body::empty();
+        let mut auth_req = match self.plain_request(hyper::Method::GET, realm, body) {
+            Ok(r) => r,
+            Err(e) => return Box::new(futures::future::err(e)),
+        };
+        if let Some(ref c) = self.credentials {
+            let basic = basic_auth_value(&c.0, &c.1);
+            match header::HeaderValue::from_str(&basic) {
+                Ok(h) => {
+                    auth_req.headers_mut().append(header::AUTHORIZATION, h);
                 }
-            }).and_then(|r| {
-                r.into_body()
-                    .concat2()
-                    .map_err(|e| format!("login: failed to fetch the whole body: {}", e).into())
-            }).and_then(|body| {
-                let s = String::from_utf8(body.into_bytes().to_vec())?;
-                serde_json::from_slice(s.as_bytes()).map_err(|e| e.into())
-            }).inspect(|_| {
-                trace!("Got token");
-            });
+                Err(e) => return Box::new(futures::future::err(e.into())),
+            };
+        };
+        self.fetch_token(auth_req)
+    }
+
+    fn login_basic(&self) -> FutureTokenAuth {
+        let token = match self.credentials {
+            Some(ref c) => basic_auth_token(&c.0, &c.1),
+            None => {
+                return Box::new(futures::future::err(Error::from(
+                    "login: basic authentication requires credentials",
+                )))
+            }
+        };
+        let mut dclient = self.clone();
+        dclient.token = Some(token.clone());
+        dclient.auth_scheme = AuthScheme::Basic;
+        let auth = dclient.is_auth(None).and_then(move |is_auth| {
+            if !is_auth {
+                bail!("login: credentials rejected by registry");
+            }
+            trace!("Basic credentials accepted");
+            Ok(TokenAuth {
+                token,
+                scheme: AuthScheme::Basic,
+                ..TokenAuth::default()
+            })
+        });
         Box::new(auth)
     }
 
     /// Check whether the client is authenticated with the registry.
+    ///
+    /// If `token` is given, it is also presented with the authentication
+    /// scheme of the client.
     pub fn is_auth(&self, token: Option<&str>) -> FutureBool {
-        let url = match hyper::Uri::from_str((self.base_url.clone() + "/v2/").as_str()) {
+        let url = match url::Url::parse(&(self.base_url.clone() + "/v2/")) {
             Ok(url) => url,
             Err(e) => return Box::new(futures::future::err(e.into())),
         };
@@ -152,20 +354,18 @@ impl Client {
             }
         };
         if let Some(t) = token {
-            let bearer = format!("Bearer {}", t);
-            if let Ok(basic_header) = header::HeaderValue::from_str(&bearer) {
-                req.headers_mut()
-                    .append(header::AUTHORIZATION, basic_header);
+            let value = auth_header_value(self.auth_scheme, t);
+            if let Ok(auth_header) = header::HeaderValue::from_str(&value) {
+                req.headers_mut().append(header::AUTHORIZATION, auth_header);
             } else {
-                let msg = format!("could not parse HeaderValue from '{}'", bearer);
+                let msg = format!("could not parse HeaderValue from '{}'", value);
                 error!("{}", msg);
                 return Box::new(futures::future::err(Error::from(msg)));
             };
         };
 
-        let freq = self.hclient.request(req);
+        let freq = self.execute(req, Operation::Metadata);
         let fres = freq
-            .from_err()
             .inspect(move |_| {
                 trace!("GET {:?}", url);
             }).and_then(move |r| {
@@ -177,6 +377,35 @@ impl Client {
                     _ => Err(format!("is_auth: wrong HTTP status '{}'", status).into()),
                 }
             });
-        Box::new(fres)
+        Box::new(self.with_timeout(fres, Operation::Metadata))
+    }
+
+    /// Send a request to a token endpoint, and parse the returned token.
+    fn fetch_token(&self, req: hyper::Request<hyper::Body>) -> FutureTokenAuth {
+        let client = self.clone();
+        let fres = self
+            .execute(req, Operation::Metadata)
+            .and_then(|r| {
+                let status = r.status();
+                trace!("Got status {}", status);
+                match status {
+                    hyper::StatusCode::OK => Ok(r),
+                    _ => Err(ErrorKind::TokenStatus(status).into()),
+                }
+            }).and_then(move |r| client.read_body(r, Operation::Metadata))
+            .and_then(|body| {
+                let mut auth: TokenAuth = serde_json::from_slice(&body)?;
+                // OAuth2 token endpoints only return `access_token`.
+                if auth.token.is_empty() {
+                    auth.token = auth.access_token.take().unwrap_or_default();
+                }
+                if auth.token.is_empty() {
+                    bail!("login: missing token in response");
+                }
+                Ok(auth)
+            }).inspect(|_| {
+                trace!("Got token");
+            });
+        Box::new(self.with_timeout(fres, Operation::Metadata))
     }
 }
@@ -1,17 +1,25 @@
-use futures::Stream;
-use reqwest;
-use reqwest::StatusCode;
+use digest::{Digest, Verifier};
+use futures::{Async, Poll, Stream};
+use hyper::StatusCode;
 use v2::*;
 
 /// Convenience alias for future binary blob.
 pub type FutureBlob = Box<futures::Future<Item = Vec<u8>, Error = Error>>;
 
+/// Convenience alias for a stream of binary blob chunks.
+pub type StreamBlob = Box<futures::Stream<Item = hyper::Chunk, Error = Error>>;
+
 impl Client {
     /// Check if a blob exists.
-    pub fn has_blob(&self, name: &str, digest: &str) -> FutureBool {
+    pub fn has_blob(&self, name: &str, digest: &Digest) -> FutureBool {
+        let (name, digest) = (name.to_string(), digest.clone());
+        self.with_mirrors(move |c| c.has_blob_direct(&name, &digest), |found| !found)
+    }
+
+    fn has_blob_direct(&self, name: &str, digest: &Digest) -> FutureBool {
         let url = {
             let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
-            match reqwest::Url::parse(&ep) {
+            match url::Url::parse(&ep) {
                 Ok(url) => url,
                 Err(e) => {
                     return Box::new(futures::future::err::<_, _>(Error::from(format!(
@@ -22,36 +30,34 @@ impl Client {
             }
         };
 
-        let fres = reqwest::async::Client::new()
-            .head(url)
-            .send()
+        let req = match self.new_request(hyper::Method::HEAD, url) {
+            Ok(r) => r,
+            Err(e) => return Box::new(futures::future::err(e)),
+        };
+        let fres = self
+            .execute(req, Operation::Metadata)
             .inspect(|res| trace!("Blob HEAD status: {:?}", res.status()))
             .and_then(|res| match res.status() {
                 StatusCode::OK => Ok(true),
                 _ => Ok(false),
-            }).map_err(|e| format!("{}", e).into());
-        Box::new(fres)
+            });
+        Box::new(self.with_timeout(fres, Operation::Metadata))
     }
 
     /// Retrieve blob.
-    pub fn get_blob(&self, name: &str, digest: &str) -> FutureBlob {
-        let url = {
-            let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
-            match reqwest::Url::parse(&ep) {
-                Ok(url) => url,
-                Err(e) => {
-                    return Box::new(futures::future::err::<_, _>(Error::from(format!(
-                        "failed to parse url from string: {}",
-                        e
-                    ))));
-                }
-            }
-        };
+    ///
+    /// The blob content is verified against its digest, failing with
+    /// `ErrorKind::DigestMismatch` if it does not match.
+    pub fn get_blob(&self, name: &str, digest: &Digest) -> FutureBlob {
+        let (name, digest) = (name.to_string(), digest.clone());
+        self.with_mirrors(move |c| c.get_blob_direct(&name, &digest), |_| false)
+    }
 
-        let fres = reqwest::async::Client::new()
-            .get(url)
-            .send()
-            .map_err(|e| ::errors::Error::from(format!("{}", e)))
+    fn get_blob_direct(&self, name: &str, digest: &Digest) -> FutureBlob {
+        let client = self.clone();
+        let digest = digest.clone();
+        let fres = self
+            .blob_response(name, digest.clone())
             .and_then(|res| {
                 trace!("Blob GET status: {:?}", res.status());
                 let status = res.status();
@@ -67,19 +73,17 @@ impl Client {
                         status
                     )))
                 }
-            }).and_then(|mut res| {
-                std::mem::replace(res.body_mut(), reqwest::async::Decoder::empty())
-                    .concat2()
-                    .map_err(|e| ::errors::Error::from(format!("{}", e)))
-                    .join(futures::future::ok(res))
-            }).map_err(|e| ::errors::Error::from(format!("{}", e)))
-            .and_then(|(body, res)| {
-                let body_vec = body.to_vec();
-                let len = body_vec.len();
+            }).and_then(move |res| {
                 let status = res.status();
+                client
+                    .read_body(res, Operation::Blob)
+                    .map(move |body| (body, status))
+            }).and_then(move |(body_vec, status)| {
+                let len = body_vec.len();
 
                 if status.is_success() {
                     trace!("Successfully received blob with {} bytes ", len);
+                    digest.verify(&body_vec)?;
                     Ok(body_vec)
                 } else if status.is_client_error() {
                     Err(Error::from(format!(
@@ -100,6 +104,96 @@ impl Client {
                     )))
                 }
             });
-        Box::new(fres)
+        Box::new(self.with_timeout(fres, Operation::Blob))
+    }
+
+    /// Retrieve blob as a stream of chunks, without buffering it.
+    ///
+    /// The blob content is verified against its digest once fully
+    /// received, and the stream fails with `ErrorKind::DigestMismatch` as
+    /// its last item if it does not match. Mirrors are only skipped if they
+    /// do not successfully respond, as a partially streamed blob cannot be
+    /// retried. The total timeout only applies to receiving the response
+    /// headers.
+    pub fn get_blob_stream(&self, name: &str, digest: &Digest) -> StreamBlob {
+        let (name, expected) = (name.to_string(), digest.clone());
+        let fres = self.with_mirrors(
+            move |c| {
+                let client = c.clone();
+                let fres = c.blob_response(&name, expected.clone()).map(move |res| {
+                    trace!("Blob GET status: {:?}", res.status());
+                    (client, res)
+                });
+                Box::new(c.with_timeout(fres, Operation::Blob))
+            },
+            |(_, res)| !res.status().is_success(),
+        );
+        let body = fres
+            .and_then(|(client, res)| {
+                let status = res.status();
+                if !status.is_success() {
+                    bail!("GET request failed with status '{}'", status);
+                }
+                Ok(client.body_stream(res, Operation::Blob))
+            }).flatten_stream();
+        Box::new(VerifiedStream {
+            inner: body,
+            verifier: Some(Verifier::new(digest.clone())),
+        })
+    }
+
+    /// Send a request for a blob.
+    fn blob_response(&self, name: &str, digest: Digest) -> retry::FutureResponse {
+        let url = {
+            let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
+            match url::Url::parse(&ep) {
+                Ok(url) => url,
+                Err(e) => {
+                    return Box::new(futures::future::err::<_, _>(Error::from(format!(
+                        "failed to parse url from string: {}",
+                        e
+                    ))));
+                }
+            }
+        };
+
+        let req = match self.new_request(hyper::Method::GET, url) {
+            Ok(r) => r,
+            Err(e) => return Box::new(futures::future::err(e)),
+        };
+        self.execute(req, Operation::Blob)
+    }
+}
+
+/// A stream of blob chunks, verified against a digest when it ends.
+struct VerifiedStream<S> {
+    inner: S,
+    verifier: Option<Verifier>,
+}
+
+impl<S: Stream<Item = hyper::Chunk, Error = Error>> Stream for VerifiedStream<S> {
+    type Item = hyper::Chunk;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Error> {
+        let item = match self.inner.poll()? {
+            Async::Ready(item) => item,
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+        match item {
+            Some(chunk) => {
+                if let Some(ref mut verifier) = self.verifier {
+                    verifier.update(&chunk);
+                }
+                Ok(Async::Ready(Some(chunk)))
+            }
+            None => {
+                if let Some(verifier) = self.verifier.take() {
+                    verifier.verify()?;
+                    trace!("Successfully streamed and verified blob");
+                }
+                Ok(Async::Ready(None))
+            }
+        }
     }
 }
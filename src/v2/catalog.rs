@@ -1,8 +1,8 @@
 use errors::{Error, Result};
-use futures::{self, Future, Stream};
+use futures::{self, Future};
 use hyper;
 use serde_json;
-use std::str::FromStr;
+use url;
 use v2;
 
 /// Convenience alias for a stream of `String` repos.
@@ -22,7 +22,7 @@ impl v2::Client {
                 "".to_string()
             };
             let ep = format!("{}/v2/_catalog{}", self.base_url, suffix);
-            match hyper::Uri::from_str(ep.as_str()) {
+            match url::Url::parse(&ep) {
                 Ok(url) => url,
                 Err(e) => {
                     let msg = format!("new_request failed: {}", e);
@@ -40,9 +40,9 @@ impl v2::Client {
                 return Box::new(futures::stream::once::<_, Error>(Err(Error::from(msg))));
             }
         };
-        let freq = self.hclient.request(req);
+        let client = self.clone();
+        let freq = self.execute(req, v2::Operation::Metadata);
         let fres = freq
-            .from_err()
             .and_then(|r| {
                 let status = r.status();
                 trace!("Got status: {:?}", status);
@@ -50,13 +50,12 @@ impl v2::Client {
                     hyper::StatusCode::OK => Ok(r),
                     _ => Err(format!("get_catalog: wrong HTTP status '{}'", status).into()),
                 }
-            }).and_then(|r| {
-                r.into_body().concat2().map_err(|e| {
-                    format!("get_catalog: failed to fetch the whole body: {}", e).into()
-                })
-            }).and_then(|body| -> Result<Catalog> {
-                serde_json::from_slice(&body.into_bytes()).map_err(|e| e.into())
-            }).map(|cat| futures::stream::iter_ok(cat.repositories.into_iter()))
+            }).and_then(move |r| client.read_body(r, v2::Operation::Metadata))
+            .and_then(|body| -> Result<Catalog> {
+                serde_json::from_slice(&body).map_err(|e| e.into())
+            }).map(|cat| futures::stream::iter_ok(cat.repositories.into_iter()));
+        let fres = self
+            .with_timeout(fres, v2::Operation::Metadata)
             .flatten_stream();
         Box::new(fres)
     }
@@ -0,0 +1,228 @@
+//! Parser for `WWW-Authenticate` challenges.
+
+// Grammar is specified at https://tools.ietf.org/html/rfc7235#section-4.1:
+//
+//   WWW-Authenticate = 1#challenge
+//   challenge        = auth-scheme [ 1*SP ( token68 / #auth-param ) ]
+//   auth-param       = token BWS "=" BWS ( token / quoted-string )
+
+use errors::*;
+use std::collections::HashMap;
+
+/// An authentication challenge issued by a registry.
+///
+/// Scheme and parameter names are case-insensitive, and are stored
+/// lowercased. Parameter values are stored unquoted and unescaped.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Challenge {
+    scheme: String,
+    token68: Option<String>,
+    params: HashMap<String, String>,
+}
+
+impl Challenge {
+    /// Parse all challenges contained in a `WWW-Authenticate` header value.
+    pub fn parse_all(header: &str) -> Result<Vec<Challenge>> {
+        let mut parser = Parser::new(header);
+        let mut challenges = vec![];
+        loop {
+            parser.skip_separators();
+            if parser.is_eof() {
+                break;
+            }
+            challenges.push(parser.challenge()?);
+        }
+        Ok(challenges)
+    }
+
+    /// Authentication scheme, lowercased (e.g. `bearer` or `basic`).
+    pub fn scheme(&self) -> &str {
+        self.scheme.as_str()
+    }
+
+    /// Whether this challenge uses the given authentication scheme.
+    pub fn is_scheme(&self, scheme: &str) -> bool {
+        self.scheme.eq_ignore_ascii_case(scheme)
+    }
+
+    /// Value of the `token68` form of this challenge, if any.
+    pub fn token68(&self) -> Option<&str> {
+        self.token68.as_deref()
+    }
+
+    /// Value of an authentication parameter, looked up case-insensitively.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    /// Value of the `realm` parameter, if any.
+    pub fn realm(&self) -> Option<&str> {
+        self.param("realm")
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input: input.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn is_eof(&self) -> bool {
+        self.pos >= self.input.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).cloned()
+    }
+
+    fn skip_whitespace(&mut self) -> usize {
+        let start = self.pos;
+        while let Some(b' ') | Some(b'\t') = self.peek() {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn skip_separators(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b',') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn take_while<F: Fn(u8) -> bool>(&mut self, f: F) -> Option<String> {
+        let start = self.pos;
+        while self.peek().is_some_and(&f) {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&self.input[start..self.pos]).into_owned())
+    }
+
+    fn token(&mut self) -> Option<String> {
+        self.take_while(is_tchar)
+    }
+
+    fn token68(&mut self) -> Option<String> {
+        let mut t = self.take_while(is_token68_char)?;
+        if let Some(padding) = self.take_while(|c| c == b'=') {
+            t += &padding;
+        }
+        Some(t)
+    }
+
+    fn quoted_string(&mut self) -> Result<String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut value = vec![];
+        loop {
+            match self.peek() {
+                None => bail!("unterminated quoted-string at offset {}", start),
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(c) => value.push(c),
+                        None => bail!("unterminated quoted-string at offset {}", start),
+                    }
+                }
+                Some(c) => value.push(c),
+            }
+            self.pos += 1;
+        }
+        Ok(String::from_utf8(value)?)
+    }
+
+    /// Try to parse an `auth-param`, rewinding if input does not start with one.
+    fn auth_param(&mut self) -> Result<Option<(String, String)>> {
+        let start = self.pos;
+        let name = match self.token() {
+            Some(n) => n,
+            None => return Ok(None),
+        };
+        self.skip_whitespace();
+        if self.peek() != Some(b'=') {
+            self.pos = start;
+            return Ok(None);
+        }
+        self.pos += 1;
+        self.skip_whitespace();
+        let value = match self.peek() {
+            // This is a token68 with padding, not a parameter.
+            None | Some(b',') | Some(b'=') => {
+                self.pos = start;
+                return Ok(None);
+            }
+            Some(b'"') => self.quoted_string()?,
+            Some(_) => match self.token() {
+                Some(v) => v,
+                None => bail!("invalid value for parameter '{}'", name),
+            },
+        };
+        Ok(Some((name.to_ascii_lowercase(), value)))
+    }
+
+    fn challenge(&mut self) -> Result<Challenge> {
+        let scheme = match self.token() {
+            Some(s) => s.to_ascii_lowercase(),
+            None => bail!("invalid auth-scheme at offset {}", self.pos),
+        };
+        let mut challenge = Challenge {
+            scheme,
+            ..Challenge::default()
+        };
+        if self.skip_whitespace() == 0 || self.is_eof() || self.peek() == Some(b',') {
+            return Ok(challenge);
+        }
+
+        match self.auth_param()? {
+            Some((k, v)) => {
+                challenge.params.insert(k, v);
+            }
+            None => {
+                challenge.token68 = self.token68();
+                if challenge.token68.is_none() {
+                    bail!("invalid challenge parameters at offset {}", self.pos);
+                }
+                return Ok(challenge);
+            }
+        };
+
+        // Further parameters, until the next challenge starts.
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b',') {
+                break;
+            }
+            self.skip_separators();
+            match self.auth_param()? {
+                Some((k, v)) => {
+                    challenge.params.insert(k, v);
+                }
+                None => break,
+            }
+        }
+        Ok(challenge)
+    }
+}
+
+fn is_tchar(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
+fn is_token68_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"-._~+/".contains(&c)
+}
@@ -1,35 +1,68 @@
-use hyper::client;
+use std::time::Duration;
+use std::{fs, path};
+use v2::proxy::{ProxyConnector, ProxySettings};
+use v2::tls::TlsSettings;
 use v2::*;
 
+/// Default directory for per-registry TLS certificates, following docker conventions.
+pub static DOCKER_CERTS_DIR: &str = "/etc/docker/certs.d";
+
 /// Configuration for a `Client`.
 #[derive(Debug)]
 pub struct Config {
-    config: client::Client<hyper_rustls::HttpsConnector<client::HttpConnector>, hyper::Body>,
     handle: reactor::Handle,
     index: String,
     insecure_registry: bool,
+    insecure_fallback: Option<bool>,
     user_agent: Option<String>,
     username: Option<String>,
     password: Option<String>,
+    identity_token: Option<String>,
+    offline_token: bool,
+    client_id: String,
+    tls: TlsSettings,
+    certs_dir: Option<path::PathBuf>,
+    proxy: Option<String>,
+    no_proxy: Option<String>,
+    env_proxy: bool,
+    retry_policy: RetryPolicy,
+    connect_timeout: Option<Duration>,
+    metadata_timeouts: Timeouts,
+    blob_timeouts: Timeouts,
+    mirrors: Vec<String>,
 }
 
 impl Config {
     /// Initialize `Config` with default values.
     pub fn default(handle: &reactor::Handle) -> Self {
-        let dns_threads = 4;
         Self {
-            config: hyper::client::Client::builder()
-                .build(hyper_rustls::HttpsConnector::new(dns_threads)),
             handle: handle.clone(),
             index: "registry-1.docker.io".into(),
             insecure_registry: false,
+            insecure_fallback: None,
             user_agent: Some(::USER_AGENT.to_owned()),
             username: None,
             password: None,
+            identity_token: None,
+            offline_token: false,
+            client_id: ::CLIENT_ID.to_owned(),
+            tls: TlsSettings::default(),
+            certs_dir: None,
+            proxy: None,
+            no_proxy: None,
+            env_proxy: true,
+            retry_policy: RetryPolicy::default(),
+            connect_timeout: Some(Duration::from_secs(30)),
+            metadata_timeouts: Timeouts::metadata(),
+            blob_timeouts: Timeouts::blob(),
+            mirrors: vec![],
         }
     }
 
     /// Set registry service to use (vhost or IP).
+    ///
+    /// Registry names are mapped to their API endpoint, e.g. `docker.io`
+    /// is served by `registry-1.docker.io`.
     pub fn registry(mut self, reg: &str) -> Self {
         self.index = reg.to_owned();
         self
@@ -41,7 +74,35 @@ impl Config {
         self
     }
 
+    /// Whether to fall back to plain HTTP if the TLS handshake with the registry fails.
+    ///
+    /// Only requests without a body fall back, and refused or timed out
+    /// connections are not retried over plain HTTP. This matches the
+    /// behaviour of docker for insecure registries, and is
+    /// enabled by default for registries on loopback addresses, such as
+    /// `localhost:5000` or `127.0.0.1:5000`. Loopback IP addresses are reached
+    /// over plain HTTP directly, as TLS requires a DNS name. Certificates are
+    /// still verified when connecting over HTTPS, unless
+    /// `danger_accept_invalid_certs` is set.
+    pub fn insecure_fallback(mut self, fallback: bool) -> Self {
+        self.insecure_fallback = Some(fallback);
+        self
+    }
+
+    /// Set mirrors to try before the registry for read operations.
+    ///
+    /// Each mirror is either a `host[:port]`, reached over HTTPS, or a full
+    /// URL. Manifests and blobs are fetched from the first mirror which has
+    /// them, falling back to the registry on failure or missing content.
+    /// Mirrors are accessed anonymously, as pull-through caches usually are.
+    pub fn mirrors(mut self, mirrors: Vec<String>) -> Self {
+        self.mirrors = mirrors;
+        self
+    }
+
     /// Set the user-agent to be used for registry authentication.
+    ///
+    /// If `None`, no user-agent header is sent.
     pub fn user_agent(mut self, user_agent: Option<String>) -> Self {
         self.user_agent = user_agent;
         self
@@ -59,21 +120,187 @@ impl Config {
         self
     }
 
+    /// Set the OAuth2 identity token to be used for registry authentication.
+    ///
+    /// When set, it is exchanged at the token endpoint as a refresh token,
+    /// and takes precedence over username and password.
+    pub fn identity_token(mut self, token: Option<String>) -> Self {
+        self.identity_token = token;
+        self
+    }
+
+    /// Whether to request a refresh token when logging in with a password.
+    ///
+    /// This uses the OAuth2 password grant with offline access, falling back
+    /// to the basic token flow for registries which do not support it.
+    pub fn offline_token(mut self, offline: bool) -> Self {
+        self.offline_token = offline;
+        self
+    }
+
+    /// Set the client identifier sent to OAuth2 token endpoints.
+    pub fn client_id(mut self, client_id: &str) -> Self {
+        self.client_id = client_id.to_owned();
+        self
+    }
+
+    /// Trust additional PEM-encoded CA certificates.
+    ///
+    /// The buffer may contain a bundle of several certificates. These are
+    /// trusted in addition to the built-in web roots.
+    pub fn add_root_certificate(mut self, pem: &[u8]) -> Self {
+        self.tls.roots.push(pem.to_vec());
+        self
+    }
+
+    /// Set a PEM-encoded client certificate and private key for TLS authentication.
+    pub fn client_identity(mut self, cert_pem: &[u8], key_pem: &[u8]) -> Self {
+        self.tls.identity = Some((cert_pem.to_vec(), key_pem.to_vec()));
+        self
+    }
+
+    /// Whether to accept invalid TLS certificates from the registry.
+    ///
+    /// This disables all certificate verification, and should only be
+    /// used for testing purposes.
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.tls.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Set the directory to look for per-registry certificates in.
+    ///
+    /// Following docker conventions, certificates for a registry are read
+    /// from `<dir>/<registry>/`: `*.crt` files are trusted as CA certificates,
+    /// and each `<name>.cert` file is used as a client certificate along with
+    /// the `<name>.key` private key. The lookup is disabled by default, pass
+    /// `DOCKER_CERTS_DIR` to use the same certificates as the docker daemon.
+    pub fn certs_dir(mut self, dir: Option<path::PathBuf>) -> Self {
+        self.certs_dir = dir;
+        self
+    }
+
+    /// Set a proxy to use for all connections to the registry.
+    ///
+    /// The proxy URL may include credentials for basic authentication,
+    /// and defaults to the `http` scheme. This takes precedence over proxies
+    /// set in the environment.
+    pub fn proxy(mut self, proxy: Option<String>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Set hosts which should be reached without a proxy.
+    ///
+    /// This is a comma-separated list in `NO_PROXY` format: domains (also
+    /// matching their subdomains, optionally with a port), IP addresses,
+    /// networks in CIDR notation, or `*` to disable proxying. This takes
+    /// precedence over the `NO_PROXY` environment variable.
+    pub fn no_proxy(mut self, no_proxy: Option<String>) -> Self {
+        self.no_proxy = no_proxy;
+        self
+    }
+
+    /// Whether to use proxies from the environment.
+    ///
+    /// If enabled (the default), `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY`
+    /// (or their lowercase variants) are honoured when no explicit proxy is set.
+    pub fn env_proxy(mut self, enabled: bool) -> Self {
+        self.env_proxy = enabled;
+        self
+    }
+
+    /// Set the policy for retrying failed idempotent requests.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Set the maximum time to establish a connection to the registry.
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Set timeouts for metadata calls, e.g. for manifests, tags or authentication.
+    ///
+    /// Defaults to `Timeouts::metadata()`.
+    pub fn metadata_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.metadata_timeouts = timeouts;
+        self
+    }
+
+    /// Set timeouts for blob transfers.
+    ///
+    /// Defaults to `Timeouts::blob()`.
+    pub fn blob_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.blob_timeouts = timeouts;
+        self
+    }
+
     /// Read credentials from a JSON config file
-    pub fn read_credentials<T: ::std::io::Read>(mut self, reader: T) -> Self {
-        if let Ok(creds) = ::get_credentials(reader, &self.index) {
-            self.username = creds.0;
-            self.password = creds.1;
+    pub fn read_credentials<T: ::std::io::Read>(self, reader: T) -> Self {
+        self.read_credentials_with(reader, &::credentials::ExecHelper)
+    }
+
+    /// Read credentials from a JSON config file, using a custom executor
+    /// for credential helpers.
+    pub fn read_credentials_with<T, H>(mut self, reader: T, helper: &H) -> Self
+    where
+        T: ::std::io::Read,
+        H: ::credentials::CredentialHelper,
+    {
+        if let Ok(creds) = ::credentials::read_credentials_with(reader, &self.index, helper) {
+            self.username = creds.username;
+            self.password = creds.password;
+            self.identity_token = creds.identity_token;
+        };
+        self
+    }
+
+    /// Resolve credentials from standard auth files.
+    ///
+    /// If `repository` is given, credentials scoped to that repository
+    /// in the configured registry take precedence.
+    pub fn resolve_credentials(
+        self,
+        resolver: &::credentials::AuthResolver,
+        repository: Option<&str>,
+    ) -> Self {
+        self.resolve_credentials_with(resolver, repository, &::credentials::ExecHelper)
+    }
+
+    /// Resolve credentials from standard auth files, using a custom executor
+    /// for credential helpers.
+    pub fn resolve_credentials_with<H: ::credentials::CredentialHelper>(
+        mut self,
+        resolver: &::credentials::AuthResolver,
+        repository: Option<&str>,
+        helper: &H,
+    ) -> Self {
+        let key = match repository {
+            Some(r) => format!("{}/{}", self.index, r),
+            None => self.index.clone(),
+        };
+        if let Ok(Some(creds)) = resolver.resolve_with(&key, helper) {
+            self.username = creds.username;
+            self.password = creds.password;
+            self.identity_token = creds.identity_token;
         };
         self
     }
 
     /// Return a `Client` to interact with a v2 registry.
-    pub fn build(self) -> Result<Client> {
+    pub fn build(mut self) -> Result<Client> {
+        let fallback = !self.insecure_registry
+            && self
+                .insecure_fallback
+                .unwrap_or_else(|| fallback::is_local_registry(&self.index));
+        let endpoint = ::reference::registry_endpoint(&self.index);
         let base = if self.insecure_registry {
-            "http://".to_string() + &self.index
+            "http://".to_string() + endpoint
         } else {
-            "https://".to_string() + &self.index
+            "https://".to_string() + endpoint
         };
         trace!(
             "Built client for {:?}: endpoint {:?} - user {:?}",
@@ -81,6 +308,24 @@ impl Config {
             base,
             self.username
         );
+        if let Some(dir) = self.certs_dir.take() {
+            self.read_certs_dir(&dir.join(&self.index))?;
+        }
+        let proxies = match self.proxy {
+            Some(ref p) => ProxySettings::explicit(p, self.no_proxy.as_deref().unwrap_or(""))?,
+            None if self.env_proxy => ProxySettings::from_env(self.no_proxy.as_deref())?,
+            None => ProxySettings::default(),
+        };
+        let proxies = Arc::new(proxies);
+        let dns_threads = 4;
+        let mut http = client::HttpConnector::new(dns_threads);
+        http.enforce_http(false);
+        http.set_connect_timeout(self.connect_timeout);
+        let connector = ProxyConnector::new(http, proxies.clone());
+        let tls = Arc::new(self.tls.client_config()?);
+        let https = hyper_rustls::HttpsConnector::from((connector, tls));
+        let hclient = client::Client::builder().build(https);
+
         let creds = match (self.username, self.password) {
             (None, None) => None,
             (u, p) => Some((
@@ -88,14 +333,61 @@ impl Config {
                 p.unwrap_or_else(|| "".into()),
             )),
         };
-        let c = Client {
+        let mut c = Client {
             base_url: base,
             credentials: creds,
-            hclient: self.config,
-            index: self.index,
+            identity_token: self.identity_token,
+            offline_token: self.offline_token,
+            client_id: self.client_id,
+            hclient,
+            proxies,
             user_agent: self.user_agent,
             token: None,
+            auth_scheme: AuthScheme::default(),
+            handle: self.handle,
+            retry_policy: self.retry_policy,
+            connect_timeout: self.connect_timeout,
+            metadata_timeouts: self.metadata_timeouts,
+            blob_timeouts: self.blob_timeouts,
+            fallback: if fallback {
+                Some(Arc::new(fallback::Fallback::default()))
+            } else {
+                None
+            },
+            mirrors: vec![],
         };
+        c.mirrors = self.mirrors.iter().map(|m| c.mirror_client(m)).collect();
         Ok(c)
     }
+
+    /// Load CA and client certificates from a docker-style certificates directory.
+    fn read_certs_dir(&mut self, dir: &path::Path) -> Result<()> {
+        let entries = match fs::read_dir(dir) {
+            Ok(e) => e,
+            Err(ref e) if e.kind() == ::std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut paths = entries
+            .map(|e| e.map(|e| e.path()))
+            .collect::<::std::io::Result<Vec<_>>>()?;
+        paths.sort();
+        for p in paths {
+            match p.extension().and_then(|e| e.to_str()) {
+                Some("crt") => {
+                    trace!("Trusting CA certificate {:?}", p);
+                    self.tls.roots.push(fs::read(&p)?);
+                }
+                Some("cert") if self.tls.identity.is_none() => {
+                    let key_path = p.with_extension("key");
+                    if !key_path.exists() {
+                        bail!("missing client key {:?} for certificate {:?}", key_path, p);
+                    }
+                    trace!("Using client certificate {:?}", p);
+                    self.tls.identity = Some((fs::read(&p)?, fs::read(&key_path)?));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
 }
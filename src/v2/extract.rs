@@ -0,0 +1,115 @@
+use digest::Digest;
+use futures::future::{self, Either, Loop};
+use mediatypes::MediaTypes;
+use reference::Reference;
+use render::FileFinder;
+use std::collections::HashMap;
+use v2::manifest::{ManifestList, ManifestSchema1Signed, ManifestSchema2, Platform};
+use v2::*;
+
+/// Convenience alias for future file contents, by requested path.
+pub type FutureFiles = Box<futures::Future<Item = HashMap<String, Vec<u8>>, Error = Error>>;
+
+impl Client {
+    /// Extract individual files from an image, without unpacking it.
+    ///
+    /// Paths are relative to the root of the image. Layers are fetched
+    /// one at a time from the top one, applying whiteouts, and no more
+    /// layers are fetched once all files are found. Symlinks are followed
+    /// within the image. For multi-platform images, the manifest for the
+    /// current platform is used.
+    ///
+    /// The result maps each requested path to the content of its file.
+    /// Paths which are missing, or which are not regular files, are left out.
+    pub fn extract_files(&self, reference: &Reference, paths: &[&str]) -> FutureFiles {
+        let finder = match FileFinder::new(paths) {
+            Ok(f) => f,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        let client = self.clone();
+        let name = reference.repository();
+        let fres = self
+            .get_platform_manifest(&name, &reference.version())
+            .and_then(|body| manifest_layers(&body))
+            .and_then(move |layers| {
+                let state = (client, name, layers, finder, 0);
+                future::loop_fn(state, |(client, name, layers, mut finder, n)| {
+                    let next: Box<futures::Future<Item = _, Error = _>> =
+                        if finder.is_pending() && n < layers.len() {
+                            let digest = &layers[layers.len() - 1 - n];
+                            trace!("Looking up files in layer {}", digest);
+                            Box::new(client.get_blob(&name, digest).and_then(move |blob| {
+                                finder.add_layer(blob.as_slice())?;
+                                Ok(Loop::Continue((client, name, layers, finder, n + 1)))
+                            }))
+                        } else if finder.restart() {
+                            // Follow links to upper layers, from the top again.
+                            let state = (client, name, layers, finder, 0);
+                            Box::new(future::ok(Loop::Continue(state)))
+                        } else {
+                            Box::new(future::ok(Loop::Break(finder.into_files())))
+                        };
+                    next
+                })
+            });
+        Box::new(fres)
+    }
+
+    /// Fetch an image manifest, resolving manifest lists and OCI indexes
+    /// to the manifest for the current platform.
+    fn get_platform_manifest(&self, name: &str, reference: &str) -> FutureManifest {
+        let client = self.clone();
+        let name = name.to_string();
+        let fres = self
+            .get_manifest_accepting(&name, reference, MANIFEST_TYPES)
+            .and_then(move |body| match platform_manifest(&body) {
+                Ok(Some(digest)) => {
+                    trace!("Using manifest {} for the current platform", digest);
+                    let reference = digest.to_string();
+                    Either::A(client.get_manifest_accepting(&name, &reference, MANIFEST_TYPES))
+                }
+                Ok(None) => Either::B(future::ok(body)),
+                Err(e) => Either::B(future::err(e)),
+            });
+        Box::new(fres)
+    }
+}
+
+/// Manifest types accepted when extracting files.
+static MANIFEST_TYPES: &[MediaTypes] = &[
+    MediaTypes::ManifestV2S2,
+    MediaTypes::OciImageManifest,
+    MediaTypes::ManifestList,
+    MediaTypes::OciImageIndex,
+];
+
+/// Digest of the manifest for the current platform, if `body` is a manifest list or OCI index.
+fn platform_manifest(body: &[u8]) -> Result<Option<Digest>> {
+    let value: serde_json::Value = serde_json::from_slice(body)?;
+    if value.get("manifests").is_none() {
+        return Ok(None);
+    }
+    let list: ManifestList = serde_json::from_value(value)?;
+    let platform = Platform::current();
+    match list.find_platform(&platform) {
+        Some(m) => Ok(Some(m.digest.clone())),
+        None => bail!(
+            "no manifest for platform {}/{}",
+            platform.os,
+            platform.architecture
+        ),
+    }
+}
+
+/// Layers of an image manifest, lower ones first.
+fn manifest_layers(body: &[u8]) -> Result<Vec<Digest>> {
+    let value: serde_json::Value = serde_json::from_slice(body)?;
+    match value.get("schemaVersion").and_then(|v| v.as_u64()) {
+        Some(1) => Ok(serde_json::from_value::<ManifestSchema1Signed>(value)?.get_layers()),
+        Some(2) if value.get("layers").is_some() => {
+            Ok(serde_json::from_value::<ManifestSchema2>(value)?.get_layers())
+        }
+        Some(2) => bail!("unsupported manifest: no layers"),
+        _ => bail!("unknown manifest schema version"),
+    }
+}
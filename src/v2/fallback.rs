@@ -0,0 +1,128 @@
+//! Plain HTTP fallback for insecure registries.
+
+use futures::{self, future, Future};
+use http::uri::Scheme;
+use hyper::{self, body::Payload};
+use rustls;
+use std::error::Error as StdError;
+use std::io;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use v2::*;
+
+/// Convenience alias for future HTTP response, before error conversion.
+type FutureHttpResponse =
+    Box<futures::Future<Item = hyper::Response<hyper::Body>, Error = hyper::Error>>;
+
+/// State of the HTTPS-to-HTTP fallback, shared between clones of a `Client`.
+#[derive(Debug, Default)]
+pub(crate) struct Fallback {
+    use_http: AtomicBool,
+}
+
+impl Client {
+    /// Execute a request, falling back to plain HTTP if the registry cannot be reached over HTTPS.
+    ///
+    /// Only requests without a body fall back, and only when the TLS handshake
+    /// failed, e.g. because the registry speaks plain HTTP. Refused or timed
+    /// out connections are returned as is. Once a fallback happened, further
+    /// requests to the registry use plain HTTP directly.
+    pub(crate) fn execute_with_fallback(
+        &self,
+        mut req: hyper::Request<hyper::Body>,
+    ) -> FutureHttpResponse {
+        let fallback = match self.fallback {
+            Some(ref f) if self.is_registry_url(req.uri()) => f.clone(),
+            _ => return Box::new(self.dispatch(req)),
+        };
+        // The TLS connector only accepts DNS names, so HTTPS to a loopback address cannot work.
+        if fallback.use_http.load(Ordering::Relaxed) || is_loopback_host(req.uri()) {
+            set_http(req.uri_mut());
+            return Box::new(self.dispatch(req));
+        }
+        if req.uri().scheme_part() != Some(&Scheme::HTTPS) || !req.body().is_end_stream() {
+            return Box::new(self.dispatch(req));
+        }
+
+        let mut http_req = retry::clone_request(&req);
+        set_http(http_req.uri_mut());
+        let client = self.clone();
+        let fres = self.dispatch(req).or_else(move |e| {
+            if !is_tls_error(&e) {
+                return future::Either::A(future::err(e));
+            }
+            warn!(
+                "HTTPS request to insecure registry failed ({}), falling back to {}",
+                e,
+                http_req.uri()
+            );
+            let fres = client.dispatch(http_req).inspect(move |_| {
+                fallback.use_http.store(true, Ordering::Relaxed);
+            });
+            future::Either::B(fres)
+        });
+        Box::new(fres)
+    }
+
+    /// Whether a URL points to the registry itself, rather than e.g. an auth server.
+    fn is_registry_url(&self, uri: &hyper::Uri) -> bool {
+        match url::Url::parse(&self.base_url) {
+            Ok(base) => {
+                base.host_str() == uri.host() && base.port() == uri.port_part().map(|p| p.as_u16())
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+fn is_loopback_host(uri: &hyper::Uri) -> bool {
+    uri.host()
+        .map(|h| h.trim_start_matches('[').trim_end_matches(']'))
+        .and_then(|h| h.parse::<IpAddr>().ok())
+        .is_some_and(|ip| ip.is_loopback())
+}
+
+/// Whether a request failed because of a TLS error, rather than e.g. a refused connection.
+fn is_tls_error(err: &hyper::Error) -> bool {
+    let mut source = err.source();
+    while let Some(e) = source {
+        if e.is::<rustls::TLSError>() {
+            return true;
+        }
+        // Custom I/O errors hide the wrapped error from `source`.
+        source = match e.downcast_ref::<io::Error>() {
+            Some(io) => io.get_ref().map(|e| e as &(StdError + 'static)),
+            None => e.source(),
+        };
+    }
+    false
+}
+
+fn set_http(uri: &mut hyper::Uri) {
+    if uri.scheme_part() != Some(&Scheme::HTTPS) {
+        return;
+    }
+    let mut parts = uri.clone().into_parts();
+    parts.scheme = Some(Scheme::HTTP);
+    if let Ok(http) = hyper::Uri::from_parts(parts) {
+        *uri = http;
+    }
+}
+
+/// Whether a registry is on a loopback address, and insecure by default.
+pub(crate) fn is_local_registry(index: &str) -> bool {
+    let url = match url::Url::parse(&format!("http://{}", index)) {
+        Ok(u) => u,
+        Err(_) => return false,
+    };
+    match url.host_str() {
+        Some("localhost") => true,
+        Some(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map(|ip| ip.is_loopback())
+            .unwrap_or(false),
+        None => false,
+    }
+}
@@ -1,3 +1,4 @@
+use digest::Digest;
 use std::collections::HashMap;
 use v2::*;
 
@@ -36,14 +37,14 @@ struct V1Compat {
 #[derive(Debug, Deserialize, Serialize)]
 struct S1Layer {
     #[serde(rename = "blobSum")]
-    blob_sum: String,
+    blob_sum: Digest,
 }
 
 impl ManifestSchema1Signed {
     /// List digests of all layers referenced by this manifest.
     ///
     /// The returned layers list is ordered starting with the base image first.
-    pub fn get_layers(&self) -> Vec<String> {
+    pub fn get_layers(&self) -> Vec<Digest> {
         self.fs_layers
             .iter()
             .rev()
@@ -1,50 +1,63 @@
+use digest::Digest;
+use std::env;
+
 /// Manifest version 2 schema 2.
 ///
 /// Specification is at https://docs.docker.com/registry/spec/manifest-v2-2/.
-#[derive(Debug, Default, Deserialize, Serialize)]
+/// OCI image manifests share this format, with an optional media type.
+///
+/// This type does not implement `Default`, as its digests have no
+/// meaningful default value.
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ManifestSchema2 {
     #[serde(rename = "schemaVersion")]
     schema_version: u16,
-    #[serde(rename = "mediaType")]
+    #[serde(rename = "mediaType", default)]
     media_type: String,
     config: Config,
     layers: Vec<S2Layer>,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     #[serde(rename = "mediaType")]
     media_type: String,
     size: u64,
-    digest: String,
+    digest: Digest,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize)]
 struct S2Layer {
     #[serde(rename = "mediaType")]
     media_type: String,
     size: u64,
-    digest: String,
+    digest: Digest,
     urls: Option<Vec<String>>,
 }
 
 /// Manifest List.
+///
+/// OCI image indexes share this format, with an optional media type.
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ManifestList {
     #[serde(rename = "schemaVersion")]
     schema_version: u16,
-    #[serde(rename = "mediaType")]
+    #[serde(rename = "mediaType", default)]
     media_type: String,
     pub manifests: Vec<ManifestObj>,
 }
 
 /// Manifest object.
-#[derive(Debug, Default, Deserialize, Serialize)]
+///
+/// This type does not implement `Default`, as its digest has no meaningful
+/// default value.
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ManifestObj {
     #[serde(rename = "mediaType")]
     media_type: String,
     size: u64,
-    pub digest: String,
+    pub digest: Digest,
+    #[serde(default)]
     pub platform: Platform,
 }
 
@@ -65,12 +78,49 @@ impl ManifestSchema2 {
     /// List digests of all layers referenced by this manifest.
     ///
     /// The returned layers list is ordered starting with the base image first.
-    pub fn get_layers(&self) -> Vec<String> {
+    pub fn get_layers(&self) -> Vec<Digest> {
         self.layers.iter().map(|l| l.digest.clone()).collect()
     }
 
     /// Get digest of the configuration object referenced by this manifest.
-    pub fn config(&self) -> String {
+    pub fn config(&self) -> Digest {
         self.config.digest.clone()
     }
 }
+
+impl ManifestList {
+    /// Find the manifest for a platform, matching its OS and architecture.
+    ///
+    /// The variant is only compared if set on `platform`.
+    pub fn find_platform(&self, platform: &Platform) -> Option<&ManifestObj> {
+        self.manifests.iter().find(|m| {
+            m.platform.os == platform.os
+                && m.platform.architecture == platform.architecture
+                && (platform.variant.is_none() || m.platform.variant == platform.variant)
+        })
+    }
+}
+
+impl Platform {
+    /// The platform this library was compiled for, with OCI names.
+    pub fn current() -> Self {
+        let os = match env::consts::OS {
+            "macos" => "darwin",
+            os => os,
+        };
+        let architecture = match env::consts::ARCH {
+            "x86_64" => "amd64",
+            "x86" => "386",
+            "aarch64" => "arm64",
+            "powerpc64" if cfg!(target_endian = "little") => "ppc64le",
+            "powerpc64" => "ppc64",
+            "mips64" if cfg!(target_endian = "little") => "mips64le",
+            arch => arch,
+        };
+        Platform {
+            architecture: architecture.to_string(),
+            os: os.to_string(),
+            ..Platform::default()
+        }
+    }
+}
@@ -2,7 +2,6 @@
 use mediatypes;
 use v2::*;
 
-use futures::Stream;
 use hyper::header;
 use hyper::StatusCode;
 use mime;
@@ -19,7 +18,31 @@ impl Client {
     /// The name and reference parameters identify the image.
     /// The reference may be either a tag or digest.
     pub fn get_manifest(&self, name: &str, reference: &str) -> FutureManifest {
-        let url = match hyper::Uri::from_str(&format!(
+        self.get_manifest_accepting(name, reference, &[mediatypes::MediaTypes::ManifestV2S2])
+    }
+
+    /// Fetch an image manifest, accepting any of the given media types.
+    pub(crate) fn get_manifest_accepting(
+        &self,
+        name: &str,
+        reference: &str,
+        mediatypes: &[mediatypes::MediaTypes],
+    ) -> FutureManifest {
+        let (name, reference) = (name.to_string(), reference.to_string());
+        let mtypes: Vec<String> = mediatypes.iter().map(|m| m.to_string()).collect();
+        self.with_mirrors(
+            move |c| c.get_manifest_direct(&name, &reference, &mtypes),
+            |_| false,
+        )
+    }
+
+    fn get_manifest_direct(
+        &self,
+        name: &str,
+        reference: &str,
+        mtypes: &[String],
+    ) -> FutureManifest {
+        let url = match url::Url::parse(&format!(
             "{}/v2/{}/manifests/{}",
             self.base_url.clone(),
             name,
@@ -41,23 +64,24 @@ impl Client {
                     return Box::new(futures::future::err(Error::from(msg)));
                 }
             };
-            let mtype = mediatypes::MediaTypes::ManifestV2S2.to_string();
-            req.headers_mut().append(
-                header::ACCEPT,
-                match header::HeaderValue::from_str(&mtype) {
-                    Ok(headervalue) => headervalue,
-                    Err(e) => {
-                        let msg = format!("failed to parse HeaderValue from str: {}:", e);
-                        error!("{}", msg);
-                        return Box::new(futures::future::err::<_, _>(Error::from(msg)));
-                    }
-                },
-            );
+            for mtype in mtypes {
+                req.headers_mut().append(
+                    header::ACCEPT,
+                    match header::HeaderValue::from_str(mtype) {
+                        Ok(headervalue) => headervalue,
+                        Err(e) => {
+                            let msg = format!("failed to parse HeaderValue from str: {}:", e);
+                            error!("{}", msg);
+                            return Box::new(futures::future::err::<_, _>(Error::from(msg)));
+                        }
+                    },
+                );
+            }
             req
         };
-        let freq = self.hclient.request(req);
+        let client = self.clone();
+        let freq = self.execute(req, Operation::Metadata);
         let fres = freq
-            .from_err()
             .inspect(move |_| {
                 trace!("GET {:?}", url);
             }).and_then(|r| {
@@ -67,12 +91,8 @@ impl Client {
                     hyper::StatusCode::OK => Ok(r),
                     _ => Err(format!("get_manifest: wrong HTTP status '{}'", status).into()),
                 }
-            }).and_then(|r| {
-                r.into_body().concat2().map_err(|e| {
-                    format!("get_manifest: failed to fetch the whole body: {}", e).into()
-                })
-            }).and_then(|body| Ok(body.into_bytes().to_vec()));
-        Box::new(fres)
+            }).and_then(move |r| client.read_body(r, Operation::Metadata));
+        Box::new(self.with_timeout(fres, Operation::Metadata))
     }
 
     /// Check if an image manifest exists.
@@ -84,6 +104,26 @@ impl Client {
         name: &str,
         reference: &str,
         mediatypes: Option<&[&str]>,
+    ) -> mediatypes::FutureMediaType {
+        let (name, reference) = (name.to_string(), reference.to_string());
+        let mediatypes: Option<Vec<String>> =
+            mediatypes.map(|m| m.iter().map(|s| s.to_string()).collect());
+        self.with_mirrors(
+            move |c| {
+                let mediatypes: Option<Vec<&str>> = mediatypes
+                    .as_ref()
+                    .map(|m| m.iter().map(String::as_str).collect());
+                c.has_manifest_direct(&name, &reference, mediatypes.as_deref())
+            },
+            Option::is_none,
+        )
+    }
+
+    fn has_manifest_direct(
+        &self,
+        name: &str,
+        reference: &str,
+        mediatypes: Option<&[&str]>,
     ) -> mediatypes::FutureMediaType {
         let url = {
             let ep = format!(
@@ -92,7 +132,7 @@ impl Client {
                 name,
                 reference
             );
-            match hyper::Uri::from_str(ep.as_str()) {
+            match url::Url::parse(&ep) {
                 Ok(url) => url,
                 Err(e) => {
                     let msg = format!("failed to parse Uri from str: {}", e);
@@ -133,13 +173,12 @@ impl Client {
             };
             for v in accept_types {
                 let _ = header::HeaderValue::from_str(&v.to_string())
-                    .map(|hval| req.headers_mut().append(hyper::header::ACCEPT, hval));
+                    .map(|hval| req.headers_mut().append(header::ACCEPT, hval));
             }
             req
         };
-        let freq = self.hclient.request(req);
+        let freq = self.execute(req, Operation::Metadata);
         let fres = freq
-            .from_err()
             .inspect(move |_| {
                 trace!("HEAD {:?}", url);
             }).and_then(|r| {
@@ -161,7 +200,7 @@ impl Client {
                 };
                 Ok(res)
             });
-        Box::new(fres)
+        Box::new(self.with_timeout(fres, Operation::Metadata))
     }
 }
 
@@ -0,0 +1,69 @@
+//! Registry mirrors, with fallback to the upstream registry.
+
+use futures::{self, future, Future};
+use std::rc::Rc;
+use std::sync::Arc;
+use v2::*;
+
+impl Client {
+    /// Return an anonymous client for a mirror of this registry.
+    ///
+    /// The mirror is either a `host[:port]`, reached over HTTPS, or a full URL.
+    pub(crate) fn mirror_client(&self, mirror: &str) -> Client {
+        let mirror = mirror.trim_end_matches('/');
+        let (base_url, fallback) = if mirror.contains("://") {
+            (mirror.to_string(), false)
+        } else {
+            let local = fallback::is_local_registry(mirror);
+            (format!("https://{}", mirror), local)
+        };
+        let mut client = self.clone();
+        client.base_url = base_url;
+        client.credentials = None;
+        client.identity_token = None;
+        client.token = None;
+        client.auth_scheme = AuthScheme::default();
+        client.fallback = if fallback {
+            Some(Arc::new(fallback::Fallback::default()))
+        } else {
+            None
+        };
+        client.mirrors = vec![];
+        client
+    }
+
+    /// Run a read operation against each mirror in order, then against this registry.
+    ///
+    /// Mirrors are skipped when the operation fails, or when `is_miss`
+    /// reports that the content was not found.
+    pub(crate) fn with_mirrors<T, F, M>(
+        &self,
+        op: F,
+        is_miss: M,
+    ) -> Box<futures::Future<Item = T, Error = Error>>
+    where
+        T: 'static,
+        F: Fn(&Client) -> Box<futures::Future<Item = T, Error = Error>> + 'static,
+        M: Fn(&T) -> bool + 'static,
+    {
+        let (op, is_miss) = (Rc::new(op), Rc::new(is_miss));
+        let upstream = self.clone();
+        let upstream_op = op.clone();
+        let mut fres: Box<futures::Future<Item = T, Error = Error>> =
+            Box::new(future::lazy(move || upstream_op(&upstream)));
+        for mirror in self.mirrors.iter().rev() {
+            let (mirror, op, is_miss) = (mirror.clone(), op.clone(), is_miss.clone());
+            let next = fres;
+            let url = mirror.base_url.clone();
+            fres = Box::new(future::lazy(move || op(&mirror)).then(move |res| {
+                match res {
+                    Ok(ref v) if !is_miss(v) => return future::Either::A(future::result(res)),
+                    Ok(_) => trace!("Content not found on mirror {}", url),
+                    Err(ref e) => trace!("Request to mirror {} failed: {}", url, e),
+                };
+                future::Either::B(next)
+            }));
+        }
+        fres
+    }
+}
@@ -35,18 +35,39 @@ use hyper::{self, client, header};
 use hyper_rustls;
 use serde_json;
 use tokio_core::reactor;
+use url;
 
 use futures::Future;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 mod config;
-pub use self::config::Config;
+pub use self::config::{Config, DOCKER_CERTS_DIR};
 
 mod catalog;
 pub use self::catalog::StreamCatalog;
 
 mod auth;
-pub use self::auth::{FutureTokenAuth, TokenAuth};
+pub use self::auth::{AuthScheme, FutureChallenges, FutureTokenAuth, TokenAuth};
+
+mod challenge;
+pub use self::challenge::Challenge;
+
+mod fallback;
+
+mod mirrors;
+
+mod proxy;
+
+mod tls;
+
+mod retry;
+pub use self::retry::RetryPolicy;
+
+mod timeout;
+use self::timeout::Operation;
+pub use self::timeout::Timeouts;
 
 pub mod manifest;
 
@@ -54,17 +75,31 @@ mod tags;
 pub use self::tags::StreamTags;
 
 mod blobs;
-pub use self::blobs::FutureBlob;
+pub use self::blobs::{FutureBlob, StreamBlob};
+
+mod extract;
+pub use self::extract::FutureFiles;
 
 /// A Client to make outgoing API requests to a registry.
 #[derive(Clone, Debug)]
 pub struct Client {
     base_url: String,
     credentials: Option<(String, String)>,
-    hclient: client::Client<hyper_rustls::HttpsConnector<client::HttpConnector>>,
-    index: String,
+    identity_token: Option<String>,
+    offline_token: bool,
+    client_id: String,
+    hclient: client::Client<hyper_rustls::HttpsConnector<proxy::ProxyConnector>>,
+    proxies: Arc<proxy::ProxySettings>,
     user_agent: Option<String>,
     token: Option<String>,
+    auth_scheme: AuthScheme,
+    handle: reactor::Handle,
+    retry_policy: RetryPolicy,
+    connect_timeout: Option<Duration>,
+    metadata_timeouts: Timeouts,
+    blob_timeouts: Timeouts,
+    fallback: Option<Arc<fallback::Fallback>>,
+    mirrors: Vec<Client>,
 }
 
 /// Convenience alias for a future boolean result.
@@ -81,20 +116,29 @@ impl Client {
     fn new_request(
         &self,
         method: hyper::Method,
-        url: hyper::Uri,
+        url: url::Url,
     ) -> Result<hyper::Request<hyper::Body>> {
-        let mut req = hyper::Request::default();
-        *req.method_mut() = method;
-        *req.uri_mut() = url;
-        req.headers_mut()
-            .append(header::HOST, header::HeaderValue::from_str(&self.index)?);
+        let mut req = self.plain_request(method, url, hyper::Body::empty())?;
         if let Some(ref t) = self.token {
-            let bearer = format!("Bearer {}", t);
+            let value = auth::auth_header_value(self.auth_scheme, t);
             req.headers_mut().append(
                 header::AUTHORIZATION,
-                header::HeaderValue::from_str(&bearer)?,
+                header::HeaderValue::from_str(&value)?,
             );
         };
+        Ok(req)
+    }
+
+    /// Build a request without registry credentials, e.g. for a token endpoint.
+    fn plain_request(
+        &self,
+        method: hyper::Method,
+        url: url::Url,
+        body: hyper::Body,
+    ) -> Result<hyper::Request<hyper::Body>> {
+        let mut req = hyper::Request::new(body);
+        *req.method_mut() = method;
+        *req.uri_mut() = hyper::Uri::from_str(url.as_str())?;
         if let Some(ref ua) = self.user_agent {
             req.headers_mut()
                 .append(header::USER_AGENT, header::HeaderValue::from_str(ua)?);
@@ -106,7 +150,7 @@ impl Client {
         let api_header = "Docker-Distribution-API-Version";
         let api_version = "registry/2.0";
 
-        let url = match hyper::Uri::from_str((self.base_url.clone() + "/v2/").as_str()) {
+        let url = match url::Url::parse(&(self.base_url.clone() + "/v2/")) {
             Ok(url) => url,
             Err(e) => {
                 return Box::new(futures::future::err::<_, _>(Error::from(format!(
@@ -123,9 +167,8 @@ impl Client {
                 return Box::new(futures::future::err::<_, _>(Error::from(msg)));
             }
         };
-        let freq = self.hclient.request(req);
+        let freq = self.execute(req, Operation::Metadata);
         let fres = freq
-            .from_err()
             .inspect(move |_| {
                 trace!("GET {:?}", url);
             }).and_then(move |r| match (r.status(), r.headers().get(api_header)) {
@@ -138,7 +181,7 @@ impl Client {
             }).inspect(|b| {
                 trace!("v2 API supported: {}", b);
             });
-        Box::new(fres)
+        Box::new(self.with_timeout(fres, Operation::Metadata))
     }
 }
 
@@ -0,0 +1,347 @@
+//! Proxy selection for registry connections.
+
+use base64;
+use errors::*;
+use futures::future::{self, Loop};
+use futures::{self, Future};
+use hyper::client::connect::{Connect, Connected, Destination};
+use hyper::client::{HttpConnector, ResponseFuture};
+use hyper::{self, header};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::{env, io};
+use tokio_io::{self, AsyncRead, AsyncWrite};
+use url::{self, percent_encoding};
+use v2::Client;
+
+/// Proxies to use for plain and TLS connections, with exemptions.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ProxySettings {
+    http: Option<url::Url>,
+    https: Option<url::Url>,
+    no_proxy: NoProxy,
+}
+
+impl ProxySettings {
+    /// Use the same proxy for all connections.
+    pub(crate) fn explicit(proxy: &str, no_proxy: &str) -> Result<Self> {
+        let url = parse_proxy_url(proxy)?;
+        Ok(Self {
+            http: Some(url.clone()),
+            https: Some(url),
+            no_proxy: NoProxy::parse(no_proxy),
+        })
+    }
+
+    /// Read proxies from the standard environment variables.
+    ///
+    /// Lowercase variables take precedence over uppercase ones. An explicit
+    /// `no_proxy` list overrides the `NO_PROXY` variable.
+    pub(crate) fn from_env(no_proxy: Option<&str>) -> Result<Self> {
+        let http = match env_var(&["http_proxy", "HTTP_PROXY"]) {
+            Some(p) => Some(parse_proxy_url(&p)?),
+            None => None,
+        };
+        let https = match env_var(&["https_proxy", "HTTPS_PROXY"]) {
+            Some(p) => Some(parse_proxy_url(&p)?),
+            None => None,
+        };
+        let no_proxy = match no_proxy {
+            Some(n) => n.to_owned(),
+            None => env_var(&["no_proxy", "NO_PROXY"]).unwrap_or_default(),
+        };
+        Ok(Self {
+            http,
+            https,
+            no_proxy: NoProxy::parse(&no_proxy),
+        })
+    }
+
+    /// Proxy to use for a target URL, if any.
+    pub(crate) fn proxy_for(&self, url: &url::Url) -> Option<url::Url> {
+        let proxy = match url.scheme() {
+            "http" => self.http.as_ref(),
+            "https" => self.https.as_ref(),
+            _ => None,
+        }?;
+        if self.no_proxy.matches(url) {
+            trace!("Bypassing proxy for {}", url);
+            return None;
+        }
+        Some(proxy.clone())
+    }
+
+    /// Value of the `Proxy-Authorization` header for a proxy, if it has credentials.
+    pub(crate) fn authorization(proxy: &url::Url) -> Option<String> {
+        if proxy.username().is_empty() && proxy.password().is_none() {
+            return None;
+        }
+        let decode = |s: &str| {
+            percent_encoding::percent_decode(s.as_bytes())
+                .decode_utf8_lossy()
+                .into_owned()
+        };
+        let plain = format!(
+            "{}:{}",
+            decode(proxy.username()),
+            decode(proxy.password().unwrap_or(""))
+        );
+        Some(format!("Basic {}", base64::encode(&plain)))
+    }
+}
+
+impl Client {
+    /// Send a request with the underlying HTTP client.
+    ///
+    /// Plain HTTP requests going through a proxy carry its credentials, if
+    /// any. For HTTPS, these are sent when opening the tunnel instead.
+    pub(crate) fn dispatch(&self, mut req: hyper::Request<hyper::Body>) -> ResponseFuture {
+        let proxy = match url::Url::parse(&req.uri().to_string()) {
+            Ok(ref u) if u.scheme() == "http" => self.proxies.proxy_for(u),
+            _ => None,
+        };
+        if let Some(auth) = proxy.as_ref().and_then(ProxySettings::authorization) {
+            if let Ok(value) = header::HeaderValue::from_str(&auth) {
+                req.headers_mut().insert(header::PROXY_AUTHORIZATION, value);
+            }
+        }
+        self.hclient.request(req)
+    }
+}
+
+/// Convenience alias for a future proxied connection.
+type FutureConnection<T> = Box<futures::Future<Item = (T, Connected), Error = io::Error> + Send>;
+
+/// A connector routing connections through the configured proxies.
+///
+/// Plain HTTP requests are sent to the proxy in absolute form, while
+/// HTTPS connections are tunneled with `CONNECT`, so that TLS is still
+/// negotiated end-to-end with the registry.
+#[derive(Clone, Debug)]
+pub(crate) struct ProxyConnector {
+    http: HttpConnector,
+    proxies: Arc<ProxySettings>,
+}
+
+impl ProxyConnector {
+    pub(crate) fn new(http: HttpConnector, proxies: Arc<ProxySettings>) -> Self {
+        Self { http, proxies }
+    }
+}
+
+impl Connect for ProxyConnector {
+    type Transport = <HttpConnector as Connect>::Transport;
+    type Error = io::Error;
+    type Future = FutureConnection<Self::Transport>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let proxy = match destination_url(&dst).and_then(|u| self.proxies.proxy_for(&u)) {
+            Some(p) => p,
+            None => return Box::new(self.http.connect(dst)),
+        };
+        let mut proxy_dst = dst.clone();
+        let host = proxy.host_str().unwrap_or_default();
+        let set = proxy_dst.set_scheme("http");
+        if let Err(e) = set.and_then(|_| proxy_dst.set_host(host)) {
+            let msg = format!("invalid proxy '{}': {}", proxy, e);
+            return Box::new(future::err(io::Error::other(msg)));
+        }
+        proxy_dst.set_port(proxy.port_or_known_default());
+
+        let conn = self.http.connect(proxy_dst);
+        if dst.scheme() != "https" {
+            return Box::new(conn.map(|(tcp, connected)| (tcp, connected.proxy(true))));
+        }
+        let authority = format!("{}:{}", dst.host(), dst.port().unwrap_or(443));
+        let auth = ProxySettings::authorization(&proxy);
+        trace!("Tunneling to {} through proxy {}", authority, host);
+        Box::new(conn.and_then(move |(tcp, connected)| {
+            tunnel(tcp, &authority, auth).map(|tcp| (tcp, connected))
+        }))
+    }
+}
+
+/// URL of a connection destination, for proxy selection.
+fn destination_url(dst: &Destination) -> Option<url::Url> {
+    let host = dst.host();
+    let host = if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]", host)
+    } else {
+        host.to_owned()
+    };
+    let port = dst.port().map(|p| format!(":{}", p)).unwrap_or_default();
+    url::Url::parse(&format!("{}://{}{}", dst.scheme(), host, port)).ok()
+}
+
+/// Maximum size of the proxy response to a `CONNECT` request.
+const MAX_CONNECT_RESPONSE: usize = 8192;
+
+/// Open a tunnel to `authority` through a connection to a proxy.
+fn tunnel<T>(
+    conn: T,
+    authority: &str,
+    auth: Option<String>,
+) -> Box<futures::Future<Item = T, Error = io::Error> + Send>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let mut req = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some(auth) = auth {
+        req.push_str(&format!("Proxy-Authorization: {}\r\n", auth));
+    }
+    req.push_str("\r\n");
+
+    let fres = tokio_io::io::write_all(conn, req.into_bytes())
+        .and_then(|(conn, _)| {
+            future::loop_fn((conn, vec![]), |(conn, mut resp)| {
+                tokio_io::io::read(conn, vec![0; 1024]).and_then(move |(conn, buf, n)| {
+                    if n == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "proxy closed the connection during CONNECT",
+                        ));
+                    }
+                    resp.extend_from_slice(&buf[..n]);
+                    if resp.windows(4).any(|w| w == b"\r\n\r\n") {
+                        Ok(Loop::Break((conn, resp)))
+                    } else if resp.len() > MAX_CONNECT_RESPONSE {
+                        Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "proxy response to CONNECT too large",
+                        ))
+                    } else {
+                        Ok(Loop::Continue((conn, resp)))
+                    }
+                })
+            })
+        }).and_then(|(conn, resp)| {
+            if resp.starts_with(b"HTTP/1.1 200") || resp.starts_with(b"HTTP/1.0 200") {
+                return Ok(conn);
+            }
+            let status = String::from_utf8_lossy(&resp);
+            let status = status.lines().next().unwrap_or_default();
+            Err(io::Error::other(format!(
+                "proxy refused CONNECT: {}",
+                status
+            )))
+        });
+    Box::new(fres)
+}
+
+/// Hosts which should be reached directly, in `NO_PROXY` format.
+#[derive(Clone, Debug, Default)]
+struct NoProxy {
+    all: bool,
+    entries: Vec<NoProxyEntry>,
+}
+
+#[derive(Clone, Debug)]
+enum NoProxyEntry {
+    /// A domain and its subdomains, optionally restricted to a port.
+    Domain(String, Option<u16>),
+    /// An IP network, as address and prefix length.
+    Network(IpAddr, u8),
+}
+
+impl NoProxy {
+    fn parse(list: &str) -> Self {
+        let mut no_proxy = Self::default();
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            if entry == "*" {
+                no_proxy.all = true;
+            } else if let Some(net) = parse_network(entry) {
+                no_proxy.entries.push(net);
+            } else {
+                let (host, port) = split_port(entry);
+                let host = host.trim_start_matches("*.").trim_start_matches('.');
+                no_proxy
+                    .entries
+                    .push(NoProxyEntry::Domain(host.to_ascii_lowercase(), port));
+            }
+        }
+        no_proxy
+    }
+
+    fn matches(&self, url: &url::Url) -> bool {
+        if self.all {
+            return true;
+        }
+        let host = match url.host_str() {
+            Some(h) => h.trim_start_matches('[').trim_end_matches(']'),
+            None => return false,
+        };
+        let ip = host.parse::<IpAddr>().ok();
+        let port = url.port_or_known_default();
+        self.entries.iter().any(|entry| match *entry {
+            NoProxyEntry::Network(net, prefix) => ip.is_some_and(|ip| in_network(ip, net, prefix)),
+            NoProxyEntry::Domain(ref domain, p) => {
+                if p.is_some() && p != port {
+                    return false;
+                }
+                let host = host.to_ascii_lowercase();
+                host == *domain || host.ends_with(&format!(".{}", domain))
+            }
+        })
+    }
+}
+
+fn env_var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|n| env::var(n).ok())
+        .find(|v| !v.trim().is_empty())
+}
+
+fn parse_proxy_url(proxy: &str) -> Result<url::Url> {
+    let proxy = proxy.trim();
+    let url = if proxy.contains("://") {
+        url::Url::parse(proxy)
+    } else {
+        url::Url::parse(&format!("http://{}", proxy))
+    };
+    match url {
+        Ok(ref u) if u.host_str().is_some() => Ok(u.clone()),
+        Ok(_) => bail!("invalid proxy URL '{}': missing host", proxy),
+        Err(e) => bail!("invalid proxy URL '{}': {}", proxy, e),
+    }
+}
+
+/// Split an optional trailing port from a host entry.
+fn split_port(entry: &str) -> (&str, Option<u16>) {
+    if let Some(i) = entry.rfind(':') {
+        if let Ok(port) = entry[i + 1..].parse() {
+            return (&entry[..i], Some(port));
+        }
+    }
+    (entry, None)
+}
+
+/// Parse an IP address or a network in CIDR notation.
+fn parse_network(entry: &str) -> Option<NoProxyEntry> {
+    let entry = entry.trim_start_matches('[').trim_end_matches(']');
+    let (addr, prefix) = match entry.find('/') {
+        Some(i) => (&entry[..i], Some(&entry[i + 1..])),
+        None => (entry, None),
+    };
+    let addr: IpAddr = addr.parse().ok()?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(p) => p.parse().ok().filter(|p| *p <= max)?,
+        None => max,
+    };
+    Some(NoProxyEntry::Network(addr, prefix))
+}
+
+fn in_network(ip: IpAddr, net: IpAddr, prefix: u8) -> bool {
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,263 @@
+//! Retry policy for idempotent registry requests.
+
+use futures::future::{self, Loop};
+use futures::{self, Future};
+use hyper::{self, body::Payload};
+use std::collections::hash_map::RandomState;
+use std::error::Error as StdError;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::time::Duration;
+use time;
+use tokio_core::reactor;
+use v2::*;
+
+/// Convenience alias for future HTTP response.
+pub(crate) type FutureResponse =
+    Box<futures::Future<Item = hyper::Response<hyper::Body>, Error = Error>>;
+
+/// Policy for retrying failed requests.
+///
+/// Only idempotent requests without a body (e.g. `GET` and `HEAD`) are
+/// retried. Between attempts, the client waits with an exponential backoff.
+/// For `429 Too Many Requests` and `503 Service Unavailable` responses, the
+/// delay requested by the registry via `Retry-After` is used instead; if it
+/// exceeds the maximum backoff, the response is returned without retrying.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: f64,
+    statuses: Vec<u16>,
+    io_errors: Vec<io::ErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    /// Up to 3 attempts, with a backoff starting at 500ms and capped at 30s.
+    ///
+    /// Requests are retried on `408`, `429`, `500`, `502`, `503` and `504`
+    /// statuses, and on refused, reset, aborted or timed out connections.
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            statuses: vec![408, 429, 500, 502, 503, 504],
+            io_errors: vec![
+                io::ErrorKind::ConnectionRefused,
+                io::ErrorKind::ConnectionReset,
+                io::ErrorKind::ConnectionAborted,
+                io::ErrorKind::TimedOut,
+                io::ErrorKind::UnexpectedEof,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy which never retries.
+    pub fn none() -> Self {
+        Self::default().max_attempts(1)
+    }
+
+    /// Set the maximum number of attempts, including the first one.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Set the delay before the first retry, and the maximum delay between attempts.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Set the factor by which the delay grows after each attempt.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Set the fraction of each delay which is randomized, between 0 and 1.
+    ///
+    /// A delay `d` with jitter `j` is picked uniformly in `[d * (1 - j), d]`.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the HTTP statuses on which requests are retried.
+    pub fn retry_statuses(mut self, statuses: Vec<u16>) -> Self {
+        self.statuses = statuses;
+        self
+    }
+
+    /// Set the kinds of I/O errors on which requests are retried.
+    ///
+    /// Connections closed by the registry before a complete response was
+    /// received are reported as `UnexpectedEof`.
+    pub fn retry_io_errors(mut self, kinds: Vec<io::ErrorKind>) -> Self {
+        self.io_errors = kinds;
+        self
+    }
+
+    /// Delay to wait before the given retry, starting at 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let base = self.initial_backoff.as_secs_f64()
+            * self.multiplier.powi(retry.saturating_sub(1) as i32);
+        let base = base.min(self.max_backoff.as_secs_f64());
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        Duration::from_secs_f64(base * (1.0 - self.jitter * random))
+    }
+
+    fn is_retryable_status(&self, status: hyper::StatusCode) -> bool {
+        self.statuses.contains(&status.as_u16())
+    }
+
+    fn is_retryable_error(&self, err: &Error) -> bool {
+        let kind = match *err.kind() {
+            ErrorKind::Timeout(..) => Some(io::ErrorKind::TimedOut),
+            ErrorKind::Hyper(ref e) => io_error_kind(e),
+            _ => None,
+        };
+        match kind {
+            Some(kind) => self.io_errors.contains(&kind),
+            None => false,
+        }
+    }
+
+    /// Delay before retrying after a response, if it should be retried.
+    fn delay_for_response(
+        &self,
+        resp: &hyper::Response<hyper::Body>,
+        retry: u32,
+    ) -> Option<Duration> {
+        let status = resp.status();
+        if !self.is_retryable_status(status) {
+            return None;
+        }
+        match status {
+            hyper::StatusCode::TOO_MANY_REQUESTS | hyper::StatusCode::SERVICE_UNAVAILABLE => {
+                match retry_after(resp) {
+                    Some(d) if d > self.max_backoff => {
+                        trace!("Retry-After {:?} exceeds maximum backoff", d);
+                        None
+                    }
+                    Some(d) => Some(d),
+                    None => Some(self.delay(retry)),
+                }
+            }
+            _ => Some(self.delay(retry)),
+        }
+    }
+}
+
+impl Client {
+    /// Execute a request, retrying it according to the retry policy.
+    pub(crate) fn execute(
+        &self,
+        req: hyper::Request<hyper::Body>,
+        op: Operation,
+    ) -> FutureResponse {
+        let retryable = is_idempotent(req.method()) && req.body().is_end_stream();
+        if !retryable || self.retry_policy.max_attempts <= 1 {
+            return self.send(req, op);
+        }
+
+        let client = self.clone();
+        let fres = future::loop_fn(1, move |attempt| {
+            let policy = client.retry_policy.clone();
+            let handle = client.handle.clone();
+            let last = attempt >= policy.max_attempts;
+            let method = req.method().clone();
+            let url = req.uri().clone();
+            client.send(clone_request(&req), op).then(move |res| {
+                let delay = match res {
+                    _ if last => None,
+                    Ok(ref resp) => policy.delay_for_response(resp, attempt),
+                    Err(ref e) if policy.is_retryable_error(e) => Some(policy.delay(attempt)),
+                    Err(_) => None,
+                };
+                let delay = match delay {
+                    Some(d) => d,
+                    None => return future::Either::A(future::result(res).map(Loop::Break)),
+                };
+                match res {
+                    Ok(ref resp) => trace!(
+                        "{} {} failed with status {}, retrying in {:?}",
+                        method,
+                        url,
+                        resp.status(),
+                        delay
+                    ),
+                    Err(ref e) => {
+                        trace!("{} {} failed: {}, retrying in {:?}", method, url, e, delay)
+                    }
+                };
+                let sleep = future::result(reactor::Timeout::new(delay, &handle))
+                    .flatten()
+                    .from_err()
+                    .map(move |_| Loop::Continue(attempt + 1));
+                future::Either::B(sleep)
+            })
+        });
+        Box::new(fres)
+    }
+}
+
+fn is_idempotent(method: &hyper::Method) -> bool {
+    matches!(
+        *method,
+        hyper::Method::GET
+            | hyper::Method::HEAD
+            | hyper::Method::PUT
+            | hyper::Method::DELETE
+            | hyper::Method::OPTIONS
+            | hyper::Method::TRACE
+    )
+}
+
+/// Clone a request without a body.
+pub(crate) fn clone_request(req: &hyper::Request<hyper::Body>) -> hyper::Request<hyper::Body> {
+    let mut clone = hyper::Request::new(hyper::Body::empty());
+    *clone.method_mut() = req.method().clone();
+    *clone.uri_mut() = req.uri().clone();
+    *clone.headers_mut() = req.headers().clone();
+    clone
+}
+
+/// Kind of the I/O error which caused a request to fail, if any.
+fn io_error_kind(err: &hyper::Error) -> Option<io::ErrorKind> {
+    if err.is_incomplete_message() || err.is_closed() {
+        return Some(io::ErrorKind::UnexpectedEof);
+    }
+    let mut source: Option<&(StdError + 'static)> = Some(err);
+    while let Some(e) = source {
+        if let Some(io) = e.downcast_ref::<io::Error>() {
+            return Some(io.kind());
+        }
+        source = e.source();
+    }
+    None
+}
+
+/// Delay requested by a `Retry-After` header, either in seconds or as an HTTP date.
+fn retry_after(resp: &hyper::Response<hyper::Body>) -> Option<Duration> {
+    let value = resp
+        .headers()
+        .get(hyper::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = time::strptime(value, "%a, %d %b %Y %H:%M:%S GMT").ok()?;
+    let secs = date.to_timespec().sec - time::get_time().sec;
+    Some(Duration::from_secs(secs.max(0) as u64))
+}
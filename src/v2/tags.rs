@@ -1,5 +1,5 @@
 use futures::prelude::*;
-use hyper::{self, header};
+use hyper::header;
 use v2::*;
 
 /// Convenience alias for a stream of `String` tags.
@@ -38,7 +38,8 @@ impl Client {
                 _ => base_url.to_string(),
             };
             let client = dclient.clone();
-            let url = hyper::Uri::from_str(&full_url);
+            let body_client = dclient.clone();
+            let url = url::Url::parse(&full_url);
 
             let freq = futures::future::result(url)
                 .from_err()
@@ -46,7 +47,7 @@ impl Client {
                     trace!("GET {:?}", &url);
                     let req = client.new_request(hyper::Method::GET, url);
                     futures::future::result(req)
-                        .and_then(move |req| client.hclient.request(req).from_err())
+                        .and_then(move |req| client.execute(req, Operation::Metadata))
                 }).and_then(|resp| {
                     let status = resp.status();
                     match status {
@@ -63,22 +64,20 @@ impl Client {
                         return Err(format!("get_tags: wrong content type '{:?}'", ct_hdr).into());
                     }
                     Ok(resp)
-                }).and_then(|resp| {
+                }).and_then(move |resp| {
                     let hdr = resp.headers().get(header::LINK).cloned();
                     trace!("next_page {:?}", hdr);
-                    resp.into_body()
-                        .concat2()
-                        .map_err(|e| {
-                            format!("get_tags: failed to fetch the whole body: {}", e).into()
-                        }).and_then(move |body| Ok((body, parse_link(hdr))))
+                    body_client
+                        .read_body(resp, Operation::Metadata)
+                        .and_then(move |body| Ok((body, parse_link(hdr))))
                 }).and_then(|(body, hdr)| -> Result<(TagsChunk, Option<String>)> {
-                    serde_json::from_slice(&body.into_bytes())
+                    serde_json::from_slice(&body)
                         .map_err(|e| e.into())
                         .map(|tags_chunk| (tags_chunk, hdr))
                 }).map(|(tags_chunk, last)| {
                     (futures::stream::iter_ok(tags_chunk.tags.into_iter()), last)
                 });
-            Some(freq)
+            Some(dclient.with_timeout(freq, Operation::Metadata))
         }).flatten();
 
         Box::new(fres)
@@ -0,0 +1,260 @@
+//! Timeouts for registry requests.
+
+use futures::{self, Async, Future, Poll, Stream};
+use hyper;
+use std::error::Error as StdError;
+use std::io;
+use std::time::{Duration, Instant};
+use tokio_core::reactor;
+use v2::*;
+
+/// Convenience alias for future response body.
+pub(crate) type FutureBody = Box<futures::Future<Item = Vec<u8>, Error = Error>>;
+
+/// Convenience alias for a stream of response body chunks.
+pub(crate) type BodyStream = Box<futures::Stream<Item = hyper::Chunk, Error = Error>>;
+
+/// Kind of registry operation, with its own timeouts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Operation {
+    /// Small API calls, e.g. for manifests, tags or authentication.
+    Metadata,
+    /// Blob transfers.
+    Blob,
+}
+
+/// Timeouts applying to a kind of registry operation.
+///
+/// Exceeded timeouts fail with `ErrorKind::Timeout`. A `None` value
+/// disables the corresponding timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    first_byte: Option<Duration>,
+    idle: Option<Duration>,
+    total: Option<Duration>,
+}
+
+impl Timeouts {
+    /// No timeouts at all.
+    pub fn none() -> Self {
+        Self {
+            first_byte: None,
+            idle: None,
+            total: None,
+        }
+    }
+
+    /// Default timeouts for metadata calls: 30s to first byte, 30s idle
+    /// and 60s in total.
+    pub fn metadata() -> Self {
+        Self {
+            first_byte: Some(Duration::from_secs(30)),
+            idle: Some(Duration::from_secs(30)),
+            total: Some(Duration::from_secs(60)),
+        }
+    }
+
+    /// Default timeouts for blob transfers: 60s to first byte, 60s idle
+    /// and no limit in total.
+    pub fn blob() -> Self {
+        Self {
+            first_byte: Some(Duration::from_secs(60)),
+            idle: Some(Duration::from_secs(60)),
+            total: None,
+        }
+    }
+
+    /// Set the maximum time to wait for response headers, once the request was sent.
+    ///
+    /// This applies separately to each attempt of a retried request.
+    pub fn first_byte(mut self, timeout: Option<Duration>) -> Self {
+        self.first_byte = timeout;
+        self
+    }
+
+    /// Set the maximum time to wait between two chunks of the response body.
+    pub fn idle(mut self, timeout: Option<Duration>) -> Self {
+        self.idle = timeout;
+        self
+    }
+
+    /// Set the maximum time for the whole operation, including retries and
+    /// reading the response body.
+    pub fn total(mut self, timeout: Option<Duration>) -> Self {
+        self.total = timeout;
+        self
+    }
+}
+
+impl Client {
+    fn timeouts(&self, op: Operation) -> Timeouts {
+        match op {
+            Operation::Metadata => self.metadata_timeouts,
+            Operation::Blob => self.blob_timeouts,
+        }
+    }
+
+    /// Send a single request, waiting for response headers at most for the first-byte timeout.
+    pub(crate) fn send(
+        &self,
+        req: hyper::Request<hyper::Body>,
+        op: Operation,
+    ) -> retry::FutureResponse {
+        let connect_timeout = self.connect_timeout;
+        let fres = self
+            .execute_with_fallback(req)
+            .map_err(move |e| match connect_timeout {
+                // Only connection timeouts are set on the underlying HTTP client.
+                Some(limit) if is_connect_timeout(&e) => {
+                    ErrorKind::Timeout(TimeoutKind::Connect, limit).into()
+                }
+                _ => Error::from(e),
+            });
+        let limit = self.timeouts(op).first_byte;
+        Box::new(Deadline::new(
+            &self.handle,
+            fres,
+            TimeoutKind::FirstByte,
+            limit,
+        ))
+    }
+
+    /// Read a whole response body, failing if no data is received for the idle timeout.
+    pub(crate) fn read_body(
+        &self,
+        resp: hyper::Response<hyper::Body>,
+        op: Operation,
+    ) -> FutureBody {
+        let fbody = self.body_stream(resp, op).fold(vec![], |mut buf, chunk| {
+            buf.extend_from_slice(&chunk);
+            futures::future::ok::<_, Error>(buf)
+        });
+        Box::new(fbody)
+    }
+
+    /// Stream a response body, failing if no data is received for the idle timeout.
+    pub(crate) fn body_stream(
+        &self,
+        resp: hyper::Response<hyper::Body>,
+        op: Operation,
+    ) -> BodyStream {
+        let body = resp.into_body().from_err();
+        let limit = self.timeouts(op).idle;
+        Box::new(IdleTimeout::new(&self.handle, body, limit))
+    }
+
+    /// Limit a whole operation to the total timeout.
+    pub(crate) fn with_timeout<F>(&self, fut: F, op: Operation) -> Deadline<F>
+    where
+        F: Future<Error = Error>,
+    {
+        let limit = self.timeouts(op).total;
+        Deadline::new(&self.handle, fut, TimeoutKind::Total, limit)
+    }
+}
+
+/// Whether a request failed because the connection could not be established in time.
+fn is_connect_timeout(err: &hyper::Error) -> bool {
+    if !err.is_connect() {
+        return false;
+    }
+    let mut source = err.source();
+    while let Some(e) = source {
+        if let Some(io) = e.downcast_ref::<io::Error>() {
+            return io.kind() == io::ErrorKind::TimedOut;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// A future which fails if not completed within a time limit.
+pub(crate) struct Deadline<F> {
+    inner: F,
+    handle: reactor::Handle,
+    timer: Option<reactor::Timeout>,
+    kind: TimeoutKind,
+    limit: Option<Duration>,
+}
+
+impl<F> Deadline<F> {
+    fn new(handle: &reactor::Handle, inner: F, kind: TimeoutKind, limit: Option<Duration>) -> Self {
+        Self {
+            inner,
+            handle: handle.clone(),
+            timer: None,
+            kind,
+            limit,
+        }
+    }
+}
+
+impl<F: Future<Error = Error>> Future for Deadline<F> {
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<F::Item, Error> {
+        if let Async::Ready(item) = self.inner.poll()? {
+            return Ok(Async::Ready(item));
+        }
+        let limit = match self.limit {
+            Some(l) => l,
+            None => return Ok(Async::NotReady),
+        };
+        if self.timer.is_none() {
+            self.timer = Some(reactor::Timeout::new(limit, &self.handle)?);
+        }
+        if let Some(ref mut timer) = self.timer {
+            if timer.poll()?.is_ready() {
+                bail!(ErrorKind::Timeout(self.kind, limit));
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+/// A stream which fails if no item is received within a time limit.
+struct IdleTimeout<S> {
+    inner: S,
+    handle: reactor::Handle,
+    timer: Option<reactor::Timeout>,
+    limit: Option<Duration>,
+}
+
+impl<S> IdleTimeout<S> {
+    fn new(handle: &reactor::Handle, inner: S, limit: Option<Duration>) -> Self {
+        Self {
+            inner,
+            handle: handle.clone(),
+            timer: None,
+            limit,
+        }
+    }
+}
+
+impl<S: Stream<Error = Error>> Stream for IdleTimeout<S> {
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, Error> {
+        let limit = match self.limit {
+            Some(l) => l,
+            None => return self.inner.poll(),
+        };
+        if let Async::Ready(item) = self.inner.poll()? {
+            if let Some(ref mut timer) = self.timer {
+                timer.reset(Instant::now() + limit);
+            }
+            return Ok(Async::Ready(item));
+        }
+        if self.timer.is_none() {
+            self.timer = Some(reactor::Timeout::new(limit, &self.handle)?);
+        }
+        if let Some(ref mut timer) = self.timer {
+            if timer.poll()?.is_ready() {
+                bail!(ErrorKind::Timeout(TimeoutKind::Idle, limit));
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
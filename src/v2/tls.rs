@@ -0,0 +1,80 @@
+//! TLS configuration for registry connections.
+
+use ct_logs;
+use errors::*;
+use rustls::{self, internal::pemfile};
+use std::sync::Arc;
+use webpki;
+use webpki_roots;
+
+/// PEM-encoded TLS settings, collected by `Config`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TlsSettings {
+    /// Additional CA certificates, each possibly a bundle.
+    pub(crate) roots: Vec<Vec<u8>>,
+    /// Client certificate chain and private key.
+    pub(crate) identity: Option<(Vec<u8>, Vec<u8>)>,
+    /// Whether to skip server certificate verification.
+    pub(crate) accept_invalid_certs: bool,
+}
+
+impl TlsSettings {
+    /// Build a `rustls` client configuration, trusting the built-in web roots
+    /// in addition to the configured CA certificates.
+    pub(crate) fn client_config(&self) -> Result<rustls::ClientConfig> {
+        let mut config = rustls::ClientConfig::new();
+        config
+            .root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        config.ct_logs = Some(&ct_logs::LOGS);
+        for pem in &self.roots {
+            match config.root_store.add_pem_file(&mut pem.as_slice()) {
+                Ok((valid, _)) if valid > 0 => {}
+                _ => bail!("invalid CA certificate: no valid PEM certificate found"),
+            }
+        }
+        if let Some((ref cert, ref key)) = self.identity {
+            let chain = pemfile::certs(&mut cert.as_slice())
+                .map_err(|_| Error::from("invalid client certificate"))?;
+            if chain.is_empty() {
+                bail!("invalid client certificate: no PEM certificate found");
+            }
+            let key = private_key(key)?;
+            // `set_single_client_cert` panics on unsupported keys.
+            rustls::sign::any_supported_type(&key)
+                .map_err(|_| Error::from("unsupported client private key"))?;
+            config.set_single_client_cert(chain, key);
+        }
+        if self.accept_invalid_certs {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoVerification));
+        }
+        Ok(config)
+    }
+}
+
+/// Read the first PKCS#8 or RSA private key from PEM data.
+fn private_key(pem: &[u8]) -> Result<rustls::PrivateKey> {
+    let pkcs8 = pemfile::pkcs8_private_keys(&mut &pem[..]).unwrap_or_default();
+    let rsa = pemfile::rsa_private_keys(&mut &pem[..]).unwrap_or_default();
+    match pkcs8.into_iter().chain(rsa).next() {
+        Some(key) => Ok(key),
+        None => bail!("invalid client private key: no PEM key found"),
+    }
+}
+
+/// A verifier accepting any server certificate.
+struct NoVerification;
+
+impl rustls::ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _roots: &rustls::RootCertStore,
+        _presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> ::std::result::Result<rustls::ServerCertVerified, rustls::TLSError> {
+        Ok(rustls::ServerCertVerified::assertion())
+    }
+}
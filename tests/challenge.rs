@@ -0,0 +1,65 @@
+extern crate dkregistry;
+
+use dkregistry::v2::Challenge;
+
+#[test]
+fn test_challenge_bearer() {
+    let hdr = r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:samalba/my-app:pull,push""#;
+    let challenges = Challenge::parse_all(hdr).unwrap();
+    assert_eq!(challenges.len(), 1);
+
+    let c = &challenges[0];
+    assert!(c.is_scheme("Bearer"));
+    assert_eq!(c.realm(), Some("https://auth.docker.io/token"));
+    assert_eq!(c.param("service"), Some("registry.docker.io"));
+    assert_eq!(
+        c.param("Scope"),
+        Some("repository:samalba/my-app:pull,push")
+    );
+    assert_eq!(c.token68(), None);
+}
+
+#[test]
+fn test_challenge_basic() {
+    let challenges = Challenge::parse_all(r#"Basic realm="Registry Realm""#).unwrap();
+    assert_eq!(challenges.len(), 1);
+    assert_eq!(challenges[0].scheme(), "basic");
+    assert_eq!(challenges[0].realm(), Some("Registry Realm"));
+}
+
+#[test]
+fn test_challenge_multiple() {
+    let hdr = r#"Newauth realm="apps", type=1, title="Login to \"apps\"", Basic realm="simple""#;
+    let challenges = Challenge::parse_all(hdr).unwrap();
+    assert_eq!(challenges.len(), 2);
+
+    assert!(challenges[0].is_scheme("newauth"));
+    assert_eq!(challenges[0].realm(), Some("apps"));
+    assert_eq!(challenges[0].param("type"), Some("1"));
+    assert_eq!(challenges[0].param("title"), Some(r#"Login to "apps""#));
+
+    assert!(challenges[1].is_scheme("basic"));
+    assert_eq!(challenges[1].realm(), Some("simple"));
+}
+
+#[test]
+fn test_challenge_token68() {
+    let challenges = Challenge::parse_all("Custom abc/DEF+123==, Bearer").unwrap();
+    assert_eq!(challenges.len(), 2);
+    assert_eq!(challenges[0].token68(), Some("abc/DEF+123=="));
+    assert!(challenges[1].is_scheme("bearer"));
+    assert_eq!(challenges[1].realm(), None);
+}
+
+#[test]
+fn test_challenge_error() {
+    let tcases = vec![
+        r#"Bearer realm="unterminated"#,
+        r#"Bearer realm=, service="x""#,
+        r#"="nope""#,
+    ];
+
+    for t in tcases {
+        assert!(Challenge::parse_all(t).is_err(), "{}", t);
+    }
+}
@@ -0,0 +1,30 @@
+//! Helpers shared by the layer and render tests.
+
+use std::{env, fs, path, process};
+
+/// Create an empty scratch directory for a test.
+pub fn scratch_dir(name: &str) -> path::PathBuf {
+    let dir = env::temp_dir().join(format!("dkregistry-test-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// List all paths below a directory, sorted.
+///
+/// Symlinks to directories are listed, but not followed.
+pub fn list(dir: &path::Path) -> Vec<String> {
+    fn walk(root: &path::Path, dir: &path::Path, out: &mut Vec<String>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let p = entry.unwrap().path();
+            out.push(p.strip_prefix(root).unwrap().to_string_lossy().into_owned());
+            if p.is_dir() && !fs::symlink_metadata(&p).unwrap().file_type().is_symlink() {
+                walk(root, &p, out);
+            }
+        }
+    }
+    let mut out = vec![];
+    walk(dir, dir, &mut out);
+    out.sort();
+    out
+}
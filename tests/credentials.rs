@@ -0,0 +1,469 @@
+extern crate dkregistry;
+#[macro_use]
+extern crate serde_json;
+
+use dkregistry::credentials::{read_credentials, read_credentials_with};
+use dkregistry::credentials::{AuthFile, CredentialHelper, Credentials};
+use dkregistry::errors::Result;
+use std::cell::RefCell;
+use std::env;
+use std::ffi::OsString;
+use std::sync::{Mutex, MutexGuard};
+
+/// Serializes tests changing process-wide environment variables.
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+/// Environment variables set for a test, restored when dropped.
+struct EnvGuard {
+    saved: Vec<(&'static str, Option<OsString>)>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl EnvGuard {
+    fn set(vars: &[(&'static str, OsString)]) -> Self {
+        let lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let saved = vars
+            .iter()
+            .map(|(name, value)| {
+                let old = env::var_os(name);
+                env::set_var(name, value);
+                (*name, old)
+            })
+            .collect();
+        EnvGuard { saved, _lock: lock }
+    }
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        for (name, old) in self.saved.drain(..).rev() {
+            match old {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+    }
+}
+
+static CONFIG: &str = r#"{
+    "auths": {
+        "https://index.docker.io/v1/": {
+            "auth": "dXNlcjpwYXNz"
+        },
+        "myregistry.example.com": {
+            "auth": "dXNlcjo=",
+            "identitytoken": "myidentity"
+        }
+    }
+}"#;
+
+#[test]
+fn test_credentials_inline() {
+    let creds = read_credentials(CONFIG.as_bytes(), "docker.io").unwrap();
+    let expected = Credentials {
+        username: Some("user".to_string()),
+        password: Some("pass".to_string()),
+        identity_token: None,
+    };
+    assert_eq!(creds, expected);
+
+    let up = dkregistry::get_credentials(CONFIG.as_bytes(), "registry-1.docker.io").unwrap();
+    assert_eq!(up, (Some("user".to_string()), Some("pass".to_string())));
+}
+
+#[test]
+fn test_credentials_identity_token() {
+    let creds = read_credentials(CONFIG.as_bytes(), "myregistry.example.com").unwrap();
+    assert_eq!(creds.username, Some("user".to_string()));
+    assert_eq!(creds.password, None);
+    assert_eq!(creds.identity_token, Some("myidentity".to_string()));
+}
+
+#[test]
+fn test_credentials_missing() {
+    let res = read_credentials(CONFIG.as_bytes(), "quay.io");
+    assert!(res.is_err());
+}
+
+#[derive(Debug, Default)]
+struct StubHelper {
+    calls: RefCell<Vec<(String, String)>>,
+}
+
+impl CredentialHelper for StubHelper {
+    fn get(&self, name: &str, server: &str) -> Result<Option<Credentials>> {
+        self.calls
+            .borrow_mut()
+            .push((name.to_string(), server.to_string()));
+        match server {
+            "https://index.docker.io/v1/" | "myregistry.example.com" => Ok(Some(Credentials {
+                username: Some(name.to_string()),
+                password: Some("secret".to_string()),
+                identity_token: None,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    fn store(&self, name: &str, server: &str, creds: &Credentials) -> Result<()> {
+        assert_eq!(creds.username, Some("newuser".to_string()));
+        self.calls
+            .borrow_mut()
+            .push((name.to_string(), server.to_string()));
+        Ok(())
+    }
+
+    fn erase(&self, name: &str, server: &str) -> Result<()> {
+        self.calls
+            .borrow_mut()
+            .push((name.to_string(), server.to_string()));
+        Ok(())
+    }
+}
+
+static HELPERS_CONFIG: &str = r#"{
+    "auths": {
+        "inline.example.com": {
+            "auth": "dXNlcjpwYXNz"
+        }
+    },
+    "credHelpers": {
+        "myregistry.example.com": "ecr-login"
+    },
+    "credsStore": "desktop"
+}"#;
+
+#[test]
+fn test_credentials_cred_helpers() {
+    let helper = StubHelper::default();
+    let creds = read_credentials_with(HELPERS_CONFIG.as_bytes(), "myregistry.example.com", &helper)
+        .unwrap();
+    assert_eq!(creds.username, Some("ecr-login".to_string()));
+    assert_eq!(creds.password, Some("secret".to_string()));
+    assert_eq!(
+        *helper.calls.borrow(),
+        vec![(
+            "ecr-login".to_string(),
+            "myregistry.example.com".to_string()
+        )]
+    );
+}
+
+#[test]
+fn test_credentials_creds_store() {
+    let helper = StubHelper::default();
+    let creds = read_credentials_with(HELPERS_CONFIG.as_bytes(), "docker.io", &helper).unwrap();
+    assert_eq!(creds.username, Some("desktop".to_string()));
+    assert_eq!(
+        *helper.calls.borrow(),
+        vec![(
+            "desktop".to_string(),
+            "https://index.docker.io/v1/".to_string()
+        )]
+    );
+}
+
+#[test]
+fn test_credentials_creds_store_fallback() {
+    let helper = StubHelper::default();
+    let creds =
+        read_credentials_with(HELPERS_CONFIG.as_bytes(), "inline.example.com", &helper).unwrap();
+    assert_eq!(creds.username, Some("user".to_string()));
+    assert_eq!(creds.password, Some("pass".to_string()));
+
+    let res = read_credentials_with(HELPERS_CONFIG.as_bytes(), "quay.io", &helper);
+    assert!(res.is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_credentials_exec_helper() {
+    use dkregistry::credentials::ExecHelper;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = env::temp_dir().join(format!("dkregistry-helper-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("docker-credential-dkrtest");
+    fs::write(
+        &script,
+        r#"#!/bin/sh
+read server
+case "$server" in
+    token.example.com)
+        echo '{"ServerURL": "token.example.com", "Username": "<token>", "Secret": "myidentity"}'
+        ;;
+    *)
+        echo 'credentials not found in native keychain'
+        exit 1
+        ;;
+esac
+"#,
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = OsString::from(&dir);
+    path.push(":");
+    path.push(env::var_os("PATH").unwrap_or_default());
+    let _env = EnvGuard::set(&[("PATH", path)]);
+
+    let creds = ExecHelper.get("dkrtest", "token.example.com").unwrap();
+    let expected = Credentials {
+        username: None,
+        password: None,
+        identity_token: Some("myidentity".to_string()),
+    };
+    assert_eq!(creds, Some(expected));
+
+    let creds = ExecHelper.get("dkrtest", "other.example.com").unwrap();
+    assert_eq!(creds, None);
+
+    let res = ExecHelper.get("dkrtest-missing", "token.example.com");
+    assert!(res.is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn new_credentials() -> Credentials {
+    Credentials {
+        username: Some("newuser".to_string()),
+        password: Some("newpass".to_string()),
+        identity_token: None,
+    }
+}
+
+#[test]
+fn test_credentials_store_inline() {
+    let config = r#"{"auths": {}, "HttpHeaders": {"X-Custom": "value"}}"#;
+    let helper = StubHelper::default();
+    let mut authfile = AuthFile::from_reader(config.as_bytes()).unwrap();
+    authfile
+        .store_with("docker.io", &new_credentials(), &helper)
+        .unwrap();
+    assert!(helper.calls.borrow().is_empty());
+
+    let mut out = vec![];
+    authfile.to_writer(&mut out).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(json["HttpHeaders"]["X-Custom"], "value");
+    assert_eq!(
+        json["auths"]["https://index.docker.io/v1/"]["auth"],
+        "bmV3dXNlcjpuZXdwYXNz"
+    );
+
+    let creds = read_credentials_with(out.as_slice(), "docker.io", &helper).unwrap();
+    assert_eq!(creds, new_credentials());
+
+    authfile.erase_with("docker.io", &helper).unwrap();
+    assert!(authfile.credentials_with("docker.io", &helper).is_err());
+}
+
+#[test]
+fn test_credentials_store_identity_token() {
+    let creds = Credentials {
+        username: Some("user".to_string()),
+        password: None,
+        identity_token: Some("myidentity".to_string()),
+    };
+    let mut authfile = AuthFile::default();
+    authfile
+        .store_with("myregistry.example.com", &creds, &StubHelper::default())
+        .unwrap();
+    let found = authfile
+        .credentials_with("myregistry.example.com", &StubHelper::default())
+        .unwrap();
+    assert_eq!(found, creds);
+}
+
+#[test]
+fn test_credentials_store_helpers() {
+    let helper = StubHelper::default();
+    let mut authfile = AuthFile::from_reader(HELPERS_CONFIG.as_bytes()).unwrap();
+
+    authfile
+        .store_with("myregistry.example.com", &new_credentials(), &helper)
+        .unwrap();
+    authfile
+        .store_with("quay.io", &new_credentials(), &helper)
+        .unwrap();
+    assert_eq!(
+        *helper.calls.borrow(),
+        vec![
+            (
+                "ecr-login".to_string(),
+                "myregistry.example.com".to_string()
+            ),
+            ("desktop".to_string(), "quay.io".to_string()),
+        ]
+    );
+
+    let mut out = vec![];
+    authfile.to_writer(&mut out).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(json["credsStore"], "desktop");
+    assert_eq!(json["auths"]["quay.io"], json!({}));
+    assert!(json["auths"].get("myregistry.example.com").is_none());
+
+    helper.calls.borrow_mut().clear();
+    authfile.erase_with("quay.io", &helper).unwrap();
+    assert_eq!(
+        *helper.calls.borrow(),
+        vec![("desktop".to_string(), "quay.io".to_string())]
+    );
+    assert!(authfile
+        .credentials_with("inline.example.com", &helper)
+        .is_ok());
+}
+
+#[test]
+fn test_credentials_store_registry_helper() {
+    // Helpers may be keyed by registry rather than by server.
+    let helper = StubHelper::default();
+    let mut authfile =
+        AuthFile::from_reader(r#"{"credHelpers": {"docker.io": "desktop"}}"#.as_bytes()).unwrap();
+
+    authfile
+        .store_with("docker.io", &new_credentials(), &helper)
+        .unwrap();
+    authfile.erase_with("docker.io", &helper).unwrap();
+    let server = "https://index.docker.io/v1/".to_string();
+    assert_eq!(
+        *helper.calls.borrow(),
+        vec![
+            ("desktop".to_string(), server.clone()),
+            ("desktop".to_string(), server),
+        ]
+    );
+
+    let mut out = vec![];
+    authfile.to_writer(&mut out).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert!(json.get("auths").is_none());
+}
+
+#[test]
+fn test_credentials_save_load() {
+    use std::{env, fs};
+
+    let dir = env::temp_dir().join(format!("dkregistry-authfile-{}", std::process::id()));
+    let path = dir.join("containers").join("auth.json");
+
+    let mut authfile = AuthFile::load(&path).unwrap();
+    assert_eq!(authfile, AuthFile::default());
+    authfile
+        .store_with("quay.io", &new_credentials(), &StubHelper::default())
+        .unwrap();
+    authfile.save(&path).unwrap();
+
+    let loaded = AuthFile::load(&path).unwrap();
+    assert_eq!(loaded, authfile);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+static SCOPED_CONFIG: &str = r#"{
+    "auths": {
+        "quay.io": {
+            "auth": "cmVnaXN0cnk6cGFzcw=="
+        },
+        "quay.io/org": {
+            "auth": "b3JnOnBhc3M="
+        },
+        "quay.io/org/repo": {
+            "auth": "cmVwbzpwYXNz"
+        },
+        "https://index.docker.io/v1/": {
+            "auth": "aHViOnBhc3M="
+        }
+    }
+}"#;
+
+#[test]
+fn test_credentials_scoped_lookup() {
+    let helper = StubHelper::default();
+    let authfile = AuthFile::from_reader(SCOPED_CONFIG.as_bytes()).unwrap();
+    let tcases = vec![
+        ("quay.io/org/repo", "repo"),
+        ("quay.io/org/repo/nested", "repo"),
+        ("quay.io/org/other", "org"),
+        ("quay.io/other/repo", "registry"),
+        ("quay.io", "registry"),
+        ("docker.io/library/busybox", "hub"),
+        ("registry-1.docker.io", "hub"),
+        ("index.docker.io/foo/bar", "hub"),
+    ];
+
+    for (key, user) in tcases {
+        let creds = authfile.lookup_with(key, &helper).unwrap();
+        assert_eq!(
+            creds.and_then(|c| c.username),
+            Some(user.to_string()),
+            "{}",
+            key
+        );
+    }
+    assert_eq!(authfile.lookup_with("gcr.io/foo", &helper).unwrap(), None);
+}
+
+#[test]
+fn test_credentials_resolver() {
+    use dkregistry::credentials::AuthResolver;
+    use std::{env, fs};
+
+    let dir = env::temp_dir().join(format!("dkregistry-resolver-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let first = dir.join("auth.json");
+    let second = dir.join("config.json");
+    let missing = dir.join("missing.json");
+    fs::write(
+        &first,
+        r#"{"auths": {"quay.io/org": {"auth": "Zmlyc3Q6cGFzcw=="}}}"#,
+    )
+    .unwrap();
+    fs::write(&second, SCOPED_CONFIG).unwrap();
+
+    let helper = StubHelper::default();
+    let resolver = AuthResolver::new(vec![missing, first, second]);
+    let creds = resolver
+        .resolve_with("quay.io/org/repo", &helper)
+        .unwrap()
+        .unwrap();
+    assert_eq!(creds.username, Some("first".to_string()));
+    let creds = resolver
+        .resolve_with("quay.io/other", &helper)
+        .unwrap()
+        .unwrap();
+    assert_eq!(creds.username, Some("registry".to_string()));
+    let creds = resolver.resolve_with("gcr.io", &helper).unwrap();
+    assert_eq!(creds, None);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_credentials_resolver_env() {
+    use dkregistry::credentials::AuthResolver;
+    use std::path::PathBuf;
+
+    let _env = EnvGuard::set(&[
+        ("REGISTRY_AUTH_FILE", "/tmp/auth.json".into()),
+        ("XDG_RUNTIME_DIR", "/run/user/1000".into()),
+        ("XDG_CONFIG_HOME", "/home/user/.config".into()),
+        ("DOCKER_CONFIG", "/home/user/.docker".into()),
+    ]);
+
+    let resolver = AuthResolver::from_env();
+    let expected: Vec<PathBuf> = vec![
+        "/tmp/auth.json".into(),
+        "/run/user/1000/containers/auth.json".into(),
+        "/home/user/.config/containers/auth.json".into(),
+        "/home/user/.docker/config.json".into(),
+    ];
+    assert_eq!(resolver.paths(), expected.as_slice());
+}
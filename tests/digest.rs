@@ -0,0 +1,97 @@
+extern crate dkregistry;
+extern crate serde_json;
+
+use dkregistry::digest::{Algorithm, Digest, Hasher, Verifier};
+use dkregistry::errors::ErrorKind;
+use dkregistry::reference::ParseError;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+static FOO_SHA256: &str = "sha256:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae";
+
+#[test]
+fn test_digest_parse() {
+    let d = Digest::from_str(FOO_SHA256).unwrap();
+    assert_eq!(d.algorithm(), Algorithm::Sha256);
+    assert_eq!(d.hex(), &FOO_SHA256[7..]);
+    assert_eq!(d.to_string(), FOO_SHA256);
+
+    let sha512 = format!("sha512:{}", "0".repeat(128));
+    let d = Digest::from_str(&sha512).unwrap();
+    assert_eq!(d.algorithm(), Algorithm::Sha512);
+    assert_eq!(d.to_string(), sha512);
+}
+
+#[test]
+fn test_digest_parse_error() {
+    let tcases = vec![
+        ("latest", ParseError::InvalidDigest("latest".into())),
+        (
+            "sha256:abcd",
+            ParseError::InvalidDigest("sha256:abcd".into()),
+        ),
+        (
+            "sha512:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae",
+            ParseError::InvalidDigest(
+                "sha512:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae".into(),
+            ),
+        ),
+        (
+            "md5:d3b07384d113edec49eaa6238ad5ff00",
+            ParseError::UnsupportedDigestAlgorithm("md5".into()),
+        ),
+    ];
+
+    for (t, expected) in tcases {
+        match *Digest::from_str(t).unwrap_err().kind() {
+            ErrorKind::InvalidDigest(ref e) => assert_eq!(*e, expected, "{}", t),
+            ref e => panic!("{}: unexpected error {}", t, e),
+        }
+    }
+
+    let upper = FOO_SHA256.to_uppercase().replace("SHA256", "sha256");
+    assert!(Digest::from_str(&upper).is_err());
+    assert!(Digest::new(Algorithm::Sha256, "zz").is_err());
+    assert!(Digest::new(Algorithm::Sha256, &FOO_SHA256[7..]).is_ok());
+}
+
+#[test]
+fn test_digest_hasher() {
+    let mut hasher = Hasher::new(Algorithm::Sha256);
+    io::copy(&mut "foo".as_bytes(), &mut hasher).unwrap();
+    assert_eq!(hasher.finish().to_string(), FOO_SHA256);
+
+    let d = Digest::from_bytes(Algorithm::Sha512, b"");
+    assert_eq!(
+        d.hex(),
+        "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce\
+         47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"
+    );
+}
+
+#[test]
+fn test_digest_verifier() {
+    let expected = Digest::from_str(FOO_SHA256).unwrap();
+    let mut verifier = Verifier::new(expected.clone());
+    verifier.write_all(b"fo").unwrap();
+    verifier.write_all(b"o").unwrap();
+    verifier.verify().unwrap();
+
+    match *expected.verify(b"bar").unwrap_err().kind() {
+        ErrorKind::DigestMismatch(ref e, ref a) => {
+            assert_eq!(*e, expected);
+            assert_eq!(*a, Digest::from_bytes(Algorithm::Sha256, b"bar"));
+        }
+        ref e => panic!("unexpected error {}", e),
+    }
+}
+
+#[test]
+fn test_digest_serde() {
+    let d = Digest::from_str(FOO_SHA256).unwrap();
+    let json = serde_json::to_string(&d).unwrap();
+    assert_eq!(json, format!("\"{}\"", FOO_SHA256));
+    let back: Digest = serde_json::from_str(&json).unwrap();
+    assert_eq!(back, d);
+    assert!(serde_json::from_str::<Digest>("\"sha256:abcd\"").is_err());
+}
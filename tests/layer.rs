@@ -0,0 +1,167 @@
+extern crate dkregistry;
+extern crate libflate;
+extern crate tar;
+
+mod common;
+
+use common::{list, scratch_dir};
+use dkregistry::digest::{Algorithm, Digest};
+use dkregistry::layer::{LayerBuilder, LayerEntry};
+use dkregistry::mediatypes::MediaTypes;
+use dkregistry::render::{self, Compression, FileType, Index};
+use libflate::gzip;
+use std::fs;
+use std::io::Read;
+
+fn builder() -> LayerBuilder {
+    let mut builder = LayerBuilder::new();
+    builder
+        .add_entry(LayerEntry::file("/usr/bin/tool", b"#!/bin/sh\n".to_vec()).mode(0o755))
+        .unwrap();
+    builder
+        .add_entry(LayerEntry::symlink("bin", "usr/bin"))
+        .unwrap();
+    builder
+        .add_entry(LayerEntry::dir("etc").mode(0o700))
+        .unwrap();
+    builder.add_whiteout("etc/old").unwrap();
+    builder
+}
+
+#[test]
+fn test_layer_build() {
+    let layer = builder().build().unwrap();
+    assert_eq!(layer.media_type, MediaTypes::OciImageLayerTgz);
+    layer.digest.verify(&layer.data).unwrap();
+
+    let mut tar = vec![];
+    gzip::Decoder::new(layer.data.as_slice())
+        .unwrap()
+        .read_to_end(&mut tar)
+        .unwrap();
+    assert_eq!(layer.diff_id, Digest::from_bytes(Algorithm::Sha256, &tar));
+
+    // Entries are sorted, parents come first, and metadata is normalized.
+    let mut archive = tar::Archive::new(tar.as_slice());
+    let entries: Vec<(String, u32, u64, u64, u64)> = archive
+        .entries()
+        .unwrap()
+        .map(|e| {
+            let e = e.unwrap();
+            let h = e.header();
+            (
+                e.path().unwrap().to_string_lossy().into_owned(),
+                h.mode().unwrap(),
+                h.uid().unwrap(),
+                h.gid().unwrap(),
+                h.mtime().unwrap(),
+            )
+        })
+        .collect();
+    let paths: Vec<&str> = entries.iter().map(|e| e.0.as_str()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            "bin",
+            "etc",
+            "etc/.wh.old",
+            "usr",
+            "usr/bin",
+            "usr/bin/tool"
+        ]
+    );
+    assert_eq!(entries[1].1, 0o700);
+    assert_eq!(entries[3].1, 0o755);
+    assert!(entries.iter().all(|e| (e.2, e.3, e.4) == (0, 0, 0)));
+
+    // Builds are reproducible.
+    assert_eq!(builder().build().unwrap().digest, layer.digest);
+    let owned = builder().owner(1000, 1000).mtime(1).build().unwrap();
+    assert_ne!(owned.diff_id, layer.diff_id);
+}
+
+#[test]
+fn test_layer_compression() {
+    let plain = builder().compression(Compression::None).build().unwrap();
+    assert_eq!(plain.media_type, MediaTypes::OciImageLayerTar);
+    assert_eq!(plain.digest, plain.diff_id);
+
+    let zstd = builder().compression(Compression::Zstd).build().unwrap();
+    assert_eq!(zstd.media_type, MediaTypes::OciImageLayerTzst);
+    assert_eq!(Compression::sniff(&zstd.data), Compression::Zstd);
+    assert_eq!(zstd.diff_id, plain.diff_id);
+
+    let index = Index::from_layers(vec![zstd.data.as_slice()]).unwrap();
+    assert_eq!(index.get("bin").unwrap().file_type, FileType::Symlink);
+    assert_eq!(index.get("usr/bin/tool").unwrap().size, 10);
+}
+
+#[test]
+fn test_layer_reject_paths() {
+    let mut builder = LayerBuilder::new();
+    assert!(builder.add_entry(LayerEntry::dir("../escape")).is_err());
+    assert!(builder.add_entry(LayerEntry::dir("/")).is_err());
+    assert!(builder.add_whiteout("/").is_err());
+}
+
+#[test]
+fn test_layer_diff() {
+    let (lower, upper) = (scratch_dir("lower"), scratch_dir("upper"));
+    for dir in &[&lower, &upper] {
+        fs::create_dir_all(dir.join("etc/conf.d")).unwrap();
+        fs::create_dir_all(dir.join("usr/lib")).unwrap();
+        fs::write(dir.join("etc/hosts"), "localhost").unwrap();
+        fs::write(dir.join("usr/lib/libc.so"), "libc").unwrap();
+    }
+    fs::write(lower.join("etc/conf.d/removed"), "old").unwrap();
+    fs::create_dir_all(lower.join("var/cache")).unwrap();
+    fs::write(lower.join("var/cache/data"), "cache").unwrap();
+    fs::write(lower.join("usr/lib/libfoo.so"), "foo v1").unwrap();
+    fs::write(upper.join("usr/lib/libfoo.so"), "foo v2").unwrap();
+    fs::write(upper.join("etc/new"), "new").unwrap();
+
+    let mut builder = LayerBuilder::new();
+    builder.add_diff(&lower, &upper).unwrap();
+    let diff = builder.build().unwrap();
+
+    let index = Index::from_layers(vec![diff.data.as_slice()]).unwrap();
+    let paths: Vec<String> = index
+        .iter()
+        .map(|e| e.path.to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            "etc",
+            "etc/conf.d",
+            "etc/new",
+            "usr",
+            "usr/lib",
+            "usr/lib/libfoo.so",
+        ]
+    );
+
+    // Applying the diff over the lower tree gives the upper one.
+    let mut builder = LayerBuilder::new();
+    builder.add_dir(&lower).unwrap();
+    let base = builder.build().unwrap();
+    let dir = scratch_dir("diff-applied");
+    render::unpack(&[base.data, diff.data], &dir).unwrap();
+    assert_eq!(list(&dir), list(&upper));
+    assert_eq!(
+        fs::read_to_string(dir.join("usr/lib/libfoo.so")).unwrap(),
+        "foo v2"
+    );
+
+    // Identical trees give an empty diff.
+    let mut builder = LayerBuilder::new();
+    builder.add_diff(&upper, &upper).unwrap();
+    let empty = builder.build().unwrap();
+    assert!(Index::from_layers(vec![empty.data.as_slice()])
+        .unwrap()
+        .is_empty());
+
+    for d in &[lower, upper, dir] {
+        fs::remove_dir_all(d).unwrap();
+    }
+}
@@ -56,3 +56,59 @@ fn test_labels_manifest_v2s1_signed() {
     assert_eq!(expected_labels_0, labels_0);
     assert_eq!(None, manif.get_labels(1));
 }
+
+#[test]
+fn test_manifest_list_find_platform() {
+    let f = fs::File::open("tests/fixtures/manifest_list_v2.json").expect("Missing fixture");
+    let bufrd = io::BufReader::new(f);
+    let manif: dkregistry::v2::manifest::ManifestList = serde_json::from_reader(bufrd).unwrap();
+
+    let mut platform = dkregistry::v2::manifest::Platform {
+        os: "linux".into(),
+        architecture: "amd64".into(),
+        ..Default::default()
+    };
+    let found = manif.find_platform(&platform).expect("Missing manifest");
+    assert_eq!(
+        found.digest.to_string(),
+        "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270"
+    );
+
+    platform.variant = Some("v7".into());
+    assert!(manif.find_platform(&platform).is_none());
+    platform.architecture = "s390x".into();
+    platform.variant = None;
+    assert!(manif.find_platform(&platform).is_none());
+}
+
+#[test]
+fn test_deserialize_oci_index() {
+    // Media types are optional, as are platforms, e.g. for attestations.
+    let index = r#"{
+        "schemaVersion": 2,
+        "manifests": [
+            {
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "size": 7143,
+                "digest": "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f",
+                "platform": { "architecture": "arm64", "os": "linux", "variant": "v8" }
+            },
+            {
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "size": 840,
+                "digest": "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270",
+                "annotations": { "vnd.docker.reference.type": "attestation-manifest" }
+            }
+        ]
+    }"#;
+    let manif: dkregistry::v2::manifest::ManifestList = serde_json::from_str(index).unwrap();
+    assert_eq!(manif.manifests.len(), 2);
+
+    let platform = dkregistry::v2::manifest::Platform {
+        os: "linux".into(),
+        architecture: "arm64".into(),
+        ..Default::default()
+    };
+    let found = manif.find_platform(&platform).expect("Missing manifest");
+    assert_eq!(found.platform.variant, Some("v8".into()));
+}
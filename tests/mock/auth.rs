@@ -0,0 +1,308 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio_core;
+
+use self::dkregistry::credentials::{AuthResolver, CredentialHelper, Credentials};
+use self::dkregistry::errors::{ErrorKind, Result};
+use self::dkregistry::v2::AuthScheme;
+use self::mockito::{mock, Matcher};
+use self::tokio_core::reactor::Core;
+use std::{env, fs, process};
+
+#[test]
+fn test_auth_login_bearer() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let challenge = format!(
+        r#"Bearer realm="http://{}/token",service="mock",scope="repository:a/b:pull,push""#,
+        addr
+    );
+    let _m1 = mock("GET", "/v2/")
+        .match_header("authorization", Matcher::Missing)
+        .with_status(401)
+        .with_header("WWW-Authenticate", &challenge)
+        .create();
+    let _m2 = mock(
+        "GET",
+        "/token?service=mock&scope=repository%3Aa%2Fb%3Apull%2Cpush",
+    )
+    .with_status(200)
+    .with_header("Content-Type", "application/json")
+    .with_body(r#"{"token": "mytoken"}"#)
+    .create();
+    let _m3 = mock("GET", "/v2/")
+        .match_header("authorization", "Bearer mytoken")
+        .with_status(200)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let mut dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .username(None)
+        .password(None)
+        .build()
+        .unwrap();
+
+    let token = tcore.run(dclient.login(&[])).unwrap();
+    assert_eq!(token.token(), "mytoken");
+    assert_eq!(token.scheme(), AuthScheme::Bearer);
+
+    dclient.set_auth(Some(&token));
+    let res = tcore.run(dclient.is_auth(None)).unwrap();
+    assert!(res);
+
+    mockito::reset();
+}
+
+#[test]
+fn test_auth_login_basic() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let _m1 = mock("GET", "/v2/")
+        .match_header("authorization", Matcher::Missing)
+        .with_status(401)
+        .with_header("WWW-Authenticate", r#"Basic realm="Registry Realm""#)
+        .create();
+    let _m2 = mock("GET", "/v2/")
+        .match_header("authorization", "Basic dXNlcjpwYXNz")
+        .with_status(200)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let mut dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .username(Some("user".to_string()))
+        .password(Some("pass".to_string()))
+        .build()
+        .unwrap();
+
+    let token = tcore.run(dclient.login(&[])).unwrap();
+    assert_eq!(token.scheme(), AuthScheme::Basic);
+
+    dclient.set_auth(Some(&token));
+    let res = tcore.run(dclient.is_auth(None)).unwrap();
+    assert!(res);
+    let res = tcore.run(dclient.is_auth(Some(token.token()))).unwrap();
+    assert!(res);
+
+    mockito::reset();
+}
+
+#[test]
+fn test_auth_login_basic_rejected() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let _m = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", r#"Basic realm="Registry Realm""#)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .username(Some("user".to_string()))
+        .password(Some("wrong".to_string()))
+        .build()
+        .unwrap();
+
+    let res = tcore.run(dclient.login(&[]));
+    assert!(res.is_err());
+
+    mockito::reset();
+}
+
+#[test]
+fn test_auth_login_identity_token() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let challenge = format!(
+        r#"Bearer realm="http://{}/oauth2/token",service="mock""#,
+        addr
+    );
+    let _m1 = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", &challenge)
+        .create();
+    let _m2 = mock("POST", "/oauth2/token")
+        .match_header("content-type", "application/x-www-form-urlencoded")
+        .match_body(
+            "grant_type=refresh_token&refresh_token=myrefresh&client_id=dkregistry\
+             &service=mock&scope=repository%3Aa%2Fb%3Apull",
+        )
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"access_token": "mytoken", "expires_in": 300}"#)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .identity_token(Some("myrefresh".to_string()))
+        .build()
+        .unwrap();
+
+    let token = tcore.run(dclient.login(&["repository:a/b:pull"])).unwrap();
+    assert_eq!(token.token(), "mytoken");
+    assert_eq!(token.expires_in(), Some(300));
+
+    mockito::reset();
+}
+
+#[test]
+fn test_auth_login_offline_token() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let challenge = format!(
+        r#"Bearer realm="http://{}/oauth2/token",service="mock""#,
+        addr
+    );
+    let _m1 = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", &challenge)
+        .create();
+    let _m2 = mock("POST", "/oauth2/token")
+        .match_body(
+            "grant_type=password&username=user&password=pass&access_type=offline\
+             &client_id=myclient&service=mock",
+        )
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"access_token": "mytoken", "refresh_token": "myrefresh"}"#)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .username(Some("user".to_string()))
+        .password(Some("pass".to_string()))
+        .offline_token(true)
+        .client_id("myclient")
+        .build()
+        .unwrap();
+
+    let token = tcore.run(dclient.login(&[])).unwrap();
+    assert_eq!(token.token(), "mytoken");
+    assert_eq!(token.refresh_token(), Some("myrefresh"));
+
+    mockito::reset();
+}
+
+#[test]
+fn test_auth_login_offline_token_fallback() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let challenge = format!(r#"Bearer realm="http://{}/token",service="mock""#, addr);
+    let _m1 = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", &challenge)
+        .create();
+    let _m2 = mock("POST", "/token").with_status(404).create();
+    let _m3 = mock("GET", "/token?service=mock")
+        .match_header("authorization", "Basic dXNlcjpwYXNz")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"token": "mytoken"}"#)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .username(Some("user".to_string()))
+        .password(Some("pass".to_string()))
+        .offline_token(true)
+        .build()
+        .unwrap();
+
+    let token = tcore.run(dclient.login(&[])).unwrap();
+    assert_eq!(token.token(), "mytoken");
+    assert_eq!(token.refresh_token(), None);
+
+    mockito::reset();
+}
+
+#[test]
+fn test_auth_login_offline_token_rejected() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let challenge = format!(r#"Bearer realm="http://{}/token",service="mock""#, addr);
+    let _m1 = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", &challenge)
+        .create();
+    let _m2 = mock("POST", "/token").with_status(401).create();
+    let m3 = mock("GET", "/token?service=mock")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"token": "mytoken"}"#)
+        .expect(0)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .username(Some("user".to_string()))
+        .password(Some("wrong".to_string()))
+        .offline_token(true)
+        .build()
+        .unwrap();
+
+    // Credentials rejected by an OAuth2 server are not sent again.
+    let res = tcore.run(dclient.login(&[]));
+    match res.unwrap_err().kind() {
+        ErrorKind::TokenStatus(status) => assert_eq!(status.as_u16(), 401),
+        e => panic!("unexpected error {}", e),
+    }
+    m3.assert();
+
+    mockito::reset();
+}
+
+/// A credential helper returning fixed credentials.
+struct StubHelper;
+
+impl CredentialHelper for StubHelper {
+    fn get(&self, name: &str, _server: &str) -> Result<Option<Credentials>> {
+        assert_eq!(name, "stub");
+        Ok(Some(Credentials {
+            username: Some("user".to_string()),
+            password: Some("pass".to_string()),
+            identity_token: None,
+        }))
+    }
+}
+
+#[test]
+fn test_auth_resolve_credentials_helper() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let _m1 = mock("GET", "/v2/")
+        .match_header("authorization", Matcher::Missing)
+        .with_status(401)
+        .with_header("WWW-Authenticate", r#"Basic realm="Registry Realm""#)
+        .create();
+    let _m2 = mock("GET", "/v2/")
+        .match_header("authorization", "Basic dXNlcjpwYXNz")
+        .with_status(200)
+        .create();
+
+    let authfile = env::temp_dir().join(format!("dkregistry-auth-helper-{}.json", process::id()));
+    fs::write(
+        &authfile,
+        format!(r#"{{"credHelpers": {{"{}": "stub"}}}}"#, addr),
+    )
+    .unwrap();
+    let resolver = AuthResolver::new(vec![authfile.clone()]);
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .resolve_credentials_with(&resolver, None, &StubHelper)
+        .build()
+        .unwrap();
+
+    let token = tcore.run(dclient.login(&[])).unwrap();
+    assert_eq!(token.scheme(), AuthScheme::Basic);
+
+    fs::remove_file(&authfile).unwrap();
+    mockito::reset();
+}
@@ -1,22 +1,25 @@
 extern crate dkregistry;
+extern crate futures;
 extern crate mockito;
 extern crate tokio_core;
 
+use self::dkregistry::digest::{Algorithm, Digest};
+use self::dkregistry::errors::ErrorKind;
+use self::futures::Stream;
 use self::mockito::mock;
 use self::tokio_core::reactor::Core;
 
 #[test]
 fn test_blobs_has_layer() {
     let name = "my-repo/my-image";
-    let digest = "fakedigest";
-    let binary_digest = "binarydigest";
+    let digest = Digest::from_bytes(Algorithm::Sha256, b"layer");
 
     let ep = format!("/v2/{}/blobs/{}", name, digest);
     let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
     let _m = mock("HEAD", ep.as_str())
         .with_status(200)
         .with_header("Content-Length", "0")
-        .with_header("Docker-Content-Digest", binary_digest)
+        .with_header("Docker-Content-Digest", &digest.to_string())
         .create();
 
     let mut tcore = Core::new().unwrap();
@@ -28,7 +31,7 @@ fn test_blobs_has_layer() {
         .build()
         .unwrap();
 
-    let futcheck = dclient.has_blob(name, digest);
+    let futcheck = dclient.has_blob(name, &digest);
 
     let res = tcore.run(futcheck).unwrap();
     assert_eq!(res, true);
@@ -39,7 +42,7 @@ fn test_blobs_has_layer() {
 #[test]
 fn test_blobs_hasnot_layer() {
     let name = "my-repo/my-image";
-    let digest = "fakedigest";
+    let digest = Digest::from_bytes(Algorithm::Sha256, b"missing");
 
     let ep = format!("/v2/{}/blobs/{}", name, digest);
     let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
@@ -54,10 +57,133 @@ fn test_blobs_hasnot_layer() {
         .build()
         .unwrap();
 
-    let futcheck = dclient.has_blob(name, digest);
+    let futcheck = dclient.has_blob(name, &digest);
 
     let res = tcore.run(futcheck).unwrap();
     assert_eq!(res, false);
 
     mockito::reset();
 }
+
+#[test]
+fn test_blobs_get_verified() {
+    let name = "my-repo/my-image";
+    let digest = Digest::from_bytes(Algorithm::Sha512, b"layer");
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let _m = mock("GET", ep.as_str())
+        .with_status(200)
+        .with_body("layer")
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()
+        .unwrap();
+
+    let futblob = dclient.get_blob(name, &digest);
+
+    let blob = tcore.run(futblob).unwrap();
+    assert_eq!(blob, b"layer");
+
+    mockito::reset();
+}
+
+#[test]
+fn test_blobs_get_mismatch() {
+    let name = "my-repo/my-image";
+    let digest = Digest::from_bytes(Algorithm::Sha256, b"layer");
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let _m = mock("GET", ep.as_str())
+        .with_status(200)
+        .with_body("corrupted")
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()
+        .unwrap();
+
+    let futblob = dclient.get_blob(name, &digest);
+
+    let err = tcore.run(futblob).unwrap_err();
+    match *err.kind() {
+        ErrorKind::DigestMismatch(ref expected, ref actual) => {
+            assert_eq!(*expected, digest);
+            assert_eq!(*actual, Digest::from_bytes(Algorithm::Sha256, b"corrupted"));
+        }
+        ref e => panic!("unexpected error {}", e),
+    }
+
+    mockito::reset();
+}
+
+#[test]
+fn test_blobs_get_stream() {
+    let name = "my-repo/my-image";
+    let digest = Digest::from_bytes(Algorithm::Sha256, b"streamed layer");
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let _m = mock("GET", ep.as_str())
+        .with_status(200)
+        .with_body("streamed layer")
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()
+        .unwrap();
+
+    let blob = tcore
+        .run(dclient.get_blob_stream(name, &digest).concat2())
+        .unwrap();
+    assert_eq!(&blob[..], b"streamed layer");
+
+    let corrupted = Digest::from_bytes(Algorithm::Sha256, b"corrupted");
+    let ep = format!("/v2/{}/blobs/{}", name, corrupted);
+    let _m = mock("GET", ep.as_str())
+        .with_status(200)
+        .with_body("streamed layer")
+        .create();
+    let err = tcore
+        .run(dclient.get_blob_stream(name, &corrupted).concat2())
+        .unwrap_err();
+    match *err.kind() {
+        ErrorKind::DigestMismatch(ref expected, _) => assert_eq!(*expected, corrupted),
+        ref e => panic!("unexpected error {}", e),
+    }
+
+    mockito::reset();
+}
+
+#[test]
+fn test_blobs_get_stream_missing() {
+    let name = "my-repo/my-image";
+    let digest = Digest::from_bytes(Algorithm::Sha256, b"missing stream");
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let _m = mock("GET", ep.as_str()).with_status(404).create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()
+        .unwrap();
+
+    let res = tcore.run(dclient.get_blob_stream(name, &digest).concat2());
+    assert!(res.is_err());
+
+    mockito::reset();
+}
@@ -0,0 +1,208 @@
+extern crate dkregistry;
+extern crate libflate;
+extern crate mockito;
+extern crate tar;
+extern crate tokio_core;
+
+use self::dkregistry::digest::{Algorithm, Digest};
+use self::dkregistry::reference::Reference;
+use self::dkregistry::v2::manifest::Platform;
+use self::libflate::gzip;
+use self::mockito::mock;
+use self::tokio_core::reactor::Core;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Build a gzip-compressed tar layer of regular files.
+fn layer(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for &(path, data) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        builder
+            .append_data(&mut header, path, data.as_bytes())
+            .unwrap();
+    }
+    let mut encoder = gzip::Encoder::new(Vec::new()).unwrap();
+    encoder.write_all(&builder.into_inner().unwrap()).unwrap();
+    encoder.finish().into_result().unwrap()
+}
+
+/// Manifest types sent by `extract_files`, one per `Accept` header.
+static ACCEPTED_TYPES: &str = r"^application/vnd\.(docker\.distribution\.manifest\.(v2|list\.v2)|oci\.image\.(manifest|index)\.v1)\+json$";
+
+/// Build a schema2 manifest referencing layers, lower ones first.
+fn manifest(layers: &[&[u8]]) -> String {
+    let layers: Vec<String> = layers
+        .iter()
+        .map(|l| {
+            format!(
+                r#"{{
+                    "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+                    "size": {},
+                    "digest": "{}"
+                }}"#,
+                l.len(),
+                Digest::from_bytes(Algorithm::Sha256, l)
+            )
+        })
+        .collect();
+    format!(
+        r#"{{
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+            "config": {{
+                "mediaType": "application/vnd.docker.container.image.v1+json",
+                "size": 2,
+                "digest": "{}"
+            }},
+            "layers": [{}]
+        }}"#,
+        Digest::from_bytes(Algorithm::Sha256, b"{}"),
+        layers.join(",")
+    )
+}
+
+#[test]
+fn test_extract_files() {
+    let name = "extract/image";
+    let base = layer(&[("etc/os-release", "ID=base"), ("etc/shadow", "secret")]);
+    let top = layer(&[("etc/os-release", "ID=top"), ("etc/.wh.shadow", "")]);
+    let (base_digest, top_digest) = (
+        Digest::from_bytes(Algorithm::Sha256, &base),
+        Digest::from_bytes(Algorithm::Sha256, &top),
+    );
+    let manifest = manifest(&[&base, &top]);
+
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let _m_manifest = mock("GET", format!("/v2/{}/manifests/latest", name).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.v2+json",
+        )
+        .with_body(&manifest)
+        .expect(2)
+        .create();
+    let m_base = mock(
+        "GET",
+        format!("/v2/{}/blobs/{}", name, base_digest).as_str(),
+    )
+    .with_status(200)
+    .with_body(&base)
+    .expect(1)
+    .create();
+    let m_top = mock("GET", format!("/v2/{}/blobs/{}", name, top_digest).as_str())
+        .with_status(200)
+        .with_body(&top)
+        .expect(2)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .username(None)
+        .password(None)
+        .build()
+        .unwrap();
+    let reference = Reference::from_str(&format!("{}/{}:latest", addr, name)).unwrap();
+
+    // Found in the top layer: the base one is never fetched.
+    let futcheck = dclient.extract_files(&reference, &["/etc/os-release"]);
+    let files = tcore.run(futcheck).unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files["/etc/os-release"], b"ID=top");
+
+    // Whited-out and missing files are left out.
+    let futcheck = dclient.extract_files(&reference, &["etc/shadow", "missing"]);
+    let files = tcore.run(futcheck).unwrap();
+    assert!(files.is_empty());
+
+    m_base.assert();
+    m_top.assert();
+
+    mockito::reset();
+}
+
+#[test]
+fn test_extract_files_index() {
+    let name = "extract/index";
+    let base = layer(&[("etc/os-release", "ID=index")]);
+    let base_digest = Digest::from_bytes(Algorithm::Sha256, &base);
+    let manifest = manifest(&[&base]);
+    let manifest_digest = Digest::from_bytes(Algorithm::Sha256, manifest.as_bytes());
+    let platform = Platform::current();
+    let index = format!(
+        r#"{{
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": [
+                {{
+                    "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                    "size": 2,
+                    "digest": "{}",
+                    "platform": {{ "architecture": "other", "os": "{}" }}
+                }},
+                {{
+                    "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                    "size": {},
+                    "digest": "{}",
+                    "platform": {{ "architecture": "{}", "os": "{}" }}
+                }}
+            ]
+        }}"#,
+        Digest::from_bytes(Algorithm::Sha256, b"{}"),
+        platform.os,
+        manifest.len(),
+        manifest_digest,
+        platform.architecture,
+        platform.os
+    );
+
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let m_index = mock("GET", format!("/v2/{}/manifests/latest", name).as_str())
+        .match_header(
+            "accept",
+            mockito::Matcher::Regex(ACCEPTED_TYPES.to_string()),
+        )
+        .with_status(200)
+        .with_header("Content-Type", "application/vnd.oci.image.index.v1+json")
+        .with_body(&index)
+        .create();
+    let m_manifest = mock(
+        "GET",
+        format!("/v2/{}/manifests/{}", name, manifest_digest).as_str(),
+    )
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+    .with_body(&manifest)
+    .create();
+    let _m_base = mock(
+        "GET",
+        format!("/v2/{}/blobs/{}", name, base_digest).as_str(),
+    )
+    .with_status(200)
+    .with_body(&base)
+    .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .username(None)
+        .password(None)
+        .build()
+        .unwrap();
+    let reference = Reference::from_str(&format!("{}/{}:latest", addr, name)).unwrap();
+
+    let futcheck = dclient.extract_files(&reference, &["etc/os-release"]);
+    let files = tcore.run(futcheck).unwrap();
+    assert_eq!(files["etc/os-release"], b"ID=index");
+
+    m_index.assert();
+    m_manifest.assert();
+
+    mockito::reset();
+}
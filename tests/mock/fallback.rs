@@ -0,0 +1,109 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio_core;
+
+use self::dkregistry::v2::RetryPolicy;
+use self::mockito::mock;
+use self::tokio_core::reactor::Core;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+static API_VERSION_K: &str = "Docker-Distribution-API-Version";
+static API_VERSION_V: &str = "registry/2.0";
+
+#[test]
+fn test_fallback_localhost_default() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let _m = mock("GET", "/v2/")
+        .with_status(200)
+        .with_header(API_VERSION_K, API_VERSION_V)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.is_v2_supported();
+    let res = tcore.run(futcheck).unwrap();
+    assert!(res);
+
+    // Further requests go to the HTTP endpoint directly.
+    let futcheck = dclient.is_v2_supported();
+    let res = tcore.run(futcheck).unwrap();
+    assert!(res);
+
+    mockito::reset();
+}
+
+#[test]
+fn test_fallback_loopback_address() {
+    let addr = mockito::SERVER_ADDRESS;
+    let ep = "/v2/fallback/loopback/manifests/latest";
+    let _m = mock("GET", ep).with_status(200).with_body("{}").create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(addr)
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.get_manifest("fallback/loopback", "latest");
+    let res = tcore.run(futcheck).unwrap();
+    assert_eq!(res, b"{}");
+
+    mockito::reset();
+}
+
+#[test]
+fn test_fallback_disabled() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let _m = mock("GET", "/v2/")
+        .with_status(200)
+        .with_header(API_VERSION_K, API_VERSION_V)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_fallback(false)
+        .retry_policy(RetryPolicy::none())
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.is_v2_supported();
+    let res = tcore.run(futcheck);
+    assert!(res.is_err());
+
+    mockito::reset();
+}
+
+#[test]
+fn test_fallback_not_on_connection_errors() {
+    // A registry closing connections before the TLS handshake completes.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = format!("localhost:{}", listener.local_addr().unwrap().port());
+    let connections = Arc::new(AtomicUsize::new(0));
+    let accepted = connections.clone();
+    thread::spawn(move || {
+        for tcp in listener.incoming() {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            drop(tcp);
+        }
+    });
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .retry_policy(RetryPolicy::none())
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.is_v2_supported();
+    let res = tcore.run(futcheck);
+    assert!(res.is_err());
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
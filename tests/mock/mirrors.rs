@@ -0,0 +1,146 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio_core;
+
+use self::dkregistry::digest::{Algorithm, Digest};
+use self::dkregistry::v2::RetryPolicy;
+use self::mockito::{mock, Matcher};
+use self::tokio_core::reactor::Core;
+
+// The mock server is reached as `localhost` for the registry, and by IP for the mirror.
+fn upstream() -> String {
+    mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost")
+}
+
+fn mirror() -> String {
+    format!("http://{}", mockito::SERVER_ADDRESS)
+}
+
+#[test]
+fn test_mirrors_get_manifest() {
+    let ep = "/v2/mirrors/hit/manifests/latest";
+    let m_mirror = mock("GET", ep)
+        .match_header("host", mockito::SERVER_ADDRESS)
+        .match_header("authorization", Matcher::Missing)
+        .with_status(200)
+        .with_body("mirror")
+        .expect(1)
+        .create();
+    let m_upstream = mock("GET", ep)
+        .match_header("host", upstream().as_str())
+        .with_status(200)
+        .with_body("upstream")
+        .expect(0)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let mut dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&upstream())
+        .insecure_registry(true)
+        .mirrors(vec![mirror()])
+        .build()
+        .unwrap();
+    dclient.set_token(Some("upstream-token"));
+
+    let futcheck = dclient.get_manifest("mirrors/hit", "latest");
+
+    let res = tcore.run(futcheck).unwrap();
+    assert_eq!(res, b"mirror");
+    m_mirror.assert();
+    m_upstream.assert();
+
+    mockito::reset();
+}
+
+#[test]
+fn test_mirrors_get_manifest_missing() {
+    let ep = "/v2/mirrors/miss/manifests/latest";
+    let _m_mirror = mock("GET", ep)
+        .match_header("host", mockito::SERVER_ADDRESS)
+        .with_status(404)
+        .create();
+    let _m_upstream = mock("GET", ep)
+        .match_header("host", upstream().as_str())
+        .with_status(200)
+        .with_body("upstream")
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&upstream())
+        .insecure_registry(true)
+        .mirrors(vec![mirror()])
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.get_manifest("mirrors/miss", "latest");
+
+    let res = tcore.run(futcheck).unwrap();
+    assert_eq!(res, b"upstream");
+
+    mockito::reset();
+}
+
+#[test]
+fn test_mirrors_has_manifest_missing() {
+    let ep = "/v2/mirrors/has/manifests/latest";
+    let _m_mirror = mock("HEAD", ep)
+        .match_header("host", mockito::SERVER_ADDRESS)
+        .with_status(404)
+        .create();
+    let _m_upstream = mock("HEAD", ep)
+        .match_header("host", upstream().as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.v2+json",
+        )
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&upstream())
+        .insecure_registry(true)
+        .mirrors(vec![mirror()])
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.has_manifest("mirrors/has", "latest", None);
+
+    let res = tcore.run(futcheck).unwrap();
+    assert_eq!(res, Some(dkregistry::mediatypes::MediaTypes::ManifestV2S2));
+
+    mockito::reset();
+}
+
+#[test]
+fn test_mirrors_get_blob_failed() {
+    let digest = Digest::from_bytes(Algorithm::Sha256, b"blob");
+    let ep = format!("/v2/mirrors/blob/blobs/{}", digest);
+    let ep = ep.as_str();
+    let _m_mirror = mock("GET", ep)
+        .match_header("host", mockito::SERVER_ADDRESS)
+        .with_status(500)
+        .create();
+    let _m_upstream = mock("GET", ep)
+        .match_header("host", upstream().as_str())
+        .with_status(200)
+        .with_body("blob")
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&upstream())
+        .insecure_registry(true)
+        .retry_policy(RetryPolicy::none())
+        .mirrors(vec![mirror()])
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.get_blob("mirrors/blob", &digest);
+
+    let res = tcore.run(futcheck).unwrap();
+    assert_eq!(res, b"blob");
+
+    mockito::reset();
+}
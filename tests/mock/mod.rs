@@ -1,5 +1,12 @@
 mod api_version;
+mod auth;
 mod base_client;
 mod blobs_download;
 mod catalog;
+mod extract;
+mod fallback;
+mod mirrors;
+mod proxy;
+mod retry;
+mod timeout;
 mod tags;
@@ -0,0 +1,120 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio_core;
+
+use self::mockito::mock;
+use self::tokio_core::reactor::Core;
+
+static API_VERSION_K: &str = "Docker-Distribution-API-Version";
+static API_VERSION_V: &str = "registry/2.0";
+
+#[test]
+fn test_proxy_explicit() {
+    let proxy = format!("http://{}", mockito::SERVER_ADDRESS);
+    let _m = mock("GET", "http://registry.test/v2/")
+        .with_status(200)
+        .with_header(API_VERSION_K, API_VERSION_V)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry("registry.test")
+        .insecure_registry(true)
+        .proxy(Some(proxy))
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.is_v2_supported();
+
+    let res = tcore.run(futcheck).unwrap();
+    assert!(res);
+
+    mockito::reset();
+}
+
+#[test]
+fn test_proxy_blob() {
+    let proxy = mockito::SERVER_ADDRESS.to_string();
+    let digest = "sha256:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae";
+    let ep = format!("http://registry.test/v2/library/busybox/blobs/{}", digest);
+    let _m = mock("GET", ep.as_str())
+        .with_status(200)
+        .with_body("foo")
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry("registry.test")
+        .insecure_registry(true)
+        .proxy(Some(proxy))
+        .build()
+        .unwrap();
+
+    let futblob = dclient.get_blob("library/busybox", &digest.parse().unwrap());
+
+    let blob = tcore.run(futblob).unwrap();
+    assert_eq!(blob, b"foo");
+
+    mockito::reset();
+}
+
+#[test]
+fn test_proxy_credentials() {
+    let proxy = format!("http://user:pass@{}", mockito::SERVER_ADDRESS);
+    let _m = mock("GET", "http://registry.test/v2/")
+        .match_header("proxy-authorization", "Basic dXNlcjpwYXNz")
+        .with_status(200)
+        .with_header(API_VERSION_K, API_VERSION_V)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry("registry.test")
+        .insecure_registry(true)
+        .proxy(Some(proxy))
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.is_v2_supported();
+
+    let res = tcore.run(futcheck).unwrap();
+    assert!(res);
+
+    mockito::reset();
+}
+
+#[test]
+fn test_proxy_no_proxy() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let _m = mock("GET", "/v2/")
+        .with_status(200)
+        .with_header(API_VERSION_K, API_VERSION_V)
+        .create();
+
+    // Nothing listens on the discard port, requests would fail if proxied.
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .proxy(Some("http://127.0.0.1:9".to_string()))
+        .no_proxy(Some("example.com, .localhost".to_string()))
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.is_v2_supported();
+
+    let res = tcore.run(futcheck).unwrap();
+    assert!(res);
+
+    mockito::reset();
+}
+
+#[test]
+fn test_proxy_invalid() {
+    let tcore = Core::new().unwrap();
+    let res = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry("registry.test")
+        .proxy(Some("http://".to_string()))
+        .build();
+    assert!(res.is_err());
+}
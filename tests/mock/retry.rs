@@ -0,0 +1,225 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio_core;
+
+use self::dkregistry::v2::RetryPolicy;
+use self::mockito::mock;
+use self::tokio_core::reactor::Core;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+static MANIFEST: &str = r#"{"schemaVersion": 2}"#;
+
+fn fast_retries(attempts: u32) -> RetryPolicy {
+    RetryPolicy::default()
+        .max_attempts(attempts)
+        .backoff(Duration::from_millis(1), Duration::from_millis(10))
+}
+
+/// Serve one canned response per connection, in order.
+///
+/// An empty response closes the connection without answering.
+fn serve(responses: Vec<String>) -> (String, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = thread::spawn(move || {
+        for resp in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut req = vec![];
+            let mut buf = [0; 1024];
+            while !req.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                req.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(resp.as_bytes()).unwrap();
+        }
+    });
+    (addr, server)
+}
+
+fn response(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[test]
+fn test_retry_exhausted() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let m = mock("GET", "/v2/retry/exhausted/manifests/latest")
+        .with_status(502)
+        .expect(3)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .retry_policy(fast_retries(3))
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.get_manifest("retry/exhausted", "latest");
+
+    let res = tcore.run(futcheck);
+    assert!(res.is_err());
+    m.assert();
+
+    mockito::reset();
+}
+
+#[test]
+fn test_retry_not_retryable() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let m = mock("GET", "/v2/retry/notfound/manifests/latest")
+        .with_status(404)
+        .expect(1)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .retry_policy(fast_retries(3))
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.get_manifest("retry/notfound", "latest");
+
+    let res = tcore.run(futcheck);
+    assert!(res.is_err());
+    m.assert();
+
+    mockito::reset();
+}
+
+#[test]
+fn test_retry_disabled() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let m = mock("GET", "/v2/retry/disabled/manifests/latest")
+        .with_status(503)
+        .expect(1)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .retry_policy(RetryPolicy::none())
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.get_manifest("retry/disabled", "latest");
+
+    let res = tcore.run(futcheck);
+    assert!(res.is_err());
+    m.assert();
+
+    mockito::reset();
+}
+
+#[test]
+fn test_retry_after() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let m = mock("GET", "/v2/retry/after/manifests/latest")
+        .with_status(429)
+        .with_header("Retry-After", "0")
+        .expect(2)
+        .create();
+
+    // The backoff is long enough to time out the test, unless `Retry-After` is used.
+    let policy = RetryPolicy::default()
+        .max_attempts(2)
+        .backoff(Duration::from_secs(600), Duration::from_secs(600));
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .retry_policy(policy)
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.get_manifest("retry/after", "latest");
+
+    let res = tcore.run(futcheck);
+    assert!(res.is_err());
+    m.assert();
+
+    mockito::reset();
+}
+
+#[test]
+fn test_retry_after_too_long() {
+    let addr = mockito::SERVER_ADDRESS.replace("127.0.0.1", "localhost");
+    let m = mock("GET", "/v2/retry/toolong/manifests/latest")
+        .with_status(503)
+        .with_header("Retry-After", "3600")
+        .expect(1)
+        .create();
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .retry_policy(fast_retries(3))
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.get_manifest("retry/toolong", "latest");
+
+    let res = tcore.run(futcheck);
+    assert!(res.is_err());
+    m.assert();
+
+    mockito::reset();
+}
+
+#[test]
+fn test_retry_success() {
+    let (addr, server) = serve(vec![
+        response("503 Service Unavailable", ""),
+        response("502 Bad Gateway", ""),
+        response("200 OK", MANIFEST),
+    ]);
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .retry_policy(fast_retries(3))
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.get_manifest("retry/success", "latest");
+
+    let res = tcore.run(futcheck).unwrap();
+    assert_eq!(res, MANIFEST.as_bytes());
+    server.join().unwrap();
+}
+
+#[test]
+fn test_retry_connection_closed() {
+    let (addr, server) = serve(vec!["".to_string(), response("200 OK", MANIFEST)]);
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .retry_policy(fast_retries(2))
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.get_manifest("retry/closed", "latest");
+
+    let res = tcore.run(futcheck).unwrap();
+    assert_eq!(res, MANIFEST.as_bytes());
+    server.join().unwrap();
+}
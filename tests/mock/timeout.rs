@@ -0,0 +1,128 @@
+extern crate dkregistry;
+extern crate tokio_core;
+
+use self::dkregistry::digest::{Algorithm, Digest};
+use self::dkregistry::errors::{ErrorKind, TimeoutKind};
+use self::dkregistry::v2::{RetryPolicy, Timeouts};
+use self::tokio_core::reactor::Core;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Answer a single connection with a partial response, then stall until notified.
+fn serve_stalled(partial: &'static str) -> (String, mpsc::Sender<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 1024];
+        let _ = stream.read(&mut buf);
+        stream.write_all(partial.as_bytes()).unwrap();
+        let _ = rx.recv();
+    });
+    (addr, tx)
+}
+
+fn timeout_kind(err: &dkregistry::errors::Error) -> Option<TimeoutKind> {
+    match *err.kind() {
+        ErrorKind::Timeout(kind, _) => Some(kind),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_timeout_first_byte() {
+    let (addr, done) = serve_stalled("");
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .retry_policy(RetryPolicy::none())
+        .metadata_timeouts(Timeouts::metadata().first_byte(Some(Duration::from_millis(100))))
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.get_manifest("timeout/firstbyte", "latest");
+
+    let err = tcore.run(futcheck).unwrap_err();
+    assert_eq!(timeout_kind(&err), Some(TimeoutKind::FirstByte));
+    done.send(()).unwrap();
+}
+
+#[test]
+fn test_timeout_idle() {
+    let partial = "HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\npartial";
+    let (addr, done) = serve_stalled(partial);
+
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .retry_policy(RetryPolicy::none())
+        .blob_timeouts(Timeouts::blob().idle(Some(Duration::from_millis(100))))
+        .build()
+        .unwrap();
+
+    let futblob = dclient.get_blob("timeout/idle", &Digest::from_bytes(Algorithm::Sha256, b""));
+
+    let err = tcore.run(futblob).unwrap_err();
+    assert_eq!(timeout_kind(&err), Some(TimeoutKind::Idle));
+    done.send(()).unwrap();
+}
+
+#[test]
+fn test_timeout_total() {
+    let (addr, done) = serve_stalled("");
+
+    let timeouts = Timeouts::none().total(Some(Duration::from_millis(100)));
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .metadata_timeouts(timeouts)
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.is_v2_supported();
+
+    let err = tcore.run(futcheck).unwrap_err();
+    assert_eq!(timeout_kind(&err), Some(TimeoutKind::Total));
+    done.send(()).unwrap();
+}
+
+#[test]
+fn test_timeout_retried() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = thread::spawn(move || {
+        // Keep the first connection open without answering, while serving the retry.
+        let (_stalled, _) = listener.accept().unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 1024];
+        let _ = stream.read(&mut buf);
+        let resp = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}";
+        stream.write_all(resp.as_bytes()).unwrap();
+    });
+
+    let policy = RetryPolicy::default()
+        .max_attempts(2)
+        .backoff(Duration::from_millis(1), Duration::from_millis(10));
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(&addr)
+        .insecure_registry(true)
+        .retry_policy(policy)
+        .metadata_timeouts(Timeouts::metadata().first_byte(Some(Duration::from_millis(100))))
+        .build()
+        .unwrap();
+
+    let futcheck = dclient.get_manifest("timeout/retried", "latest");
+
+    let res = tcore.run(futcheck).unwrap();
+    assert_eq!(res, b"{}");
+    server.join().unwrap();
+}
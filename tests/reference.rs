@@ -1,8 +1,12 @@
 extern crate dkregistry;
+extern crate serde_json;
 extern crate spectral;
 
-use dkregistry::reference::Reference;
+use dkregistry::digest::Digest;
+use dkregistry::errors::ErrorKind;
+use dkregistry::reference::{ParseError, Reference, Version};
 use spectral::prelude::*;
+use std::collections::HashSet;
 use std::str::FromStr;
 
 #[test]
@@ -34,3 +38,267 @@ fn test_reference_error() {
         asserting(t).that(&r).is_err();
     }
 }
+
+#[test]
+fn test_reference_components() {
+    let digest = "sha256:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+    let tagged = format!("busybox:latest@{}", digest);
+    let tcases = vec![
+        ("localhost:5000/foo", "localhost:5000", "foo", "latest"),
+        ("localhost/foo:1.0", "localhost", "foo", "1.0"),
+        ("quay.io/a/b/c/d:v1", "quay.io", "a/b/c/d", "v1"),
+        (
+            "example.com:443/ns/repo",
+            "example.com:443",
+            "ns/repo",
+            "latest",
+        ),
+        ("[::1]:5000/foo:tag", "[::1]:5000", "foo", "tag"),
+        ("Registry/foo", "Registry", "foo", "latest"),
+        ("foo/bar/baz", "docker.io", "foo/bar/baz", "latest"),
+        ("docker://a.b/c__d-e.f---g", "a.b", "c__d-e.f---g", "latest"),
+        (&tagged, "docker.io", "library/busybox", digest),
+    ];
+
+    for (t, registry, repository, version) in tcases {
+        let r = Reference::from_str(t);
+        asserting(t).that(&r).is_ok();
+        let r = r.unwrap();
+        asserting(t)
+            .that(&r.registry().as_str())
+            .is_equal_to(registry);
+        asserting(t)
+            .that(&r.repository().as_str())
+            .is_equal_to(repository);
+        asserting(t)
+            .that(&r.version().as_str())
+            .is_equal_to(version);
+    }
+}
+
+#[test]
+fn test_reference_parse_error() {
+    let tcases = vec![
+        ("", ParseError::EmptyName),
+        ("quay.io/", ParseError::InvalidPathComponent("".into())),
+        (
+            "Busybox",
+            ParseError::InvalidPathComponent("Busybox".into()),
+        ),
+        (
+            "quay.io/Foo/bar",
+            ParseError::InvalidPathComponent("Foo".into()),
+        ),
+        (
+            "foo/bar_-baz",
+            ParseError::InvalidPathComponent("bar_-baz".into()),
+        ),
+        (
+            "foo/bar___baz",
+            ParseError::InvalidPathComponent("bar___baz".into()),
+        ),
+        ("foo/-bar", ParseError::InvalidPathComponent("-bar".into())),
+        ("-host.io/foo", ParseError::InvalidDomain("-host.io".into())),
+        (
+            "host.io:port/foo",
+            ParseError::InvalidDomain("host.io:port".into()),
+        ),
+        ("[::1/foo", ParseError::InvalidDomain("[::1".into())),
+        (
+            "[zz::1]:5000/foo",
+            ParseError::InvalidDomain("[zz::1]:5000".into()),
+        ),
+        ("busybox:", ParseError::InvalidTag("".into())),
+        ("busybox:.tag", ParseError::InvalidTag(".tag".into())),
+        ("busybox@sha256", ParseError::InvalidDigest("sha256".into())),
+        (
+            "busybox@sha256:abcd",
+            ParseError::InvalidDigest("sha256:abcd".into()),
+        ),
+        (
+            "busybox@md5:ffffffffffffffffffffffffffffffff",
+            ParseError::UnsupportedDigestAlgorithm("md5".into()),
+        ),
+    ];
+
+    for (t, expected) in tcases {
+        match *Reference::from_str(t).unwrap_err().kind() {
+            ErrorKind::InvalidReference(ref e) => asserting(t).that(e).is_equal_to(&expected),
+            ref e => panic!("{}: unexpected error {}", t, e),
+        }
+    }
+
+    let long_tag = format!("busybox:{}", "t".repeat(129));
+    asserting(&long_tag)
+        .that(&Reference::from_str(&long_tag))
+        .is_err();
+
+    let long_name = format!("quay.io/{}", "n".repeat(255));
+    match *Reference::from_str(&long_name).unwrap_err().kind() {
+        ErrorKind::InvalidReference(ParseError::NameTooLong(263)) => {}
+        ref e => panic!("unexpected error {}", e),
+    }
+
+    let upper_digest =
+        "busybox@sha256:FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF";
+    asserting(upper_digest)
+        .that(&Reference::from_str(upper_digest))
+        .is_err();
+}
+
+#[test]
+fn test_reference_normalize() {
+    let digest = "sha256:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+    let by_digest = format!("index.docker.io/library/busybox@{}", digest);
+    let canonical_digest = format!("docker.io/library/busybox@{}", digest);
+    let familiar_digest = format!("busybox@{}", digest);
+    let tcases = vec![
+        ("busybox", "docker.io/library/busybox:latest", "busybox"),
+        (
+            "docker.io/busybox:1.31",
+            "docker.io/library/busybox:1.31",
+            "busybox:1.31",
+        ),
+        (
+            "registry-1.docker.io/library/busybox",
+            "docker.io/library/busybox:latest",
+            "busybox",
+        ),
+        (
+            "library/busybox",
+            "docker.io/library/busybox:latest",
+            "busybox",
+        ),
+        (
+            "docker.io/library/a/b",
+            "docker.io/library/a/b:latest",
+            "library/a/b",
+        ),
+        ("user/repo:v1", "docker.io/user/repo:v1", "user/repo:v1"),
+        (
+            "quay.io/coreos/etcd",
+            "quay.io/coreos/etcd:latest",
+            "quay.io/coreos/etcd",
+        ),
+        (
+            "localhost:5000/foo:1",
+            "localhost:5000/foo:1",
+            "localhost:5000/foo:1",
+        ),
+        (&by_digest, &canonical_digest, &familiar_digest),
+    ];
+
+    for (t, canonical, familiar) in tcases {
+        let r = Reference::from_str(t).unwrap();
+        asserting(t)
+            .that(&r.canonical().as_str())
+            .is_equal_to(canonical);
+        asserting(t)
+            .that(&r.to_string().as_str())
+            .is_equal_to(canonical);
+        asserting(t)
+            .that(&r.familiar().as_str())
+            .is_equal_to(familiar);
+    }
+}
+
+#[test]
+fn test_reference_endpoint() {
+    let tcases = vec![
+        ("busybox", "docker.io", "registry-1.docker.io"),
+        (
+            "index.docker.io/foo/bar",
+            "docker.io",
+            "registry-1.docker.io",
+        ),
+        ("quay.io/foo/bar", "quay.io", "quay.io"),
+    ];
+
+    for (t, registry, endpoint) in tcases {
+        let r = Reference::from_str(t).unwrap();
+        asserting(t)
+            .that(&r.registry().as_str())
+            .is_equal_to(registry);
+        asserting(t)
+            .that(&r.endpoint().as_str())
+            .is_equal_to(endpoint);
+    }
+
+    let r = Reference::new(
+        Some("registry-1.docker.io".into()),
+        "library/busybox".into(),
+        None,
+    );
+    assert_eq!(r.registry(), "docker.io");
+    assert_eq!(r.familiar_name(), "busybox");
+    assert_eq!(r.canonical_name(), "docker.io/library/busybox");
+}
+
+#[test]
+fn test_reference_tag_digest() {
+    let digest = "sha256:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+    let r = Reference::from_str(&format!("quay.io/coreos/etcd:v3.3@{}", digest)).unwrap();
+    assert_eq!(r.version(), digest);
+    assert_eq!(r.tag(), Some("v3.3".to_string()));
+    assert_eq!(r.digest(), Some(Digest::from_str(digest).unwrap()));
+    assert_eq!(
+        r.canonical(),
+        format!("quay.io/coreos/etcd:v3.3@{}", digest)
+    );
+
+    let v = Version::from_str(&format!(":v3.3@{}", digest)).unwrap();
+    let expected = Version::TagDigest("v3.3".into(), Digest::from_str(digest).unwrap());
+    assert_eq!(v, expected);
+    assert_eq!(v.tag(), Some("v3.3"));
+    assert!(Version::from_str(":v3.3@sha256:abcd").is_err());
+}
+
+#[test]
+fn test_reference_eq_hash() {
+    let same = [
+        "busybox",
+        "docker://busybox:latest",
+        "library/busybox",
+        "index.docker.io/library/busybox:latest",
+    ];
+    let set: HashSet<Reference> = same
+        .iter()
+        .map(|t| Reference::from_str(t).unwrap())
+        .collect();
+    assert_eq!(set.len(), 1);
+
+    let busybox = Reference::from_str("busybox").unwrap();
+    assert_ne!(busybox, Reference::from_str("busybox:1.31").unwrap());
+    assert_ne!(
+        busybox,
+        Reference::from_str("quay.io/library/busybox").unwrap()
+    );
+    assert_eq!(
+        Version::from_str(":latest").unwrap(),
+        Version::Tag("latest".into())
+    );
+}
+
+#[test]
+fn test_reference_serde() {
+    let digest = "sha256:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+    let r = Reference::from_str(&format!("busybox:1.31@{}", digest)).unwrap();
+    let json = serde_json::to_string(&r).unwrap();
+    assert_eq!(
+        json,
+        format!("\"docker.io/library/busybox:1.31@{}\"", digest)
+    );
+    let back: Reference = serde_json::from_str(&json).unwrap();
+    assert_eq!(back, r);
+
+    let refs: Vec<Reference> =
+        serde_json::from_str(r#"["busybox", "quay.io/coreos/etcd:v3.3"]"#).unwrap();
+    assert_eq!(refs[1].registry(), "quay.io");
+    assert!(serde_json::from_str::<Reference>(r#""Busybox""#).is_err());
+    assert!(serde_json::from_str::<Reference>("42").is_err());
+
+    let v: Version = serde_json::from_str(r#"":v1""#).unwrap();
+    assert_eq!(v, Version::Tag("v1".into()));
+    assert_eq!(serde_json::to_string(&v).unwrap(), r#"":v1""#);
+    assert!(serde_json::from_str::<Version>(r#""v1""#).is_err());
+}
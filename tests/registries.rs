@@ -0,0 +1,234 @@
+extern crate dkregistry;
+
+use dkregistry::reference::Reference;
+use dkregistry::registries::{RegistriesConf, ShortNameMode};
+use std::fs::File;
+use std::path::Path;
+
+static DIGEST: &str = "sha256:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+
+fn fixture() -> RegistriesConf {
+    let fp = File::open("tests/fixtures/registries.conf").unwrap();
+    RegistriesConf::from_reader(fp).unwrap()
+}
+
+fn names(refs: &[Reference]) -> Vec<String> {
+    refs.iter().map(|r| r.to_string()).collect()
+}
+
+#[test]
+fn test_registries_parse() {
+    let conf = fixture();
+    assert_eq!(
+        conf.unqualified_search_registries,
+        Some(vec![
+            "registry.fedoraproject.org".to_string(),
+            "docker.io".to_string()
+        ])
+    );
+    assert_eq!(conf.short_name_mode, Some(ShortNameMode::Enforcing));
+    assert_eq!(conf.registries.len(), 3);
+    assert!(conf.registries[0].insecure);
+    assert_eq!(
+        conf.registries[0].mirrors[0].location,
+        "mirror.example.com/foo"
+    );
+    assert!(conf.registries[1].blocked);
+    assert!(conf.registries[2].mirror_by_digest_only);
+    assert_eq!(conf.aliases["ubi"], "registry.access.redhat.com/ubi9");
+}
+
+#[test]
+fn test_registries_short_name_alias() {
+    let conf = fixture();
+    let refs = conf.resolve_short_name("ubi:9.3").unwrap();
+    assert_eq!(names(&refs), vec!["registry.access.redhat.com/ubi9:9.3"]);
+}
+
+#[test]
+fn test_registries_short_name_search() {
+    let mut conf = fixture();
+    conf.short_name_mode = Some(ShortNameMode::Permissive);
+    let refs = conf.resolve_short_name("busybox").unwrap();
+    assert_eq!(
+        names(&refs),
+        vec![
+            "registry.fedoraproject.org/busybox:latest",
+            "docker.io/library/busybox:latest",
+        ]
+    );
+
+    let empty = RegistriesConf::default();
+    assert!(empty.resolve_short_name("busybox").is_err());
+}
+
+#[test]
+fn test_registries_short_name_mode() {
+    // Enforcing mode rejects ambiguous short names, but not aliases.
+    let mut conf = fixture();
+    assert!(conf.resolve_short_name("busybox").is_err());
+    assert!(conf.resolve("busybox").is_err());
+    let refs = conf.resolve_short_name("ubi").unwrap();
+    assert_eq!(names(&refs), vec!["registry.access.redhat.com/ubi9:latest"]);
+
+    conf.unqualified_search_registries = Some(vec!["registry.fedoraproject.org".to_string()]);
+    let refs = conf.resolve_short_name("busybox").unwrap();
+    assert_eq!(
+        names(&refs),
+        vec!["registry.fedoraproject.org/busybox:latest"]
+    );
+
+    // Disabled mode ignores aliases.
+    conf.short_name_mode = Some(ShortNameMode::Disabled);
+    let refs = conf.resolve_short_name("ubi").unwrap();
+    assert_eq!(names(&refs), vec!["registry.fedoraproject.org/ubi:latest"]);
+}
+
+#[test]
+fn test_registries_qualified_name() {
+    let conf = fixture();
+    let name = format!("quay.io/coreos/etcd@{}", DIGEST);
+    let refs = conf.resolve_short_name(&name).unwrap();
+    assert_eq!(names(&refs), vec![name]);
+
+    let refs = conf.resolve_short_name("localhost/app:1").unwrap();
+    assert_eq!(names(&refs), vec!["localhost/app:1"]);
+}
+
+#[test]
+fn test_registries_rewrite_and_mirrors() {
+    let conf = fixture();
+    let sources = conf.resolve("example.com/foo/app:1").unwrap();
+    let locations: Vec<String> = sources.iter().map(|s| s.reference.to_string()).collect();
+    assert_eq!(
+        locations,
+        vec![
+            "mirror.example.com/foo/app:1",
+            "internal.example.com/bar/app:1",
+        ]
+    );
+    assert!(!sources[0].insecure);
+    assert!(sources[1].insecure);
+
+    // `example.com/foobar` is not within the `example.com/foo` namespace.
+    let sources = conf.resolve("example.com/foobar/app").unwrap();
+    assert_eq!(sources.len(), 1);
+    assert_eq!(sources[0].reference.registry(), "example.com");
+}
+
+#[test]
+fn test_registries_mirror_by_digest_only() {
+    let conf = fixture();
+    let by_tag = Reference::new(None, "library/busybox".to_string(), None);
+    let sources = conf.pull_sources(&by_tag).unwrap();
+    assert_eq!(sources.len(), 1);
+
+    let sources = conf
+        .resolve(&format!("docker.io/library/busybox@{}", DIGEST))
+        .unwrap();
+    assert_eq!(sources.len(), 2);
+    assert_eq!(sources[0].reference.registry(), "localhost:5000");
+    assert_eq!(sources[0].reference.repository(), "library/busybox");
+    assert!(sources[0].insecure);
+    assert_eq!(sources[1].reference.registry(), "docker.io");
+    assert_eq!(sources[1].reference.endpoint(), "registry-1.docker.io");
+}
+
+#[test]
+fn test_registries_blocked() {
+    let conf = fixture();
+    assert!(conf.resolve("blocked.example.com/app").is_err());
+}
+
+#[test]
+fn test_registries_wildcard() {
+    let conf = RegistriesConf::from_reader(
+        r#"
+        [[registry]]
+        prefix = "*.example.com"
+        blocked = true
+        "#
+        .as_bytes(),
+    )
+    .unwrap();
+    assert!(conf.find_registry("sub.example.com/app").is_some());
+    assert!(conf.find_registry("example.com/app").is_none());
+    assert!(conf.resolve("sub.example.com:5000/app").is_err());
+}
+
+#[test]
+fn test_registries_wildcard_mirrors() {
+    let conf = RegistriesConf::from_reader(
+        r#"
+        [[registry]]
+        prefix = "*.example.com"
+
+        [[registry.mirror]]
+        location = "mirror.local/example"
+        "#
+        .as_bytes(),
+    )
+    .unwrap();
+    let sources = conf.resolve("sub.example.com/app:1").unwrap();
+    let locations: Vec<String> = sources.iter().map(|s| s.reference.to_string()).collect();
+    assert_eq!(
+        locations,
+        vec!["mirror.local/example/app:1", "sub.example.com/app:1"]
+    );
+}
+
+#[test]
+fn test_registries_invalid() {
+    let tcases = vec![
+        "[registries.search]\nregistries = [\"docker.io\"]",
+        "[[registry]]\ninsecure = true",
+        "[[registry]]\nprefix = \"example.*.com\"",
+        "[[registry]]\nprefix = \"*.example.com\"\nlocation = \"example.com\"",
+        "unqualified-search-registries = \"docker.io\"",
+        "[aliases]\n\"busybox\" = \"busybox\"",
+        "[aliases]\n\"busybox\" = \"docker.io/\"",
+        "[aliases]\n\"busybox\" = \"docker.io/library/busybox:latest\"",
+        "[aliases]\n\"docker.io/busybox\" = \"docker.io/library/busybox\"",
+        "[aliases]\n\"busybox:latest\" = \"docker.io/library/busybox\"",
+    ];
+
+    for t in tcases {
+        assert!(RegistriesConf::from_reader(t.as_bytes()).is_err(), "{}", t);
+    }
+}
+
+#[test]
+fn test_registries_dropins() {
+    let conf = RegistriesConf::load(Path::new("tests/fixtures/registries.conf")).unwrap();
+    assert_eq!(
+        conf.unqualified_search_registries,
+        Some(vec!["quay.io".to_string()])
+    );
+    assert_eq!(conf.registries.len(), 3);
+    assert_eq!(conf.aliases["ubi"], "registry.access.redhat.com/ubi8");
+    assert_eq!(conf.aliases["fedora"], "registry.fedoraproject.org/fedora");
+
+    let sources = conf.resolve("fedora").unwrap();
+    assert_eq!(
+        sources[0].reference.to_string(),
+        "registry.fedoraproject.org/fedora:latest"
+    );
+    assert!(conf.resolve("blocked.example.com/app").is_ok());
+
+    // An explicit empty list clears the search registries.
+    let mut cleared = conf.clone();
+    cleared.merge(
+        RegistriesConf::from_reader("unqualified-search-registries = []".as_bytes()).unwrap(),
+    );
+    assert_eq!(cleared.unqualified_search_registries, Some(vec![]));
+    assert!(cleared.resolve("busybox").is_err());
+    let mut kept = conf.clone();
+    kept.merge(RegistriesConf::from_reader("short-name-mode = \"disabled\"".as_bytes()).unwrap());
+    assert_eq!(
+        kept.unqualified_search_registries,
+        conf.unqualified_search_registries
+    );
+
+    let missing = RegistriesConf::load(Path::new("tests/fixtures/missing.conf")).unwrap();
+    assert_eq!(missing, RegistriesConf::default());
+}
@@ -0,0 +1,727 @@
+extern crate dkregistry;
+extern crate futures;
+extern crate libflate;
+extern crate tar;
+extern crate xattr;
+extern crate zstd;
+
+mod common;
+
+use common::{list, scratch_dir};
+use dkregistry::mediatypes::MediaTypes;
+use dkregistry::render::{
+    Compression, FileFinder, FileType, IdMapping, Index, Ownership, Unpacker,
+};
+use futures::Future;
+use libflate::gzip;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::{fs, path};
+
+/// A layer entry: a regular file with content, a directory, a symlink or a
+/// hard link.
+enum Entry<'a> {
+    File(&'a str, &'a str),
+    Dir(&'a str),
+    Link(&'a str, &'a str),
+    HardLink(&'a str, &'a str),
+}
+
+/// Build a gzip-compressed tar layer.
+fn layer(entries: &[Entry]) -> Vec<u8> {
+    gzip_layer(&tar_layer(entries))
+}
+
+/// Build an uncompressed tar layer.
+fn tar_layer(entries: &[Entry]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for e in entries {
+        let mut header = tar::Header::new_gnu();
+        let (path, data) = match *e {
+            Entry::File(path, data) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(0o644);
+                (path, data)
+            }
+            Entry::Dir(path) => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(0o755);
+                (path, "")
+            }
+            Entry::Link(path, target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_link_name(target).unwrap();
+                header.set_mode(0o777);
+                (path, "")
+            }
+            Entry::HardLink(path, target) => {
+                header.set_entry_type(tar::EntryType::Link);
+                header.set_link_name(target).unwrap();
+                header.set_mode(0o644);
+                (path, "")
+            }
+        };
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        header.set_size(data.len() as u64);
+        builder
+            .append_data(&mut header, path, data.as_bytes())
+            .unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+/// Gzip-compress a tar archive.
+fn gzip_layer(tar: &[u8]) -> Vec<u8> {
+    let mut encoder = gzip::Encoder::new(Vec::new()).unwrap();
+    encoder.write_all(tar).unwrap();
+    encoder.finish().into_result().unwrap()
+}
+
+#[test]
+fn test_render_opaque_whiteout() {
+    let lower = layer(&[
+        Entry::Dir("etc"),
+        Entry::File("etc/hosts", "lower"),
+        Entry::Dir("etc/conf.d"),
+        Entry::File("etc/conf.d/old", "old"),
+        Entry::File("keep", "keep"),
+    ]);
+    let upper = layer(&[
+        Entry::Dir("etc"),
+        Entry::File("etc/.wh..wh..opq", ""),
+        Entry::File("etc/hosts", "upper"),
+        Entry::File("etc/new", "new"),
+    ]);
+
+    let dir = scratch_dir("opaque");
+    dkregistry::render::unpack(&[lower, upper], &dir).unwrap();
+
+    assert_eq!(list(&dir), vec!["etc", "etc/hosts", "etc/new", "keep"]);
+    assert_eq!(fs::read_to_string(dir.join("etc/hosts")).unwrap(), "upper");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_render_opaque_whiteout_new_dir() {
+    let lower = layer(&[Entry::File("file", "lower")]);
+    let upper = layer(&[
+        Entry::Dir("data"),
+        Entry::File("data/.wh..wh..opq", ""),
+        Entry::File("data/file", "upper"),
+    ]);
+
+    let dir = scratch_dir("opaque-new");
+    dkregistry::render::unpack(&[lower, upper], &dir).unwrap();
+
+    assert_eq!(list(&dir), vec!["data", "data/file", "file"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_render_wrong_target() {
+    let res = dkregistry::render::unpack(&[], path::Path::new("relative"));
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_render_whiteout() {
+    let lower = layer(&[
+        Entry::File("file", "file"),
+        Entry::Link("link", "file"),
+        Entry::Dir("dir"),
+        Entry::File("dir/nested", "nested"),
+        Entry::File("keep", "keep"),
+    ]);
+    let upper = layer(&[
+        Entry::File(".wh.file", ""),
+        Entry::File(".wh.link", ""),
+        Entry::File(".wh.dir", ""),
+        Entry::File(".wh.missing", ""),
+    ]);
+
+    let dir = scratch_dir("whiteout");
+    dkregistry::render::unpack(&[lower, upper], &dir).unwrap();
+
+    assert_eq!(list(&dir), vec!["keep"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_render_whiteout_dangling_symlink() {
+    let outside = scratch_dir("dangling-outside");
+    fs::write(outside.join("secret"), "secret").unwrap();
+    let target = outside.join("secret").to_string_lossy().into_owned();
+
+    let lower = layer(&[Entry::Link("link", &target)]);
+    let upper = layer(&[Entry::File(".wh.link", "")]);
+
+    let dir = scratch_dir("dangling");
+    dkregistry::render::unpack(&[lower, upper], &dir).unwrap();
+
+    assert!(list(&dir).is_empty());
+    assert!(outside.join("secret").exists());
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_dir_all(&outside).unwrap();
+}
+
+#[test]
+fn test_render_reject_traversal() {
+    let mut header = tar::Header::new_old();
+    header.as_old_mut().name[..10].copy_from_slice(b"../escaped");
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_mode(0o644);
+    header.set_size(0);
+    header.set_cksum();
+    let mut builder = tar::Builder::new(Vec::new());
+    builder.append(&header, io::empty()).unwrap();
+    let evil = gzip_layer(&builder.into_inner().unwrap());
+
+    let dir = scratch_dir("traversal");
+    let target = dir.join("target");
+    fs::create_dir(&target).unwrap();
+    assert!(dkregistry::render::unpack(&[evil], &target).is_err());
+    assert!(!dir.join("escaped").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_render_reject_symlink_escape() {
+    let outside = scratch_dir("escape-outside");
+    fs::write(outside.join("secret"), "secret").unwrap();
+    let target = outside.to_string_lossy().into_owned();
+    let lower = layer(&[Entry::Link("etc", &target)]);
+
+    let tcases = vec![
+        layer(&[Entry::File("etc/passwd", "evil")]),
+        layer(&[Entry::File("etc/.wh.secret", "")]),
+        layer(&[Entry::File("etc/.wh..wh..opq", "")]),
+    ];
+
+    for (i, upper) in tcases.into_iter().enumerate() {
+        let dir = scratch_dir(&format!("escape-{}", i));
+        let res = dkregistry::render::unpack(&[lower.clone(), upper], &dir);
+        assert!(res.is_err(), "case {}", i);
+        assert_eq!(list(&outside), vec!["secret"], "case {}", i);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // A directory entry replaces the symlink, rather than following it.
+    let upper = layer(&[Entry::Dir("etc"), Entry::File("etc/passwd", "ok")]);
+    let dir = scratch_dir("escape-dir");
+    dkregistry::render::unpack(&[lower, upper], &dir).unwrap();
+    assert_eq!(list(&dir), vec!["etc", "etc/passwd"]);
+    assert_eq!(list(&outside), vec!["secret"]);
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_dir_all(&outside).unwrap();
+}
+
+#[test]
+fn test_render_layer_same_layer_whiteout() {
+    // Whiteouts only hide lower-layer entries, wherever they appear.
+    let lower = layer(&[Entry::File("old", "old"), Entry::File("dir/old", "old")]);
+    let upper = layer(&[
+        Entry::File("new", "new"),
+        Entry::File(".wh.new", ""),
+        Entry::File(".wh.old", ""),
+        Entry::Dir("dir"),
+        Entry::File("dir/new", "new"),
+        Entry::File("dir/.wh..wh..opq", ""),
+    ]);
+
+    let dir = scratch_dir("same-layer");
+    dkregistry::render::unpack_layer(lower.as_slice(), &dir).unwrap();
+    dkregistry::render::unpack_layer(upper.as_slice(), &dir).unwrap();
+
+    assert_eq!(list(&dir), vec!["dir", "dir/new", "new"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_render_layer_stream() {
+    let lower = layer(&[Entry::Dir("etc"), Entry::File("etc/hosts", "lower")]);
+    let upper = layer(&[Entry::File("etc/.wh.hosts", ""), Entry::File("bin", "bin")]);
+
+    let dir = scratch_dir("stream");
+    for l in &[lower, upper] {
+        let chunks: Vec<Vec<u8>> = l.chunks(7).map(|c| c.to_vec()).collect();
+        let stream = futures::stream::iter_ok(chunks);
+        dkregistry::render::unpack_layer_stream(stream, &dir)
+            .wait()
+            .unwrap();
+    }
+    assert_eq!(list(&dir), vec!["bin", "etc"]);
+
+    // Truncated or failing streams are errors.
+    let broken = layer(&[Entry::File("broken", "broken")]);
+    let truncated = futures::stream::iter_ok(vec![broken[..10].to_vec()]);
+    let res = dkregistry::render::unpack_layer_stream(truncated, &dir).wait();
+    assert!(res.is_err());
+
+    let failing = futures::stream::iter_result(vec![
+        Ok(broken[..10].to_vec()),
+        Err("download failed".into()),
+    ]);
+    let res = dkregistry::render::unpack_layer_stream(failing, &dir).wait();
+    assert!(res.is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_render_compression_sniff() {
+    let entries = [Entry::File("file", "content")];
+    let tcases = vec![
+        (tar_layer(&entries), Compression::None),
+        (layer(&entries), Compression::Gzip),
+        (
+            zstd::encode_all(tar_layer(&entries).as_slice(), 0).unwrap(),
+            Compression::Zstd,
+        ),
+    ];
+
+    for (i, (data, expected)) in tcases.into_iter().enumerate() {
+        assert_eq!(Compression::sniff(&data), expected);
+        let dir = scratch_dir(&format!("sniff-{}", i));
+        dkregistry::render::unpack_layer(data.as_slice(), &dir).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("file")).unwrap(), "content");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn test_render_compression_media_type() {
+    let tcases = vec![
+        ("application/vnd.oci.image.layer.v1.tar", Compression::None),
+        (
+            "application/vnd.oci.image.layer.v1.tar+gzip",
+            Compression::Gzip,
+        ),
+        (
+            "application/vnd.oci.image.layer.v1.tar+zstd",
+            Compression::Zstd,
+        ),
+        (
+            "application/vnd.docker.image.rootfs.diff.tar.gzip",
+            Compression::Gzip,
+        ),
+    ];
+    for (mtype, expected) in tcases {
+        let mime = mtype.parse().unwrap();
+        let media_type = MediaTypes::from_mime(&mime).unwrap();
+        assert_eq!(media_type, MediaTypes::from_str(mtype).unwrap());
+        assert_eq!(media_type.to_mime().unwrap(), mime);
+        assert_eq!(Compression::from_media_type(&media_type), Some(expected));
+    }
+    assert_eq!(
+        Compression::from_media_type(&MediaTypes::ManifestV2S2),
+        None
+    );
+
+    // Zstd layers are decoded according to their media type.
+    let data = zstd::encode_all(tar_layer(&[Entry::File("file", "zstd")]).as_slice(), 0).unwrap();
+    let dir = scratch_dir("media-type");
+    let zstd_type = MediaTypes::OciImageLayerTzst;
+    dkregistry::render::unpack_layer_as(data.as_slice(), Some(&zstd_type), &dir).unwrap();
+    assert_eq!(fs::read_to_string(dir.join("file")).unwrap(), "zstd");
+    let gzip_type = MediaTypes::OciImageLayerTgz;
+    assert!(dkregistry::render::unpack_layer_as(data.as_slice(), Some(&gzip_type), &dir).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Build a PAX extended header record.
+fn pax_record(key: &str, value: &str) -> String {
+    let body = format!(" {}={}\n", key, value);
+    let mut len = body.len() + 1;
+    while len.to_string().len() + body.len() != len {
+        len += 1;
+    }
+    format!("{}{}", len, body)
+}
+
+/// Build a layer with a setuid file owned by 1000:1000, with extended
+/// attributes, and a block device.
+fn rootless_layer(uid: u64) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+
+    let pax = pax_record("SCHILY.xattr.user.keep", "yes")
+        + &pax_record("SCHILY.xattr.security.drop", "yes");
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::XHeader);
+    header.set_size(pax.len() as u64);
+    builder
+        .append_data(&mut header, "PaxHeader/file", pax.as_bytes())
+        .unwrap();
+
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_mode(0o4755);
+    header.set_uid(uid);
+    header.set_gid(1000);
+    header.set_size(4);
+    builder
+        .append_data(&mut header, "file", "file".as_bytes())
+        .unwrap();
+
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Block);
+    header.set_mode(0o600);
+    header.set_device_major(8).unwrap();
+    header.set_device_minor(0).unwrap();
+    header.set_size(0);
+    builder
+        .append_data(&mut header, "sda", io::empty())
+        .unwrap();
+
+    gzip_layer(&builder.into_inner().unwrap())
+}
+
+#[test]
+fn test_render_rootless() {
+    let unpacker = Unpacker::default()
+        .skip_devices(true)
+        .skip_xattrs(vec!["security.".to_string()]);
+
+    let dir = scratch_dir("rootless");
+    unpacker
+        .unpack_layer(rootless_layer(1000).as_slice(), &dir)
+        .unwrap();
+
+    assert_eq!(list(&dir), vec!["file"]);
+    let keep = xattr::get(dir.join("file"), "user.keep").unwrap();
+    assert_eq!(keep, Some(b"yes".to_vec()));
+    assert_eq!(xattr::get(dir.join("file"), "security.drop").unwrap(), None);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_render_ownership_mapping() {
+    use std::os::unix::fs::MetadataExt;
+
+    let mapping = IdMapping {
+        container_id: 0,
+        host_id: 100_000,
+        size: 65536,
+    };
+    assert_eq!(mapping.map(1000), Some(101_000));
+    assert_eq!(mapping.map(65535), Some(165_535));
+    assert_eq!(mapping.map(65536), None);
+
+    let dir = scratch_dir("ownership");
+    // Changing owners requires privileges.
+    if fs::metadata(&dir).unwrap().uid() != 0 {
+        fs::remove_dir_all(&dir).unwrap();
+        return;
+    }
+
+    let unpacker = Unpacker::default()
+        .ownership(Ownership::Map {
+            uids: vec![mapping],
+            gids: vec![mapping],
+        })
+        .skip_devices(true)
+        .skip_xattrs(vec!["security.".to_string()]);
+    unpacker
+        .unpack_layer(rootless_layer(1000).as_slice(), &dir)
+        .unwrap();
+
+    let meta = fs::symlink_metadata(dir.join("file")).unwrap();
+    assert_eq!((meta.uid(), meta.gid()), (101_000, 101_000));
+    assert_eq!(meta.mode() & 0o7777, 0o4755);
+
+    // IDs outside of mappings are rejected.
+    let res = unpacker.unpack_layer(rootless_layer(70_000).as_slice(), &dir);
+    assert!(res.is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_render_flatten() {
+    let long_name = format!("deep/{}/file", "d".repeat(120));
+    let lower = layer(&[
+        Entry::Dir("etc"),
+        Entry::File("etc/a", "a"),
+        Entry::File("etc/b", "b"),
+        Entry::Dir("replaced"),
+        Entry::File("replaced/x", "x"),
+        Entry::Dir("opq"),
+        Entry::File("opq/old", "old"),
+        Entry::File("gone", "gone"),
+        Entry::File(&long_name, "long"),
+        Entry::Link("link", "etc/b"),
+    ]);
+    let middle = layer(&[
+        Entry::File("etc/.wh.a", ""),
+        Entry::File("replaced", "now a file"),
+        Entry::File(".wh.gone", ""),
+        Entry::File("gone", "back again"),
+    ]);
+    let upper = layer(&[
+        Entry::Dir("opq"),
+        Entry::File("opq/.wh..wh..opq", ""),
+        Entry::File("opq/new", "new"),
+        Entry::File("etc/b", "upper b"),
+    ]);
+    let layers = vec![lower, middle, upper];
+
+    let flat = dkregistry::render::flatten(layers.iter().map(Vec::as_slice), vec![]).unwrap();
+    let flat_dir = scratch_dir("flatten");
+    dkregistry::render::unpack_layer(flat.as_slice(), &flat_dir).unwrap();
+
+    let unpacked_dir = scratch_dir("flatten-unpacked");
+    dkregistry::render::unpack(&layers, &unpacked_dir).unwrap();
+
+    let tree = list(&flat_dir);
+    assert_eq!(tree, list(&unpacked_dir));
+    assert!(!tree.contains(&"etc/a".to_string()));
+    assert!(!tree.contains(&"opq/old".to_string()));
+    assert!(tree.contains(&long_name));
+    for path in &tree {
+        let (flat_path, unpacked_path) = (flat_dir.join(path), unpacked_dir.join(path));
+        if flat_path.is_file() {
+            assert_eq!(
+                fs::read(&flat_path).unwrap(),
+                fs::read(&unpacked_path).unwrap(),
+                "{}",
+                path
+            );
+        }
+    }
+    assert_eq!(
+        fs::read_to_string(flat_dir.join("gone")).unwrap(),
+        "back again"
+    );
+    assert_eq!(
+        fs::read_to_string(flat_dir.join("link")).unwrap(),
+        "upper b"
+    );
+
+    // Each path appears once in the flattened archive.
+    let mut archive = tar::Archive::new(flat.as_slice());
+    let mut paths: Vec<String> = archive
+        .entries()
+        .unwrap()
+        .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+        .collect();
+    let count = paths.len();
+    paths.sort();
+    paths.dedup();
+    assert_eq!(paths.len(), count);
+
+    fs::remove_dir_all(&flat_dir).unwrap();
+    fs::remove_dir_all(&unpacked_dir).unwrap();
+}
+
+#[test]
+fn test_render_flatten_hard_links() {
+    let lower = layer(&[
+        Entry::Dir("etc"),
+        Entry::File("etc/a", "a"),
+        Entry::HardLink("etc/b", "etc/a"),
+        Entry::File("etc/c", "c"),
+        Entry::HardLink("etc/d", "etc/c"),
+        Entry::File("etc/e", "e"),
+        Entry::HardLink("etc/f", "etc/e"),
+    ]);
+    let upper = layer(&[
+        Entry::File("etc/.wh.a", ""),
+        Entry::File("etc/c", "upper c"),
+    ]);
+    let layers = vec![lower, upper];
+
+    let flat = dkregistry::render::flatten(layers.iter().map(Vec::as_slice), vec![]).unwrap();
+    let flat_dir = scratch_dir("flatten-hard-links");
+    dkregistry::render::unpack_layer(flat.as_slice(), &flat_dir).unwrap();
+
+    let unpacked_dir = scratch_dir("flatten-hard-links-unpacked");
+    dkregistry::render::unpack(&layers, &unpacked_dir).unwrap();
+
+    let tree = list(&flat_dir);
+    assert_eq!(tree, list(&unpacked_dir));
+    assert!(!tree.contains(&"etc/a".to_string()));
+    // Links to whited-out or replaced files keep the lower content.
+    for (path, content) in &[
+        ("etc/b", "a"),
+        ("etc/c", "upper c"),
+        ("etc/d", "c"),
+        ("etc/f", "e"),
+    ] {
+        assert_eq!(fs::read_to_string(flat_dir.join(path)).unwrap(), *content);
+        assert_eq!(
+            fs::read_to_string(unpacked_dir.join(path)).unwrap(),
+            *content
+        );
+    }
+    fs::remove_dir_all(&flat_dir).unwrap();
+    fs::remove_dir_all(&unpacked_dir).unwrap();
+}
+
+#[test]
+fn test_render_flatten_xattrs() {
+    let flat = dkregistry::render::flatten(vec![rootless_layer(1000).as_slice()], vec![]).unwrap();
+    let dir = scratch_dir("flatten-xattrs");
+    Unpacker::default()
+        .skip_devices(true)
+        .skip_xattrs(vec!["security.".to_string()])
+        .unpack_layer(flat.as_slice(), &dir)
+        .unwrap();
+
+    let keep = xattr::get(dir.join("file"), "user.keep").unwrap();
+    assert_eq!(keep, Some(b"yes".to_vec()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_render_index() {
+    let lower = layer(&[
+        Entry::Dir("etc"),
+        Entry::File("etc/a", "a"),
+        Entry::File("etc/b", "b"),
+        Entry::Dir("replaced"),
+        Entry::File("replaced/x", "x"),
+        Entry::Dir("opq"),
+        Entry::File("opq/old", "old"),
+        Entry::Link("link", "etc/b"),
+    ]);
+    let upper = layer(&[
+        Entry::File("etc/.wh.a", ""),
+        Entry::File("etc/b", "upper b"),
+        Entry::File("replaced", "now a file"),
+        Entry::File("opq/new", "new"),
+        Entry::File("opq/.wh..wh..opq", ""),
+    ]);
+
+    let index = Index::from_layers(vec![lower.as_slice(), upper.as_slice()]).unwrap();
+    let paths: Vec<String> = index
+        .iter()
+        .map(|e| e.path.to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(
+        paths,
+        vec!["etc", "etc/b", "link", "opq", "opq/new", "replaced"]
+    );
+    assert_eq!(index.len(), 6);
+
+    let b = index.get("/etc/b").unwrap();
+    assert_eq!(b.file_type, FileType::File);
+    assert_eq!((b.size, b.mode, b.layer), (7, 0o644, 1));
+    let link = index.get("link").unwrap();
+    assert_eq!(link.file_type, FileType::Symlink);
+    assert_eq!(link.link_target, Some(path::PathBuf::from("etc/b")));
+    assert_eq!(link.layer, 0);
+    assert_eq!(index.get("opq").unwrap().file_type, FileType::Directory);
+    assert_eq!(index.get("replaced").unwrap().layer, 1);
+    assert!(index.get("etc/a").is_none());
+
+    // The index matches the unpacked filesystem.
+    let dir = scratch_dir("index");
+    dkregistry::render::unpack(&[lower, upper], &dir).unwrap();
+    assert_eq!(list(&dir), paths);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_render_find_files() {
+    let base = layer(&[
+        Entry::Dir("etc"),
+        Entry::File("etc/a", "a"),
+        Entry::File("etc/hostname", "base"),
+        Entry::Dir("usr"),
+        Entry::Dir("usr/lib"),
+        Entry::File("usr/lib/os-release", "ID=base"),
+        Entry::Dir("usr/bin"),
+        Entry::File("usr/bin/tool", "tool v1"),
+        Entry::Link("bin", "usr/bin"),
+    ]);
+    let mid = layer(&[
+        Entry::File("etc/.wh.a", ""),
+        Entry::File("usr/lib/os-release", "ID=mid"),
+        Entry::File("usr/bin/tool", "tool v2"),
+    ]);
+    let top = layer(&[Entry::Link("etc/os-release", "../usr/lib/os-release")]);
+    let layers = [base, mid, top];
+
+    // Walk layers from the top, as many times as needed, counting fetches.
+    let find = |paths: &[&str]| {
+        let mut finder = FileFinder::new(paths).unwrap();
+        let mut fetched = 0;
+        loop {
+            for l in layers.iter().rev() {
+                if !finder.is_pending() {
+                    break;
+                }
+                finder.add_layer(l.as_slice()).unwrap();
+                fetched += 1;
+            }
+            if !finder.restart() {
+                break;
+            }
+        }
+        (finder.into_files(), fetched)
+    };
+
+    let (files, fetched) = find(&["/etc/os-release", "etc/a", "bin/tool", "etc", "missing"]);
+    let mut keys: Vec<&String> = files.keys().collect();
+    keys.sort();
+    assert_eq!(keys, vec!["/etc/os-release", "bin/tool"]);
+    assert_eq!(files["/etc/os-release"], b"ID=mid");
+    // The symlink in the base layer points to a file replaced in the middle one.
+    assert_eq!(files["bin/tool"], b"tool v2");
+    assert_eq!(fetched, 5);
+
+    // Lower layers are not needed once everything is found.
+    let (files, fetched) = find(&["etc/os-release"]);
+    assert_eq!(files["etc/os-release"], b"ID=mid");
+    assert_eq!(fetched, 2);
+    let (files, fetched) = find(&["etc/hostname"]);
+    assert_eq!(files["etc/hostname"], b"base");
+    assert_eq!(fetched, 3);
+
+    assert!(FileFinder::new(&["../etc/passwd"]).is_err());
+}
+
+#[test]
+fn test_render_find_files_links() {
+    let base = layer(&[
+        Entry::File("etc/a", "base"),
+        Entry::HardLink("etc/b", "etc/a"),
+        Entry::Link("forward", "etc/c"),
+        Entry::File("etc/c", "c"),
+        Entry::Link("backward", "etc/a"),
+    ]);
+    let top = layer(&[Entry::File("etc/a", "top")]);
+    let layers = [base, top];
+
+    let find = |paths: &[&str]| {
+        let mut finder = FileFinder::new(paths).unwrap();
+        let mut walks = 0;
+        loop {
+            walks += 1;
+            for l in layers.iter().rev() {
+                if !finder.is_pending() {
+                    break;
+                }
+                finder.add_layer(l.as_slice()).unwrap();
+            }
+            if !finder.restart() {
+                break;
+            }
+        }
+        (finder.into_files(), walks)
+    };
+
+    // A hard link reads its target from its own layer, even if overridden.
+    let (files, walks) = find(&["etc/b"]);
+    assert_eq!(files["etc/b"], b"base");
+    assert_eq!(walks, 2);
+
+    // A symlink to a later entry of the same layer is followed in one walk.
+    let (files, walks) = find(&["forward"]);
+    assert_eq!(files["forward"], b"c");
+    assert_eq!(walks, 1);
+
+    // A symlink to an upper layer needs another walk from the top.
+    let (files, walks) = find(&["backward"]);
+    assert_eq!(files["backward"], b"top");
+    assert_eq!(walks, 2);
+}
@@ -0,0 +1,193 @@
+extern crate dkregistry;
+extern crate rustls;
+extern crate tokio_core;
+
+use rustls::internal::pemfile;
+use rustls::Session;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{mpsc, Arc};
+use std::{env, fs, path, thread};
+use tokio_core::reactor::Core;
+
+static FIXTURES: &str = "tests/fixtures/certs.d";
+static SERVER_FIXTURES: &str = "tests/fixtures/tls";
+
+fn fixture(name: &str) -> Vec<u8> {
+    fs::read(path::Path::new(FIXTURES).join("registry.test").join(name)).unwrap()
+}
+
+fn server_fixture(name: &str) -> Vec<u8> {
+    fs::read(path::Path::new(SERVER_FIXTURES).join(name)).unwrap()
+}
+
+/// Client certificates presented on a successful request, if any.
+type PeerCerts = Option<Vec<rustls::Certificate>>;
+
+/// Start a registry serving `/v2/` over TLS on localhost, with a certificate
+/// signed by the test CA. Client certificates signed by that CA are accepted,
+/// but not required.
+fn tls_registry() -> (String, mpsc::Receiver<PeerCerts>) {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_pem_file(&mut &fixture("ca.crt")[..]).unwrap();
+    let verifier = rustls::AllowAnyAnonymousOrAuthenticatedClient::new(roots);
+    let mut config = rustls::ServerConfig::new(verifier);
+    let chain = pemfile::certs(&mut &server_fixture("server.crt")[..]).unwrap();
+    let mut keys = pemfile::pkcs8_private_keys(&mut &server_fixture("server.key")[..]).unwrap();
+    config.set_single_cert(chain, keys.remove(0)).unwrap();
+    let config = Arc::new(config);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for tcp in listener.incoming() {
+            let mut tcp = tcp.unwrap();
+            let mut session = rustls::ServerSession::new(&config);
+            let mut request = vec![];
+            {
+                let mut stream = rustls::Stream::new(&mut session, &mut tcp);
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(n) if n > 0 => request.extend_from_slice(&buf[..n]),
+                        // Failed handshake or closed connection.
+                        _ => break,
+                    }
+                }
+                if !request.ends_with(b"\r\n\r\n") {
+                    continue;
+                }
+                let _ = stream.write_all(
+                    b"HTTP/1.1 200 OK\r\n\
+                      Docker-Distribution-API-Version: registry/2.0\r\n\
+                      Content-Length: 0\r\n\
+                      Connection: close\r\n\r\n",
+                );
+            }
+            session.send_close_notify();
+            let _ = session.write_tls(&mut tcp);
+            let _ = tx.send(session.get_peer_certificates());
+        }
+    });
+    (format!("localhost:{}", port), rx)
+}
+
+/// Create a docker-style certificates directory for `registry`, with the given fixtures.
+fn certs_dir(registry: &str, files: &[&str]) -> path::PathBuf {
+    let dir = env::temp_dir().join(format!(
+        "dkregistry-certs-{}-{}",
+        std::process::id(),
+        registry.replace(':', "-")
+    ));
+    let reg_dir = dir.join(registry);
+    fs::create_dir_all(&reg_dir).unwrap();
+    for f in files {
+        fs::write(reg_dir.join(f), fixture(f)).unwrap();
+    }
+    dir
+}
+
+fn check_registry(registry: &str, dir: Option<path::PathBuf>) -> dkregistry::errors::Result<bool> {
+    let mut tcore = Core::new().unwrap();
+    let dclient = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry(registry)
+        .insecure_fallback(false)
+        .retry_policy(dkregistry::v2::RetryPolicy::none())
+        .env_proxy(false)
+        .certs_dir(dir)
+        .build()
+        .unwrap();
+    tcore.run(dclient.is_v2_supported())
+}
+
+#[test]
+fn test_tls_certs_dir() {
+    let tcore = Core::new().unwrap();
+    let res = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry("registry.test")
+        .certs_dir(Some(FIXTURES.into()))
+        .build();
+    assert!(res.is_ok());
+}
+
+#[test]
+fn test_tls_certs_dir_missing() {
+    let tcore = Core::new().unwrap();
+    let res = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry("unknown.test")
+        .certs_dir(Some(FIXTURES.into()))
+        .build();
+    assert!(res.is_ok());
+}
+
+#[test]
+fn test_tls_certs_dir_missing_key() {
+    let dir = env::temp_dir().join(format!("dkregistry-certs-{}", std::process::id()));
+    let reg_dir = dir.join("registry.test");
+    fs::create_dir_all(&reg_dir).unwrap();
+    fs::write(reg_dir.join("client.cert"), fixture("client.cert")).unwrap();
+
+    let tcore = Core::new().unwrap();
+    let res = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry("registry.test")
+        .certs_dir(Some(dir.clone()))
+        .build();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_tls_explicit_certificates() {
+    let tcore = Core::new().unwrap();
+    let res = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry("registry.test")
+        .add_root_certificate(&fixture("ca.crt"))
+        .client_identity(&fixture("client.cert"), &fixture("client.key"))
+        .build();
+    assert!(res.is_ok());
+}
+
+#[test]
+fn test_tls_invalid_certificate() {
+    let tcore = Core::new().unwrap();
+    let res = dkregistry::v2::Client::configure(&tcore.handle())
+        .registry("registry.test")
+        .client_identity(b"not a certificate", b"not a key")
+        .build();
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_tls_handshake_untrusted_ca() {
+    let (registry, presented) = tls_registry();
+    let dir = certs_dir(&registry, &[]);
+    let res = check_registry(&registry, Some(dir.clone()));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(res.is_err());
+    assert!(presented.try_recv().is_err());
+}
+
+#[test]
+fn test_tls_handshake_certs_dir_ca() {
+    let (registry, presented) = tls_registry();
+    let dir = certs_dir(&registry, &["ca.crt"]);
+    let res = check_registry(&registry, Some(dir.clone()));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(res.unwrap());
+    assert_eq!(presented.recv().unwrap(), None);
+}
+
+#[test]
+fn test_tls_handshake_client_certificate() {
+    let (registry, presented) = tls_registry();
+    let dir = certs_dir(&registry, &["ca.crt", "client.cert", "client.key"]);
+    let res = check_registry(&registry, Some(dir.clone()));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(res.unwrap());
+    let expected = pemfile::certs(&mut &fixture("client.cert")[..]).unwrap();
+    assert_eq!(presented.recv().unwrap(), Some(expected));
+}